@@ -0,0 +1,531 @@
+use crate::CommandError;
+
+/// One word of a command line, with its quotes and escapes removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub text: String,
+    /// The word as it was typed, quotes and all.
+    pub raw: String,
+    /// Where the word starts, counting characters from 1.
+    pub column: usize,
+    /// Set when the word starts with a quote, so `"+1"` and `"--all"` are
+    /// plain text rather than a tag or an option.
+    pub literal: bool,
+}
+
+impl Token {
+    /// An error about this word.
+    pub fn error(&self, message: impl Into<String>) -> CommandError {
+        CommandError::Syntax {
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    /// The column just past this word.
+    fn end(&self) -> usize {
+        self.column + self.raw.chars().count()
+    }
+}
+
+/// Splits `line` into words the way a shell would. Single quotes keep
+/// everything up to the next one; double quotes do the same but allow `\"`
+/// and `\\`. Elsewhere a backslash escapes a quote, a backslash or a space
+/// and is otherwise kept, so regexes like `/\d+/` need no doubling.
+pub fn tokenize(line: &str) -> Result<Vec<Token>, CommandError> {
+    let chars: Vec<char> = line.chars().collect();
+    let escapable = |c: Option<&char>| c.is_some_and(|&c| c.is_whitespace() || "'\"\\".contains(c));
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut text = String::new();
+        while let Some(&c) = chars.get(i).filter(|c| !c.is_whitespace()) {
+            match c {
+                '\'' | '"' => {
+                    let open = i;
+                    i += 1;
+                    loop {
+                        match chars.get(i) {
+                            None => {
+                                return Err(CommandError::Syntax {
+                                    column: open + 1,
+                                    message: "Unterminated quote".to_string(),
+                                });
+                            }
+                            Some(&close) if close == c => break,
+                            Some('\\')
+                                if c == '"' && matches!(chars.get(i + 1), Some('"' | '\\')) =>
+                            {
+                                i += 1;
+                                text.push(chars[i]);
+                            }
+                            Some(&other) => text.push(other),
+                        }
+                        i += 1;
+                    }
+                }
+                '\\' if escapable(chars.get(i + 1)) => {
+                    i += 1;
+                    text.push(chars[i]);
+                }
+                _ => text.push(c),
+            }
+            i += 1;
+        }
+        tokens.push(Token {
+            text,
+            raw: chars[start..i].iter().collect(),
+            column: start + 1,
+            literal: matches!(chars[start], '\'' | '"'),
+        });
+    }
+    Ok(tokens)
+}
+
+/// Words separated by whitespace alone, for text that was never meant to be
+/// quoted, such as a task line read back from a file.
+pub fn split(text: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut in_word = false;
+    for (i, c) in text.chars().enumerate() {
+        if c.is_whitespace() {
+            in_word = false;
+            continue;
+        }
+        if !in_word {
+            in_word = true;
+            tokens.push(Token {
+                text: String::new(),
+                raw: String::new(),
+                column: i + 1,
+                literal: false,
+            });
+        }
+        let token = tokens.last_mut().expect("a word was started");
+        token.text.push(c);
+        token.raw.push(c);
+    }
+    tokens
+}
+
+/// Words already split by the calling shell, numbered by their columns in
+/// `args.join(" ")`.
+pub fn from_args(args: &[String]) -> Vec<Token> {
+    let mut column = 1;
+    args.iter()
+        .map(|arg| {
+            let token = Token {
+                text: arg.clone(),
+                raw: arg.clone(),
+                column,
+                literal: false,
+            };
+            column += arg.chars().count() + 1;
+            token
+        })
+        .collect()
+}
+
+/// A positional argument in a command's [`Spec`], named for usage messages.
+#[derive(Debug, Clone, Copy)]
+pub enum Arg {
+    /// A task id.
+    Id(&'static str),
+    /// Task ids, as a list like `3,5,9-12` or a query like `tag:sprint42`.
+    Tasks(&'static str),
+    /// A task id or `none`.
+    IdOrNone(&'static str),
+    /// Exactly one word.
+    Word(&'static str),
+    /// All remaining words, at least one.
+    Text(&'static str),
+    /// All remaining words, if any.
+    OptionalText(&'static str),
+}
+
+impl Arg {
+    fn usage(&self) -> String {
+        match self {
+            Arg::Id(name) | Arg::Tasks(name) | Arg::Word(name) | Arg::Text(name) => {
+                format!("<{}>", name)
+            }
+            Arg::IdOrNone(name) => format!("<{}|none>", name),
+            Arg::OptionalText(name) => format!("[{}]", name),
+        }
+    }
+}
+
+/// A `--name` option, given anywhere after the command name.
+#[derive(Debug, Clone, Copy)]
+pub struct Flag {
+    pub name: &'static str,
+    /// What the option's value is called, for options that take one, either
+    /// as the next word or as `--name=value`.
+    pub value: Option<&'static str>,
+}
+
+impl Flag {
+    pub const fn switch(name: &'static str) -> Self {
+        Flag { name, value: None }
+    }
+
+    pub const fn value(name: &'static str, value: &'static str) -> Self {
+        Flag {
+            name,
+            value: Some(value),
+        }
+    }
+}
+
+/// What one command accepts after its name. A bare `--` ends the options,
+/// so later words are positional even if they start with `--`.
+#[derive(Debug)]
+pub struct Spec {
+    pub name: &'static str,
+    pub args: &'static [Arg],
+    pub flags: &'static [Flag],
+}
+
+impl Spec {
+    pub const fn new(name: &'static str, args: &'static [Arg], flags: &'static [Flag]) -> Self {
+        Spec { name, args, flags }
+    }
+
+    /// E.g. `import <file> [--format <format>] [--dry-run]`.
+    pub fn usage(&self) -> String {
+        let mut usage = vec![self.name.to_string()];
+        usage.extend(self.args.iter().map(Arg::usage));
+        usage.extend(self.flags.iter().map(|flag| match flag.value {
+            Some(value) => format!("[--{} <{}>]", flag.name, value),
+            None => format!("[--{}]", flag.name),
+        }));
+        usage.join(" ")
+    }
+
+    /// Matches `words`, which follow the command name at `name`, against
+    /// this spec.
+    pub fn parse(&'static self, name: &Token, words: &[Token]) -> Result<Args, CommandError> {
+        let end = words.last().unwrap_or(name).end() + 1;
+        let mut positional = Vec::new();
+        let mut flags = Vec::new();
+        let mut options = true;
+        let mut words = words.iter();
+        while let Some(word) = words.next() {
+            let option = word
+                .text
+                .strip_prefix("--")
+                .filter(|_| options && !word.literal);
+            let Some(option) = option else {
+                positional.push(word.clone());
+                continue;
+            };
+            if option.is_empty() {
+                options = false;
+                continue;
+            }
+            let (option, inline) = match option.split_once('=') {
+                Some((option, value)) => (option, Some(value)),
+                None => (option, None),
+            };
+            let flag = self
+                .flags
+                .iter()
+                .find(|flag| flag.name == option)
+                .ok_or_else(|| {
+                    word.error(format!(
+                        "Unknown option '--{}' for '{}'; quote it to use it as text",
+                        option, self.name
+                    ))
+                })?;
+            let value = match (flag.value, inline) {
+                (None, None) => None,
+                (None, Some(_)) => {
+                    return Err(word.error(format!("'--{}' takes no value", option)));
+                }
+                (Some(_), Some(value)) => {
+                    let column = word.column + option.chars().count() + 3;
+                    Some(Token {
+                        text: value.to_string(),
+                        raw: value.to_string(),
+                        column,
+                        literal: false,
+                    })
+                }
+                (Some(name), None) => {
+                    Some(words.next().cloned().ok_or_else(|| CommandError::Syntax {
+                        column: end,
+                        message: format!("Missing <{}> for '--{}'", name, option),
+                    })?)
+                }
+            };
+            flags.push((flag.name, value));
+        }
+
+        let mut args = Args {
+            spec: self,
+            end,
+            values: Vec::new(),
+            flags,
+        };
+        let mut positional = positional.into_iter();
+        for (index, arg) in self.args.iter().enumerate() {
+            let value = match arg {
+                Arg::Id(_) | Arg::IdOrNone(_) | Arg::Tasks(_) | Arg::Word(_) => {
+                    let word = positional.next().ok_or_else(|| args.missing(index))?;
+                    match arg {
+                        Arg::IdOrNone(_) if word.text.eq_ignore_ascii_case("none") => {
+                            Value::Id(None)
+                        }
+                        Arg::Id(_) | Arg::IdOrNone(_) => {
+                            Value::Id(Some(word.text.parse().map_err(|_| {
+                                word.error(format!("Invalid task id '{}'", word.text))
+                            })?))
+                        }
+                        _ => Value::Words(vec![word]),
+                    }
+                }
+                Arg::Text(_) | Arg::OptionalText(_) => {
+                    let rest: Vec<Token> = positional.by_ref().collect();
+                    if rest.is_empty() && matches!(arg, Arg::Text(_)) {
+                        return Err(args.missing(index));
+                    }
+                    Value::Words(rest)
+                }
+            };
+            args.values.push(value);
+        }
+        if let Some(extra) = positional.next() {
+            return Err(extra.error(format!(
+                "Unexpected argument '{}'. Usage: {}",
+                extra.text,
+                self.usage()
+            )));
+        }
+        Ok(args)
+    }
+}
+
+#[derive(Debug)]
+enum Value {
+    Id(Option<u32>),
+    Words(Vec<Token>),
+}
+
+/// A command's arguments, matched against its [`Spec`]. The accessors take
+/// the position of the argument in the spec.
+#[derive(Debug)]
+pub struct Args {
+    spec: &'static Spec,
+    end: usize,
+    values: Vec<Value>,
+    flags: Vec<(&'static str, Option<Token>)>,
+}
+
+impl Args {
+    pub fn id(&self, index: usize) -> u32 {
+        self.id_or_none(index)
+            .unwrap_or_else(|| panic!("argument {} of '{}' is not an id", index, self.spec.name))
+    }
+
+    pub fn id_or_none(&self, index: usize) -> Option<u32> {
+        match self.values[index] {
+            Value::Id(id) => id,
+            Value::Words(_) => panic!("argument {} of '{}' is not an id", index, self.spec.name),
+        }
+    }
+
+    pub fn words(&self, index: usize) -> &[Token] {
+        match &self.values[index] {
+            Value::Words(words) => words,
+            Value::Id(_) => panic!("argument {} of '{}' is an id", index, self.spec.name),
+        }
+    }
+
+    pub fn word(&self, index: usize) -> &Token {
+        &self.words(index)[0]
+    }
+
+    /// The words of an argument joined by single spaces.
+    pub fn text(&self, index: usize) -> String {
+        join(self.words(index), |t| &t.text)
+    }
+
+    /// Whether the switch `--name` was given.
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|(flag, _)| *flag == name)
+    }
+
+    /// The value of the last `--name` given.
+    pub fn value(&self, name: &str) -> Option<&Token> {
+        self.flags
+            .iter()
+            .rev()
+            .find(|(flag, _)| *flag == name)
+            .and_then(|(_, value)| value.as_ref())
+    }
+
+    /// An error about the argument at `index`, pointing at its first word or
+    /// at the end of the line if it has none.
+    pub fn error(&self, index: usize, message: impl Into<String>) -> CommandError {
+        let column = match &self.values[index] {
+            Value::Words(words) if !words.is_empty() => words[0].column,
+            _ => self.end,
+        };
+        CommandError::Syntax {
+            column,
+            message: message.into(),
+        }
+    }
+
+    /// Reports the argument at `index` as missing.
+    pub fn missing(&self, index: usize) -> CommandError {
+        CommandError::Syntax {
+            column: self.end,
+            message: format!(
+                "Missing {} for '{}'. Usage: {}",
+                self.spec.args[index].usage(),
+                self.spec.name,
+                self.spec.usage()
+            ),
+        }
+    }
+}
+
+/// Joins `words` by single spaces, taking `part` of each.
+pub fn join(words: &[Token], part: fn(&Token) -> &String) -> String {
+    words
+        .iter()
+        .map(part)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static ADD: Spec = Spec::new("add", &[Arg::Text("description")], &[]);
+    static DEPEND: Spec = Spec::new("depend", &[Arg::Tasks("ids"), Arg::Id("blocker")], &[]);
+    static IMPORT: Spec = Spec::new(
+        "import",
+        &[Arg::Word("file")],
+        &[Flag::value("format", "format"), Flag::switch("dry-run")],
+    );
+
+    fn texts(line: &str) -> Vec<String> {
+        tokenize(line)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.text)
+            .collect()
+    }
+
+    fn parse(spec: &'static Spec, line: &str) -> Result<Args, CommandError> {
+        let tokens = tokenize(line)?;
+        spec.parse(&tokens[0], &tokens[1..])
+    }
+
+    /// The column and message of the syntax error `line` gives.
+    fn error(spec: &'static Spec, line: &str) -> (usize, String) {
+        match parse(spec, line) {
+            Err(CommandError::Syntax { column, message }) => (column, message),
+            other => panic!("{} gave {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn quotes_group_words_and_mark_them_literal() {
+        assert_eq!(
+            texts(r#"add "buy milk" 'and eggs'"#),
+            ["add", "buy milk", "and eggs"]
+        );
+        assert_eq!(texts(r#"add pre"fix"ed"#), ["add", "prefixed"]);
+        assert_eq!(texts(r#"add '' """#), ["add", "", ""]);
+        let tokens = tokenize(r#"add "+1" +2"#).unwrap();
+        assert!(tokens[1].literal && !tokens[2].literal);
+        assert_eq!(tokens[1].raw, "\"+1\"");
+        assert_eq!((tokens[1].column, tokens[2].column), (5, 10));
+        match tokenize(r#"add "unterminated"#) {
+            Err(CommandError::Syntax { column, message }) => {
+                assert_eq!((column, message.as_str()), (5, "Unterminated quote"))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn backslashes_escape_quotes_spaces_and_themselves() {
+        assert_eq!(
+            texts(r#"add it\'s a\ b \\ \"hi\""#),
+            ["add", "it's", "a b", "\\", "\"hi\""]
+        );
+        assert_eq!(
+            texts(r#"add "say \"hi\" \\ \n""#),
+            ["add", "say \"hi\" \\ \\n"]
+        );
+        assert_eq!(texts(r#"add 'no \' escapes"#), ["add", "no \\", "escapes"]);
+        assert_eq!(texts(r#"list /\d+/"#), ["list", "/\\d+/"]);
+    }
+
+    #[test]
+    fn flags_take_values_inline_or_as_the_next_word() {
+        let args = parse(&IMPORT, "import tasks.txt --format=csv --dry-run").unwrap();
+        assert_eq!(args.word(0).text, "tasks.txt");
+        assert!(args.flag("dry-run"));
+        let format = args.value("format").unwrap();
+        assert_eq!((format.text.as_str(), format.column), ("csv", 27));
+
+        let args = parse(&IMPORT, "import --format csv tasks.txt").unwrap();
+        assert_eq!(args.value("format").unwrap().text, "csv");
+        assert_eq!(args.word(0).text, "tasks.txt");
+        assert!(!args.flag("dry-run"));
+    }
+
+    #[test]
+    fn a_double_dash_ends_the_options() {
+        let args = parse(&ADD, "add -- --dry-run is text").unwrap();
+        assert_eq!(args.text(0), "--dry-run is text");
+        let args = parse(&ADD, r#"add "--quoted" too"#).unwrap();
+        assert_eq!(args.text(0), "--quoted too");
+    }
+
+    #[test]
+    fn unknown_or_misused_flags_point_at_the_flag() {
+        let (column, message) = error(&ADD, "add milk --urgent");
+        assert_eq!(column, 10);
+        assert!(message.starts_with("Unknown option '--urgent' for 'add'"));
+        assert_eq!(
+            error(&IMPORT, "import a.txt --dry-run=yes"),
+            (14, "'--dry-run' takes no value".to_string())
+        );
+        assert_eq!(
+            error(&IMPORT, "import a.txt --format"),
+            (23, "Missing <format> for '--format'".to_string())
+        );
+    }
+
+    #[test]
+    fn missing_and_bad_arguments_point_at_their_column() {
+        let (column, message) = error(&DEPEND, "depend 3");
+        assert_eq!(column, 10);
+        assert_eq!(
+            message,
+            "Missing <blocker> for 'depend'. Usage: depend <ids> <blocker>"
+        );
+        assert_eq!(error(&DEPEND, "depend").0, 8);
+        assert_eq!(error(&ADD, "add  ").0, 5);
+        assert_eq!(
+            error(&DEPEND, "depend 3 x"),
+            (10, "Invalid task id 'x'".to_string())
+        );
+        let (column, message) = error(&DEPEND, "depend 3 4 5");
+        assert_eq!(column, 12);
+        assert!(message.starts_with("Unexpected argument '5'"));
+    }
+}
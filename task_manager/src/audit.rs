@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::task::AnyTask;
+use crate::{CommandError, TaskManager};
+
+/// When a command ran and which session ran it. Journaled with the command
+/// so replaying it later records the original moment.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Stamp {
+    pub at: NaiveDateTime,
+    pub session: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Added,
+    Edited(Vec<String>),
+    State {
+        from: String,
+        to: String,
+    },
+    Deleted,
+    /// Changed by undo or redo, with the message that reported it.
+    UndoRedo(String),
+}
+
+/// One entry in the audit log. Entries are only ever appended; undo adds an
+/// entry of its own rather than taking one back.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Event {
+    pub task: u32,
+    #[serde(flatten)]
+    pub stamp: Stamp,
+    pub action: Action,
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}  ", self.stamp.at.format("%Y-%m-%d %H:%M"))?;
+        match &self.action {
+            Action::Added => write!(f, "added")?,
+            Action::Edited(fields) if fields.is_empty() => write!(f, "edited")?,
+            Action::Edited(fields) => write!(f, "edited {}", fields.join(", "))?,
+            Action::State { from, to } => write!(f, "{} -> {}", from, to)?,
+            Action::Deleted => write!(f, "deleted")?,
+            Action::UndoRedo(message) => write!(f, "{}", message)?,
+        }
+        if !self.stamp.session.is_empty() {
+            write!(f, "  (session {})", self.stamp.session)?;
+        }
+        Ok(())
+    }
+}
+
+impl TaskManager {
+    /// Updates the timestamps of every task that differs from `before`.
+    pub fn touch(&mut self, before: &[AnyTask], at: NaiveDateTime) {
+        let old: HashMap<u32, &AnyTask> = before.iter().map(|t| (t.id(), t)).collect();
+        for task in &mut self.tasks {
+            let old = old.get(&task.id()).copied();
+            if old == Some(&*task) {
+                continue;
+            }
+            let completed = matches!(task, AnyTask::Completed(_))
+                && !old.is_some_and(|old| matches!(old, AnyTask::Completed(_)));
+            let finished = task.is_finished();
+            let stamps = task.stamps_mut();
+            if old.is_none() {
+                stamps.created = Some(at);
+            }
+            stamps.updated = Some(at);
+            if completed {
+                stamps.completed = Some(at);
+            } else if !finished {
+                stamps.completed = None;
+            }
+        }
+    }
+
+    /// Appends an event for every task added, changed or removed since
+    /// `before`. With `undo_redo` set, each change is logged under that
+    /// message instead.
+    pub fn log_changes(&mut self, before: &[AnyTask], stamp: &Stamp, undo_redo: Option<&str>) {
+        let old: HashMap<u32, &AnyTask> = before.iter().map(|t| (t.id(), t)).collect();
+        let new: HashMap<u32, &AnyTask> = self.tasks.iter().map(|t| (t.id(), t)).collect();
+        let mut ids: Vec<u32> = old
+            .keys()
+            .chain(new.keys())
+            .copied()
+            .filter(|id| old.get(id) != new.get(id))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let events: Vec<Event> = ids
+            .into_iter()
+            .map(|id| {
+                let action = match (undo_redo, old.get(&id), new.get(&id)) {
+                    (Some(message), _, _) => Action::UndoRedo(message.to_string()),
+                    (None, None, _) => Action::Added,
+                    (None, Some(_), None) => Action::Deleted,
+                    (None, Some(old), Some(task)) if old.state() != task.state() => Action::State {
+                        from: old.state().to_string(),
+                        to: task.state().to_string(),
+                    },
+                    (None, Some(old), Some(task)) => Action::Edited(
+                        old.details()
+                            .changed_fields(task.details())
+                            .into_iter()
+                            .map(str::to_string)
+                            .collect(),
+                    ),
+                };
+                Event {
+                    task: id,
+                    stamp: stamp.clone(),
+                    action,
+                }
+            })
+            .collect();
+        self.audit.extend(events);
+    }
+
+    /// Everything recorded about task `id`, which may since have been deleted.
+    pub fn task_history(&self, id: u32) -> Result<String, CommandError> {
+        let events: Vec<&Event> = self.audit.iter().filter(|e| e.task == id).collect();
+        let task = self.tasks.iter().find(|t| t.id() == id);
+        if events.is_empty() && task.is_none() {
+            return Err(CommandError::TaskNotFound(id));
+        }
+
+        let mut out = String::from("--------------- HISTORY ---------------\n");
+        if let Some(task) = task {
+            out.push_str(&format!("{}\n", task));
+            let stamps = task.stamps();
+            for (label, stamp) in [
+                ("Created", stamps.created),
+                ("Updated", stamps.updated),
+                ("Completed", stamps.completed),
+            ] {
+                if let Some(at) = stamp {
+                    out.push_str(&format!("{:<10} {}\n", label, at.format("%Y-%m-%d %H:%M")));
+                }
+            }
+            out.push('\n');
+        }
+        if events.is_empty() {
+            out.push_str("Nothing recorded yet.\n");
+        }
+        for event in events {
+            out.push_str(&format!("{}\n", event));
+        }
+        out.push_str("---------------------------------------");
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::dates::Clock;
+    use crate::store::{JsonFileStore, MemoryStore};
+    use crate::tests::{manager, now, run, scratch_dir};
+
+    /// Task `id`'s events as `history` shows them, leaving out the session.
+    fn actions(manager: &TaskManager, id: u32) -> Vec<String> {
+        manager
+            .audit
+            .iter()
+            .filter(|event| event.task == id)
+            .map(|event| {
+                let mut event = event.clone();
+                event.stamp.session.clear();
+                event.to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn history_shows_each_change_in_order() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        manager.clock = Clock::Fixed(now() + Duration::minutes(5));
+        run(&mut manager, &mut store, "edit 1 +shop !high").unwrap();
+        run(&mut manager, &mut store, "complete 1").unwrap();
+        run(&mut manager, &mut store, "undo").unwrap();
+        run(&mut manager, &mut store, "delete 1").unwrap();
+
+        let history = manager.task_history(1).unwrap();
+        let lines: Vec<&str> = history.lines().skip(1).collect();
+        assert_eq!(
+            lines[..5],
+            [
+                "2026-10-14 09:00  added",
+                "2026-10-14 09:05  edited priority, tags",
+                "2026-10-14 09:05  pending -> completed",
+                "2026-10-14 09:05  Undid: Completed task 1.",
+                "2026-10-14 09:05  deleted",
+            ]
+        );
+        assert!(matches!(
+            manager.task_history(2),
+            Err(CommandError::TaskNotFound(2))
+        ));
+    }
+
+    #[test]
+    fn events_survive_save_load_and_merge() {
+        let path = scratch_dir("audit-save").join("tasks.json");
+        let mut store = JsonFileStore::new(path.clone());
+        let mut base = manager();
+        run(&mut base, &mut store, "add buy milk").unwrap();
+        base.save(&mut store).unwrap();
+
+        let mut loaded = TaskManager::load(&mut JsonFileStore::new(path)).unwrap();
+        assert_eq!(loaded.audit, base.audit);
+
+        let mut theirs = base.clone();
+        theirs.clock = Clock::Fixed(now() + Duration::hours(1));
+        run(&mut theirs, &mut MemoryStore::default(), "complete 1").unwrap();
+        loaded.clock = Clock::Fixed(now() + Duration::hours(2));
+        run(
+            &mut loaded,
+            &mut MemoryStore::default(),
+            "note 1 semi-skimmed",
+        )
+        .unwrap();
+        loaded.merge(&base, theirs);
+        assert_eq!(
+            actions(&loaded, 1),
+            [
+                "2026-10-14 09:00  added",
+                "2026-10-14 10:00  pending -> completed",
+                "2026-10-14 11:00  edited notes",
+            ]
+        );
+    }
+}
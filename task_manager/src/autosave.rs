@@ -0,0 +1,90 @@
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::TaskManager;
+use crate::store::SharedStore;
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long to wait after the last change before saving, so a burst of
+/// commands ends up in a single write.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+enum Message {
+    Changed,
+    Shutdown,
+}
+
+/// Background thread that saves the manager shortly after it changes, and at
+/// least every `interval` while changes keep coming in.
+pub struct Autosaver {
+    tx: Sender<Message>,
+    handle: Mutex<Option<JoinHandle<io::Result<()>>>>,
+}
+
+impl Autosaver {
+    pub fn spawn(manager: Arc<Mutex<TaskManager>>, store: SharedStore, interval: Duration) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let mut first_change: Option<Instant> = None;
+            let mut last_change = Instant::now();
+            loop {
+                let timeout = match first_change {
+                    Some(first) => (last_change + DEBOUNCE)
+                        .min(first + interval)
+                        .saturating_duration_since(Instant::now()),
+                    None => interval,
+                };
+                match rx.recv_timeout(timeout) {
+                    Ok(Message::Changed) => {
+                        last_change = Instant::now();
+                        first_change.get_or_insert(last_change);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        first_change = None;
+                        if let Err(e) = save_if_dirty(&manager, &store) {
+                            eprintln!("[AUTOSAVE ERROR] Failed to save tasks: {}", e);
+                        }
+                    }
+                    Ok(Message::Shutdown) | Err(RecvTimeoutError::Disconnected) => {
+                        return save_if_dirty(&manager, &store);
+                    }
+                }
+            }
+        });
+        Autosaver {
+            tx,
+            handle: Mutex::new(Some(handle)),
+        }
+    }
+
+    /// Tells the thread the manager has changed.
+    pub fn notify(&self) {
+        let _ = self.tx.send(Message::Changed);
+    }
+
+    /// Flushes unsaved changes and stops the thread. Concurrent callers all
+    /// wait for the flush; later calls return immediately.
+    pub fn shutdown(&self) -> io::Result<()> {
+        let _ = self.tx.send(Message::Shutdown);
+        let mut handle = self.handle.lock().unwrap();
+        match handle.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("autosave thread panicked"))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn save_if_dirty(manager: &Mutex<TaskManager>, store: &SharedStore) -> io::Result<()> {
+    let mut manager = manager.lock().unwrap();
+    if !manager.is_dirty() {
+        return Ok(());
+    }
+    let mut store = store.lock().unwrap();
+    manager.save(store.as_mut())
+}
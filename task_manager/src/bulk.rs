@@ -0,0 +1,262 @@
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::args::Token;
+use crate::query::{Filter, Query};
+use crate::search::SearchIndex;
+use crate::{Command, CommandError, TaskManager};
+
+/// Ids a single list or range may name, so `1-4000000000` is refused rather
+/// than tried.
+const MAX_IDS: usize = 1000;
+
+/// The tasks an id-taking command was given: `3`, `3,5,9-12`, or a `list`
+/// query such as `tag:sprint42` or `"+work and !high"`.
+///
+/// A query has to say so: plain words only count as one when quoted, so a
+/// stray word, as in `delete milk`, does not quietly act on every task whose
+/// text contains it.
+#[derive(Debug, Clone)]
+pub enum Targets {
+    Ids(Vec<u32>),
+    /// A query, along with the text it was read from.
+    Query(String, Query),
+}
+
+impl Targets {
+    pub fn parse(word: &Token, now: NaiveDateTime) -> Result<Self, CommandError> {
+        let text = &word.text;
+        if !text.starts_with(|c: char| c.is_ascii_digit()) {
+            let query = Query::parse(text, now).map_err(|e| word.error(e))?;
+            let quoted = word.literal || text.contains('"');
+            if !quoted && query.filter.as_ref().is_none_or(Filter::is_plain_text) {
+                return Err(word.error(format!(
+                    "'{}' is not a task id or a filter like tag:home; quote it to match the text",
+                    text
+                )));
+            }
+            return Ok(Targets::Query(text.clone(), query));
+        }
+
+        let mut ids = Vec::new();
+        let mut offset = 0;
+        for part in text.split(',') {
+            let error = |message: String| CommandError::Syntax {
+                column: word.column + offset,
+                message,
+            };
+            let (first, last) = part.split_once('-').unwrap_or((part, part));
+            let (Ok(first), Ok(last)) = (first.parse::<u32>(), last.parse::<u32>()) else {
+                return Err(error(format!("Invalid id or range '{}'", part)));
+            };
+            if first > last {
+                return Err(error(format!("The range '{}' runs backwards", part)));
+            }
+            if ids.len() + (last - first) as usize >= MAX_IDS {
+                return Err(error(format!("Too many ids, at most {} at once", MAX_IDS)));
+            }
+            for id in first..=last {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+            offset += part.chars().count() + 1;
+        }
+        Ok(Targets::Ids(ids))
+    }
+}
+
+/// `3, 5, 9-12`, collapsing runs of consecutive ids.
+pub fn id_ranges(ids: &[u32]) -> String {
+    let mut sorted = ids.to_vec();
+    sorted.sort_unstable();
+    sorted
+        .chunk_by(|a, b| a + 1 == *b)
+        .map(|run| match run {
+            [id] => id.to_string(),
+            [first, .., last] => format!("{}-{}", first, last),
+            [] => unreachable!("chunks are never empty"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Command {
+    /// The task an id-taking command acts on.
+    pub fn target(&self) -> Option<u32> {
+        match self {
+            Command::Edit(id, _)
+            | Command::Clear(id, _)
+            | Command::Note(id, _)
+            | Command::Delete(id)
+            | Command::Parent(id, _)
+            | Command::Depend(id, _)
+            | Command::Undepend(id, _)
+            | Command::Start(id)
+            | Command::Complete(id)
+            | Command::Block(id)
+            | Command::Unblock(id)
+            | Command::Cancel(id)
+            | Command::Reopen(id)
+            | Command::Archive(id)
+            | Command::StartTimer(id, _)
+            | Command::Log(id, _)
+            | Command::History(id) => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// This command acting on task `id` instead.
+    pub fn retarget(&self, id: u32) -> Command {
+        let mut command = self.clone();
+        match &mut command {
+            Command::Edit(target, _)
+            | Command::Clear(target, _)
+            | Command::Note(target, _)
+            | Command::Delete(target)
+            | Command::Parent(target, _)
+            | Command::Depend(target, _)
+            | Command::Undepend(target, _)
+            | Command::Start(target)
+            | Command::Complete(target)
+            | Command::Block(target)
+            | Command::Unblock(target)
+            | Command::Cancel(target)
+            | Command::Reopen(target)
+            | Command::Archive(target)
+            | Command::StartTimer(target, _)
+            | Command::Log(target, _)
+            | Command::History(target) => *target = id,
+            _ => unreachable!("{:?} does not take a task id", self),
+        }
+        command
+    }
+}
+
+impl TaskManager {
+    /// The ids `targets` names. Listed ids are returned as given, missing
+    /// or not; a query must match at least one task.
+    pub fn select(&self, targets: &Targets) -> Result<Vec<u32>, CommandError> {
+        match targets {
+            Targets::Ids(ids) => Ok(ids.clone()),
+            Targets::Query(text, query) => {
+                let ids: Vec<u32> = query.select(&self.tasks).iter().map(|t| t.id()).collect();
+                if ids.is_empty() {
+                    return Err(CommandError::NoMatch(text.clone()));
+                }
+                Ok(ids)
+            }
+        }
+    }
+
+    /// Applies every command or none of them. All of them are tried either
+    /// way, so a failure reports each task that would have failed.
+    pub fn apply_batch(
+        &mut self,
+        commands: Vec<Command>,
+        today: NaiveDate,
+    ) -> Result<String, CommandError> {
+        let (tasks, next_id) = (self.tasks.clone(), self.next_id);
+        let mut succeeded = Vec::new();
+        let mut failed = Vec::new();
+        let mut messages = Vec::new();
+        for command in commands {
+            let id = command.target().unwrap_or_default();
+            match self.apply_change(command, today) {
+                Ok(message) => {
+                    // Several changes to one task count it once.
+                    if succeeded.last() != Some(&id) {
+                        succeeded.push(id);
+                    }
+                    messages.push(format!("  {}", message));
+                }
+                Err(e) => failed.push((id, e)),
+            }
+        }
+        if !failed.is_empty() {
+            self.tasks = tasks;
+            self.next_id = next_id;
+            self.index = SearchIndex::build(&self.tasks);
+            return Err(CommandError::Batch { succeeded, failed });
+        }
+        messages.insert(
+            0,
+            format!(
+                "Changed {} task(s): {}.",
+                succeeded.len(),
+                id_ranges(&succeeded)
+            ),
+        );
+        Ok(messages.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::tests::{descriptions, manager, run, state};
+
+    fn tasks() -> (TaskManager, MemoryStore) {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        for line in [
+            "add buy milk +home",
+            "add milk the cow",
+            "add file taxes +home",
+        ] {
+            run(&mut manager, &mut store, line).unwrap();
+        }
+        (manager, store)
+    }
+
+    #[test]
+    fn ids_lists_and_ranges_are_expanded() {
+        let (mut manager, mut store) = tasks();
+        run(&mut manager, &mut store, "complete 1,3").unwrap();
+        assert_eq!(state(&manager, 1), "completed");
+        assert_eq!(state(&manager, 2), "pending");
+        assert_eq!(state(&manager, 3), "completed");
+        run(&mut manager, &mut store, "delete 1-3").unwrap();
+        assert!(manager.tasks.is_empty());
+        assert!(run(&mut manager, &mut store, "delete 3-1").is_err());
+        assert!(run(&mut manager, &mut store, "delete 1-4000000000").is_err());
+    }
+
+    #[test]
+    fn a_plain_word_is_not_taken_as_a_query() {
+        let (mut manager, mut store) = tasks();
+        let error = run(&mut manager, &mut store, "delete milk").unwrap_err();
+        assert!(matches!(error, CommandError::Syntax { .. }), "{:?}", error);
+        assert_eq!(manager.tasks.len(), 3);
+
+        run(&mut manager, &mut store, "delete 'milk the'").unwrap();
+        assert_eq!(descriptions(&manager), ["buy milk", "file taxes"]);
+        run(&mut manager, &mut store, "delete \"milk\"").unwrap();
+        assert_eq!(descriptions(&manager), ["file taxes"]);
+    }
+
+    #[test]
+    fn filters_select_every_matching_task() {
+        let (mut manager, mut store) = tasks();
+        run(&mut manager, &mut store, "complete tag:home").unwrap();
+        assert_eq!(state(&manager, 1), "completed");
+        assert_eq!(state(&manager, 2), "pending");
+        assert_eq!(state(&manager, 3), "completed");
+        let error = run(&mut manager, &mut store, "delete tag:work").unwrap_err();
+        assert!(matches!(error, CommandError::NoMatch(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn a_batch_that_fails_anywhere_changes_nothing() {
+        let (mut manager, mut store) = tasks();
+        let error = run(&mut manager, &mut store, "complete 1,2,9").unwrap_err();
+        assert!(matches!(error, CommandError::Batch { .. }), "{:?}", error);
+        assert!(manager.tasks.iter().all(|task| task.state() == "pending"));
+        assert_eq!(manager.revision, 3);
+    }
+
+    #[test]
+    fn id_ranges_collapse_runs() {
+        assert_eq!(id_ranges(&[9, 3, 10, 5, 11, 4]), "3-5, 9-11");
+        assert_eq!(id_ranges(&[7]), "7");
+    }
+}
@@ -0,0 +1,369 @@
+use chrono::{Datelike, Days, Local, Months, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+
+/// Where "now" comes from when reading relative dates. `Fixed` makes runs
+/// reproducible, e.g. `--now 2026-10-14T09:00`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Clock {
+    #[default]
+    System,
+    Fixed(NaiveDateTime),
+}
+
+impl Clock {
+    pub fn now(&self) -> NaiveDateTime {
+        match self {
+            Clock::System => Local::now().naive_local(),
+            Clock::Fixed(now) => *now,
+        }
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        self.now().date()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        parse_timestamp(text)
+            .map(Clock::Fixed)
+            .ok_or_else(|| format!("Invalid time '{}', expected YYYY-MM-DDTHH:MM", text))
+    }
+}
+
+/// A date read from text, and the time of day if one was given.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct When {
+    pub date: NaiveDate,
+    pub time: Option<NaiveTime>,
+}
+
+impl When {
+    /// The moment this refers to, taking `default` as the time if none was given.
+    pub fn at(&self, default: NaiveTime) -> NaiveDateTime {
+        self.date.and_time(self.time.unwrap_or(default))
+    }
+}
+
+/// The most words a date expression can span, as in "end of the month 5pm".
+pub const MAX_WORDS: usize = 5;
+
+/// Reads an ISO date or timestamp, or an expression like "tomorrow 9am",
+/// "next friday", "in 3 days", "end of month" or "nov 1 at noon", relative
+/// to `now`.
+pub fn parse(text: &str, now: NaiveDateTime) -> Option<When> {
+    let text = text.trim();
+    if let Some(timestamp) = parse_timestamp(text) {
+        return Some(When {
+            date: timestamp.date(),
+            time: Some(timestamp.time()),
+        });
+    }
+    let text = text.to_lowercase();
+    let words: Vec<&str> = text.split_whitespace().collect();
+
+    // A trailing time of day, with an optional "at" before it.
+    let (last, rest) = words.split_last()?;
+    let (words, time) = match parse_time(last) {
+        Some(time) => (rest.strip_suffix(&["at"]).unwrap_or(rest), Some(time)),
+        None => (&words[..], None),
+    };
+    if words.is_empty() {
+        // A bare time means today, or tomorrow if that time has passed.
+        let time = time?;
+        let date = if time > now.time() {
+            now.date()
+        } else {
+            now.date().succ_opt()?
+        };
+        return Some(When {
+            date,
+            time: Some(time),
+        });
+    }
+    let today = now.date();
+
+    let date = match words {
+        ["today"] | ["tonight"] => today,
+        ["tomorrow"] | ["tmr"] => today.succ_opt()?,
+        ["yesterday"] => today.pred_opt()?,
+        ["eod"] => today,
+        ["eow"] | ["end", "of", "week"] | ["end", "of", "the", "week"] => {
+            weekday_on_or_after(today, Weekday::Sun)?
+        }
+        ["eom"] | ["end", "of", "month"] | ["end", "of", "the", "month"] => today
+            .with_day(1)?
+            .checked_add_months(Months::new(1))?
+            .pred_opt()?,
+        ["eoy"] | ["end", "of", "year"] | ["end", "of", "the", "year"] => {
+            NaiveDate::from_ymd_opt(today.year(), 12, 31)?
+        }
+        ["next", "week"] => today.checked_add_days(Days::new(7))?,
+        ["next", "month"] => today.checked_add_months(Months::new(1))?,
+        ["next", "year"] => today.checked_add_months(Months::new(12))?,
+        ["next", day] => weekday_on_or_after(today.succ_opt()?, parse_weekday(day)?)?,
+        ["this", day] | [day] if parse_weekday(day).is_some() => {
+            weekday_on_or_after(today, parse_weekday(day)?)?
+        }
+        ["in", amount, unit] => return offset(now, parse_amount(amount)?, unit, time),
+        ["in", unit] => return offset(now, 1, unit, time),
+        [amount, unit, "from", "now"] => return offset(now, parse_amount(amount)?, unit, time),
+        [month, day] | [day, month] if parse_month(month).is_some() => {
+            month_day(today, parse_month(month)?, day)?
+        }
+        [single] => NaiveDate::parse_from_str(single, "%Y-%m-%d").ok()?,
+        _ => return None,
+    };
+    Some(When { date, time })
+}
+
+fn parse_timestamp(text: &str) -> Option<NaiveDateTime> {
+    ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+}
+
+/// `now` moved forward by `amount` of `unit`. Hours and minutes keep the
+/// time of day; a larger unit keeps whatever time was given with it. `None`
+/// when the result is past the last date chrono can represent.
+fn offset(now: NaiveDateTime, amount: u32, unit: &str, time: Option<NaiveTime>) -> Option<When> {
+    let unit = unit.trim_end_matches('s');
+    let amount64 = u64::from(amount);
+    let moment = match unit {
+        "minute" | "min" => {
+            now.checked_add_signed(chrono::Duration::try_minutes(amount.into())?)?
+        }
+        "hour" | "hr" | "h" => {
+            now.checked_add_signed(chrono::Duration::try_hours(amount.into())?)?
+        }
+        _ => {
+            let date = match unit {
+                "day" | "d" => now.date().checked_add_days(Days::new(amount64))?,
+                "week" | "w" => now.date().checked_add_days(Days::new(7 * amount64))?,
+                "month" => now.date().checked_add_months(Months::new(amount))?,
+                "year" | "y" => now
+                    .date()
+                    .checked_add_months(Months::new(amount.checked_mul(12)?))?,
+                _ => return None,
+            };
+            return Some(When { date, time });
+        }
+    };
+    Some(When {
+        date: moment.date(),
+        time: Some(moment.time()),
+    })
+}
+
+fn parse_amount(word: &str) -> Option<u32> {
+    match word {
+        "a" | "an" | "one" => Some(1),
+        "two" => Some(2),
+        "three" => Some(3),
+        _ => word.parse().ok(),
+    }
+}
+
+/// "9am", "9:30pm", "17:00", "noon", "midnight".
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    match word {
+        "noon" => return NaiveTime::from_hms_opt(12, 0, 0),
+        "midnight" => return NaiveTime::from_hms_opt(0, 0, 0),
+        _ => {}
+    }
+    let (clock, offset) = if let Some(clock) = word.strip_suffix("am") {
+        (clock, Some(0))
+    } else if let Some(clock) = word.strip_suffix("pm") {
+        (clock, Some(12))
+    } else {
+        (word, None)
+    };
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) => (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?),
+        // A bare number is only a time with am/pm after it.
+        None if offset.is_some() => (clock.parse::<u32>().ok()?, 0),
+        None => return None,
+    };
+    let hour = match offset {
+        Some(offset) if (1..=12).contains(&hour) => hour % 12 + offset,
+        Some(_) => return None,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+fn parse_weekday(word: &str) -> Option<Weekday> {
+    let day = match word {
+        "mon" | "monday" => Weekday::Mon,
+        "tue" | "tues" | "tuesday" => Weekday::Tue,
+        "wed" | "wednesday" => Weekday::Wed,
+        "thu" | "thurs" | "thursday" => Weekday::Thu,
+        "fri" | "friday" => Weekday::Fri,
+        "sat" | "saturday" => Weekday::Sat,
+        "sun" | "sunday" => Weekday::Sun,
+        _ => return None,
+    };
+    Some(day)
+}
+
+fn weekday_on_or_after(date: NaiveDate, day: Weekday) -> Option<NaiveDate> {
+    let ahead = (7 + day.num_days_from_monday() - date.weekday().num_days_from_monday()) % 7;
+    date.checked_add_days(Days::new(ahead.into()))
+}
+
+fn parse_month(word: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let prefix = word.get(..3)?;
+    let month = MONTHS.iter().position(|m| *m == prefix)?;
+    // Accept "sept" and full names, but not arbitrary words that start alike.
+    let full = [
+        "january",
+        "february",
+        "march",
+        "april",
+        "may",
+        "june",
+        "july",
+        "august",
+        "september",
+        "october",
+        "november",
+        "december",
+    ][month];
+    (word.len() == 3 || word == "sept" || word == full).then_some(month as u32 + 1)
+}
+
+/// The next `month`/`day` on or after `today`.
+fn month_day(today: NaiveDate, month: u32, day: &str) -> Option<NaiveDate> {
+    let day: u32 = day
+        .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+        .parse()
+        .ok()?;
+    let date = NaiveDate::from_ymd_opt(today.year(), month, day)?;
+    if date >= today {
+        Some(date)
+    } else {
+        NaiveDate::from_ymd_opt(today.year() + 1, month, day)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::now;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    /// Reads `text` on Wednesday 2026-10-14 at 09:00.
+    fn on(text: &str) -> Option<(NaiveDate, Option<NaiveTime>)> {
+        parse(text, now()).map(|when| (when.date, when.time))
+    }
+
+    #[test]
+    fn iso_timestamps_are_read_as_written() {
+        assert_eq!(
+            on(" 2026-12-01T10:30 "),
+            Some((date(2026, 12, 1), Some(time(10, 30))))
+        );
+        assert_eq!(
+            on("2026-12-01T10:30:00"),
+            Some((date(2026, 12, 1), Some(time(10, 30))))
+        );
+    }
+
+    #[test]
+    fn relative_days_and_weekdays() {
+        assert_eq!(on("today"), Some((date(2026, 10, 14), None)));
+        assert_eq!(
+            on("tomorrow 9am"),
+            Some((date(2026, 10, 15), Some(time(9, 0))))
+        );
+        assert_eq!(on("wednesday"), Some((date(2026, 10, 14), None)));
+        assert_eq!(on("friday"), Some((date(2026, 10, 16), None)));
+        assert_eq!(on("next wed"), Some((date(2026, 10, 21), None)));
+        assert_eq!(on("eow"), Some((date(2026, 10, 18), None)));
+        assert_eq!(on("end of the month"), Some((date(2026, 10, 31), None)));
+        assert_eq!(on("eoy"), Some((date(2026, 12, 31), None)));
+        assert_eq!(on("next month"), Some((date(2026, 11, 14), None)));
+    }
+
+    #[test]
+    fn offsets_from_now() {
+        assert_eq!(on("in 3 days"), Some((date(2026, 10, 17), None)));
+        assert_eq!(on("in a week"), Some((date(2026, 10, 21), None)));
+        assert_eq!(
+            on("in 2 hours"),
+            Some((date(2026, 10, 14), Some(time(11, 0))))
+        );
+        assert_eq!(
+            on("90 minutes from now"),
+            Some((date(2026, 10, 14), Some(time(10, 30))))
+        );
+        assert_eq!(
+            on("in 1 year 5pm"),
+            Some((date(2027, 10, 14), Some(time(17, 0))))
+        );
+    }
+
+    #[test]
+    fn month_names_times_and_iso_dates() {
+        assert_eq!(
+            on("nov 1 at noon"),
+            Some((date(2026, 11, 1), Some(time(12, 0))))
+        );
+        assert_eq!(on("5 january"), Some((date(2027, 1, 5), None)));
+        assert_eq!(on("8am"), Some((date(2026, 10, 15), Some(time(8, 0)))));
+        assert_eq!(on("5:30pm"), Some((date(2026, 10, 14), Some(time(17, 30)))));
+        assert_eq!(on("2026-12-01"), Some((date(2026, 12, 1), None)));
+        assert_eq!(
+            on("2026-12-01T10:30"),
+            Some((date(2026, 12, 1), Some(time(10, 30))))
+        );
+        for text in [
+            "someday",
+            "13pm",
+            "marchy 3",
+            "feb 30",
+            "in 3 fortnights",
+            "",
+        ] {
+            assert_eq!(on(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn offsets_past_the_last_date_are_refused() {
+        for text in [
+            "in 4294967295 hours",
+            "in 4294967295 days",
+            "in 4294967295 weeks",
+            "in 4294967295 months",
+            "in 400000000 years",
+        ] {
+            assert_eq!(on(text), None, "{}", text);
+        }
+        let last = NaiveDate::MAX.and_time(time(23, 0));
+        for text in [
+            "tomorrow",
+            "friday",
+            "next week",
+            "next month",
+            "eom",
+            "in 2 hours",
+        ] {
+            assert_eq!(parse(text, last), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn a_fixed_clock_reads_the_now_flag() {
+        assert_eq!(Clock::parse("2026-10-14T09:00"), Ok(Clock::Fixed(now())));
+        assert_eq!(Clock::Fixed(now()).today(), date(2026, 10, 14));
+        assert!(Clock::parse("tomorrow").is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use std::sync::{Arc, Mutex};
+
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Config, Context, Editor, Helper};
+
+use crate::args::Arg;
+use crate::{Command, TaskManager};
+
+/// Commands kept in the history file.
+const HISTORY_SIZE: usize = 1000;
+
+const STATES: [&str; 6] = [
+    "pending",
+    "in-progress",
+    "blocked",
+    "completed",
+    "cancelled",
+    "archived",
+];
+
+pub type LineEditor = Editor<TaskHelper, DefaultHistory>;
+
+/// Makes a pending `readline` return `Interrupted`, as Ctrl-C does. While it
+/// waits for input the editor catches SIGINT itself and puts the terminal
+/// back before returning.
+#[cfg(unix)]
+pub fn interrupt() {
+    use nix::sys::signal::{Signal, kill};
+    use nix::unistd::Pid;
+    // Sent to the whole process, since the calling thread may block it.
+    let _ = kill(Pid::this(), Signal::SIGINT);
+}
+
+#[cfg(not(unix))]
+pub fn interrupt() {}
+
+/// A line editor with history and Ctrl-R search, completing from `manager`.
+pub fn line_editor(manager: Arc<Mutex<TaskManager>>) -> rustyline::Result<LineEditor> {
+    let config = Config::builder()
+        .auto_add_history(true)
+        .history_ignore_space(true)
+        .completion_type(CompletionType::List)
+        .max_history_size(HISTORY_SIZE)?
+        .build();
+    let mut editor = Editor::with_config(config)?;
+    editor.set_helper(Some(TaskHelper {
+        manager,
+        files: FilenameCompleter::new(),
+    }));
+    Ok(editor)
+}
+
+fn candidates<'a>(prefix: &str, words: impl IntoIterator<Item = &'a str>) -> Vec<Pair> {
+    words
+        .into_iter()
+        .filter(|word| word.starts_with(prefix))
+        .map(|word| Pair {
+            display: word.to_string(),
+            replacement: word.to_string(),
+        })
+        .collect()
+}
+
+/// Has each completion end the word, for names that are always followed by
+/// something.
+fn spaced(mut pairs: Vec<Pair>) -> Vec<Pair> {
+    for pair in &mut pairs {
+        pair.replacement.push(' ');
+    }
+    pairs
+}
+
+/// Completes command names, then whatever each command's spec expects: task
+/// ids, file names or `--options`, plus `+tags` and `tag:` and `state:`
+/// values anywhere.
+pub struct TaskHelper {
+    manager: Arc<Mutex<TaskManager>>,
+    files: FilenameCompleter,
+}
+
+impl TaskHelper {
+    fn tags(&self, prefix: &str, before: &str) -> Vec<Pair> {
+        let manager = self.manager.lock().unwrap();
+        let mut tags: Vec<String> = manager
+            .tasks
+            .iter()
+            .flat_map(|t| t.details().tags.iter())
+            .map(|tag| format!("{}{}", before, tag))
+            .collect();
+        tags.sort();
+        tags.dedup();
+        candidates(prefix, tags.iter().map(String::as_str))
+    }
+
+    fn ids(&self, prefix: &str) -> Vec<Pair> {
+        let manager = self.manager.lock().unwrap();
+        let mut tasks: Vec<_> = manager
+            .tasks
+            .iter()
+            .filter(|t| t.id().to_string().starts_with(prefix))
+            .collect();
+        tasks.sort_by_key(|t| t.id());
+        tasks
+            .into_iter()
+            .map(|task| Pair {
+                display: format!("{:<4} {}", task.id(), task.details().description),
+                replacement: task.id().to_string(),
+            })
+            .collect()
+    }
+}
+
+impl Completer for TaskHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        let start = before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let word = &before[start..];
+        let mut words = before[..start].split_whitespace();
+        let Some(name) = words.next().map(str::to_lowercase) else {
+            let names = Command::SPECS.iter().map(|spec| spec.name);
+            let pairs = candidates(word, names.chain(["exit", "quit"]));
+            return Ok((start, spaced(pairs)));
+        };
+        let Some(spec) = Command::spec(&name) else {
+            return Ok((start, Vec::new()));
+        };
+        let position = words.filter(|w| !w.starts_with("--")).count();
+        let arg = spec.args.get(position);
+
+        let pairs = if word.starts_with("--") {
+            let flags: Vec<String> = spec.flags.iter().map(|f| format!("--{}", f.name)).collect();
+            spaced(candidates(word, flags.iter().map(String::as_str)))
+        } else if let Some(Arg::Word("file")) = arg {
+            return self.files.complete(line, pos, ctx);
+        } else if word.starts_with('+') {
+            self.tags(word, "+")
+        } else if word.starts_with("tag:") {
+            self.tags(word, "tag:")
+        } else if let Some(key) = ["state:", "is:"].into_iter().find(|k| word.starts_with(k)) {
+            let states: Vec<String> = STATES.iter().map(|s| format!("{}{}", key, s)).collect();
+            candidates(word, states.iter().map(String::as_str))
+        } else if let Some(Arg::Tasks(_)) = arg
+            && let Some(comma) = word.rfind(',')
+        {
+            // Complete the last id of a list like `3,5,1`.
+            return Ok((start + comma + 1, self.ids(&word[comma + 1..])));
+        } else if let Some(Arg::Id(_) | Arg::IdOrNone(_) | Arg::Tasks(_)) = arg {
+            self.ids(word)
+        } else {
+            Vec::new()
+        };
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for TaskHelper {
+    type Hint = String;
+}
+
+impl Highlighter for TaskHelper {}
+
+impl Validator for TaskHelper {}
+
+impl Helper for TaskHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::tests::{manager, run};
+
+    /// Completes `line` at its end, as `(start, replacements)`.
+    fn complete(helper: &TaskHelper, line: &str) -> (usize, Vec<String>) {
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let (start, pairs) = helper.complete(line, line.len(), &ctx).unwrap();
+        (start, pairs.into_iter().map(|p| p.replacement).collect())
+    }
+
+    fn helper() -> TaskHelper {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        for line in [
+            "add buy milk +shop",
+            "add walk dog +home",
+            "add call mum +home",
+        ] {
+            run(&mut manager, &mut store, line).unwrap();
+        }
+        TaskHelper {
+            manager: Arc::new(Mutex::new(manager)),
+            files: FilenameCompleter::new(),
+        }
+    }
+
+    #[test]
+    fn command_names_complete_first() {
+        let helper = helper();
+        assert_eq!(complete(&helper, "com"), (0, vec!["complete ".to_string()]));
+        assert_eq!(
+            complete(&helper, "  ex"),
+            (2, vec!["export ".to_string(), "exit ".to_string()])
+        );
+        assert_eq!(complete(&helper, "frobnicate 1"), (11, vec![]));
+    }
+
+    #[test]
+    fn arguments_complete_by_what_the_command_takes() {
+        let helper = helper();
+        assert_eq!(
+            complete(&helper, "complete "),
+            (9, vec!["1".into(), "2".into(), "3".into()])
+        );
+        assert_eq!(
+            complete(&helper, "depend 1,"),
+            (9, vec!["1".into(), "2".into(), "3".into()])
+        );
+        assert_eq!(complete(&helper, "list +h"), (5, vec!["+home".to_string()]));
+        assert_eq!(
+            complete(&helper, "list tag:s"),
+            (5, vec!["tag:shop".to_string()])
+        );
+        assert_eq!(
+            complete(&helper, "list state:can"),
+            (5, vec!["state:cancelled".to_string()])
+        );
+        assert_eq!(
+            complete(&helper, "list --f"),
+            (5, vec!["--format ".to_string(), "--filter ".to_string()])
+        );
+    }
+
+    #[test]
+    fn multibyte_whitespace_does_not_split_a_character() {
+        let helper = helper();
+        // An ideographic space is three bytes long.
+        let line = "list\u{3000}+sh";
+        assert_eq!(complete(&helper, line), (7, vec!["+shop".to_string()]));
+        assert_eq!(complete(&helper, "add caf\u{e9}\u{a0}"), (11, vec![]));
+    }
+}
@@ -0,0 +1,299 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::task::AnyTask;
+use crate::{CommandError, TaskManager};
+
+/// Subtasks and dependencies share one ordering: a blocker comes before the
+/// tasks waiting on it, and a subtask comes before its parent.
+impl TaskManager {
+    fn find(&self, id: u32) -> Option<&AnyTask> {
+        self.tasks.iter().find(|t| t.id() == id)
+    }
+
+    /// Tasks that must be finished before `id`.
+    fn prerequisites(&self, id: u32) -> impl Iterator<Item = u32> + '_ {
+        let blockers = self
+            .find(id)
+            .map(|t| t.details().blocked_by.clone())
+            .unwrap_or_default();
+        let children = self
+            .tasks
+            .iter()
+            .filter(move |t| t.details().parent == Some(id))
+            .map(AnyTask::id);
+        blockers.into_iter().chain(children)
+    }
+
+    /// A chain of prerequisites from `to` down to `from`, if `from` must
+    /// already come before `to`.
+    fn path(&self, from: u32, to: u32) -> Option<Vec<u32>> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![vec![to]];
+        while let Some(path) = stack.pop() {
+            let last = *path.last().unwrap();
+            if last == from {
+                return Some(path);
+            }
+            if seen.insert(last) {
+                for next in self.prerequisites(last) {
+                    let mut path = path.clone();
+                    path.push(next);
+                    stack.push(path);
+                }
+            }
+        }
+        None
+    }
+
+    /// Fails if making `before` a prerequisite of `after` would close a loop.
+    fn check_cycle(&self, before: u32, after: u32) -> Result<(), CommandError> {
+        if before == after {
+            return Err(CommandError::DependencyCycle(vec![after, before]));
+        }
+        match self.path(after, before) {
+            Some(mut path) => {
+                path.insert(0, after);
+                Err(CommandError::DependencyCycle(path))
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn set_parent(&mut self, id: u32, parent: Option<u32>) -> Result<(), CommandError> {
+        self.position(id)?;
+        if let Some(parent) = parent {
+            self.position(parent)?;
+            self.check_cycle(id, parent)?;
+        }
+        self.task_mut(id)?.details_mut().parent = parent;
+        Ok(())
+    }
+
+    pub fn add_dependency(&mut self, id: u32, blocker: u32) -> Result<(), CommandError> {
+        self.position(id)?;
+        self.position(blocker)?;
+        self.check_cycle(blocker, id)?;
+        let blocked_by = &mut self.task_mut(id)?.details_mut().blocked_by;
+        if !blocked_by.contains(&blocker) {
+            blocked_by.push(blocker);
+        }
+        Ok(())
+    }
+
+    pub fn remove_dependency(&mut self, id: u32, blocker: u32) -> Result<(), CommandError> {
+        let blocked_by = &mut self.task_mut(id)?.details_mut().blocked_by;
+        let before = blocked_by.len();
+        blocked_by.retain(|&b| b != blocker);
+        if blocked_by.len() == before {
+            return Err(CommandError::InvalidArgument(format!(
+                "task {} does not depend on task {}",
+                id, blocker
+            )));
+        }
+        Ok(())
+    }
+
+    /// Drops references to a deleted task: its subtasks move up to its
+    /// parent and nothing waits on it any more.
+    pub fn unlink(&mut self, id: u32, parent: Option<u32>) {
+        for task in &mut self.tasks {
+            let details = task.details_mut();
+            if details.parent == Some(id) {
+                details.parent = parent;
+            }
+            details.blocked_by.retain(|&b| b != id);
+        }
+    }
+
+    /// Blockers of `id` that are not finished yet.
+    pub fn unfinished_blockers(&self, id: u32) -> Vec<u32> {
+        let Some(task) = self.find(id) else {
+            return Vec::new();
+        };
+        task.details()
+            .blocked_by
+            .iter()
+            .copied()
+            .filter(|&b| self.find(b).is_some_and(|t| !t.is_finished()))
+            .collect()
+    }
+
+    /// Every unfinished task that is neither blocked nor waiting on a blocked
+    /// task, in an order that respects dependencies and subtasks, picking the
+    /// most urgent ready task first. Anything caught in a cycle (possible
+    /// after merging another session's changes) goes last.
+    pub fn plan(&self) -> Vec<&AnyTask> {
+        let mut open: Vec<&AnyTask> = self
+            .tasks
+            .iter()
+            .filter(|t| !t.is_finished() && !matches!(t, AnyTask::Blocked(_)))
+            .collect();
+        // Whatever waits on a task left out cannot be completed either.
+        let ids = loop {
+            let ids: HashSet<u32> = open.iter().map(|t| t.id()).collect();
+            let before = open.len();
+            open.retain(|t| {
+                self.unfinished_blockers(t.id())
+                    .iter()
+                    .all(|b| ids.contains(b))
+            });
+            if open.len() == before {
+                break ids;
+            }
+        };
+        let by_id: HashMap<u32, &AnyTask> = open.iter().map(|t| (t.id(), *t)).collect();
+
+        let mut waiting: HashMap<u32, usize> = HashMap::new();
+        let mut unlocks: HashMap<u32, Vec<u32>> = HashMap::new();
+        for task in &open {
+            for before in self.prerequisites(task.id()).filter(|b| ids.contains(b)) {
+                *waiting.entry(task.id()).or_default() += 1;
+                unlocks.entry(before).or_default().push(task.id());
+            }
+        }
+
+        let urgency = |task: &AnyTask| {
+            let details = task.details();
+            (
+                Reverse(details.priority),
+                details.due.is_none(),
+                details.due,
+                task.id(),
+            )
+        };
+        let mut ready: BTreeSet<_> = open
+            .iter()
+            .filter(|t| !waiting.contains_key(&t.id()))
+            .map(|t| urgency(t))
+            .collect();
+        let mut order = Vec::with_capacity(open.len());
+        while let Some(key) = ready.pop_first() {
+            let id = key.3;
+            order.push(by_id[&id]);
+            for &next in unlocks.get(&id).into_iter().flatten() {
+                let count = waiting.get_mut(&next).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    waiting.remove(&next);
+                    ready.insert(urgency(by_id[&next]));
+                }
+            }
+        }
+        order.extend(open.iter().filter(|t| waiting.contains_key(&t.id())));
+        order
+    }
+
+    /// `tasks` arranged as a forest, each paired with its depth. A task whose
+    /// parent is not among `tasks` is shown as a root.
+    pub fn tree<'a>(&self, tasks: &[&'a AnyTask]) -> Vec<(usize, &'a AnyTask)> {
+        let ids: HashSet<u32> = tasks.iter().map(|t| t.id()).collect();
+        let mut children: HashMap<u32, Vec<&AnyTask>> = HashMap::new();
+        let mut roots = Vec::new();
+        for task in tasks {
+            match task.details().parent.filter(|p| ids.contains(p)) {
+                Some(parent) => children.entry(parent).or_default().push(task),
+                None => roots.push(*task),
+            }
+        }
+
+        let mut out = Vec::with_capacity(tasks.len());
+        let mut seen = HashSet::new();
+        let mut stack: Vec<(usize, &AnyTask)> = roots.into_iter().rev().map(|t| (0, t)).collect();
+        while let Some((depth, task)) = stack.pop() {
+            if !seen.insert(task.id()) {
+                continue;
+            }
+            out.push((depth, task));
+            if let Some(kids) = children.get(&task.id()) {
+                stack.extend(kids.iter().rev().map(|t| (depth + 1, *t)));
+            }
+        }
+        // Parent loops have no root to hang from.
+        out.extend(
+            tasks
+                .iter()
+                .filter(|t| !seen.contains(&t.id()))
+                .map(|t| (0, *t)),
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CommandError;
+    use crate::store::MemoryStore;
+    use crate::tests::{manager, run};
+
+    fn cycle(result: Result<String, CommandError>) -> Vec<u32> {
+        match result {
+            Err(CommandError::DependencyCycle(path)) => path,
+            other => panic!("expected a cycle, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn direct_cycles_are_rejected() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add order parts").unwrap();
+        run(&mut manager, &mut store, "add build shelf").unwrap();
+        run(&mut manager, &mut store, "depend 2 1").unwrap();
+
+        assert_eq!(
+            cycle(run(&mut manager, &mut store, "depend 1 2")),
+            [1, 2, 1]
+        );
+        assert_eq!(cycle(run(&mut manager, &mut store, "depend 1 1")), [1, 1]);
+        assert!(
+            manager.tasks[manager.position(1).unwrap()]
+                .details()
+                .blocked_by
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn transitive_cycles_are_rejected() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        for line in [
+            "add order parts",
+            "add build shelf",
+            "add paint shelf",
+            "depend 2 1",
+            "depend 3 2",
+        ] {
+            run(&mut manager, &mut store, line).unwrap();
+        }
+        assert_eq!(
+            cycle(run(&mut manager, &mut store, "depend 1 3")),
+            [1, 3, 2, 1]
+        );
+        // A subtask comes before its parent, so that closes the loop too.
+        assert_eq!(
+            cycle(run(&mut manager, &mut store, "parent 3 1")),
+            [1, 3, 2, 1]
+        );
+        run(&mut manager, &mut store, "parent 1 3").unwrap();
+    }
+
+    #[test]
+    fn plan_leaves_out_everything_waiting_on_a_blocked_task() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        for line in [
+            "add order parts",
+            "add build shelf",
+            "add paint shelf",
+            "add sweep floor",
+            "add hang pictures !high",
+            "depend 2 1",
+            "depend 3 2",
+            "depend 5 4",
+            "block 1",
+        ] {
+            run(&mut manager, &mut store, line).unwrap();
+        }
+        let order: Vec<u32> = manager.plan().iter().map(|t| t.id()).collect();
+        assert_eq!(order, [4, 5]);
+    }
+}
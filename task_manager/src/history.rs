@@ -0,0 +1,210 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::task::AnyTask;
+use crate::{CommandError, TaskManager};
+
+/// How many commands `undo` can step back through.
+pub const HISTORY_LIMIT: usize = 100;
+
+/// Undo and redo stacks, saved with the tasks so they survive restarts.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    undo: VecDeque<Change>,
+    redo: VecDeque<Change>,
+}
+
+/// What one command did to the task list: the tasks it touched as they were
+/// before and after, each with its position in the list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    summary: String,
+    before: Vec<(usize, AnyTask)>,
+    after: Vec<(usize, AnyTask)>,
+    next_id: (u32, u32),
+}
+
+impl Change {
+    /// Diffs the task list as it was before a command against `after`.
+    /// Usually only the touched tasks are kept, but if the command reordered
+    /// the others the whole list is.
+    pub fn between(summary: String, before: &[AnyTask], next_id: u32, after: &TaskManager) -> Self {
+        let old: HashMap<u32, &AnyTask> = before.iter().map(|t| (t.id(), t)).collect();
+        let new: HashMap<u32, &AnyTask> = after.tasks.iter().map(|t| (t.id(), t)).collect();
+        let mut changed: HashSet<u32> = before
+            .iter()
+            .filter(|t| new.get(&t.id()) != Some(t))
+            .chain(after.tasks.iter().filter(|t| old.get(&t.id()) != Some(t)))
+            .map(AnyTask::id)
+            .collect();
+
+        let unchanged = |tasks: &[AnyTask]| -> Vec<u32> {
+            tasks
+                .iter()
+                .map(AnyTask::id)
+                .filter(|id| !changed.contains(id))
+                .collect()
+        };
+        if unchanged(before) != unchanged(&after.tasks) {
+            changed.extend(old.keys().chain(new.keys()));
+        }
+
+        let touched = |tasks: &[AnyTask]| -> Vec<(usize, AnyTask)> {
+            tasks
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| changed.contains(&t.id()))
+                .map(|(pos, t)| (pos, t.clone()))
+                .collect()
+        };
+        Change {
+            summary,
+            before: touched(before),
+            after: touched(&after.tasks),
+            next_id: (next_id, after.next_id),
+        }
+    }
+}
+
+impl History {
+    /// Records a freshly applied command, which makes anything undone so far
+    /// unreachable.
+    pub fn record(&mut self, change: Change) {
+        self.redo.clear();
+        push_bounded(&mut self.undo, change);
+    }
+
+    /// Applies `renumber` to every copy of a task kept for undo and redo.
+    pub fn renumber(&mut self, mut renumber: impl FnMut(&mut AnyTask)) {
+        let changes = self.undo.iter_mut().chain(self.redo.iter_mut());
+        for change in changes {
+            for (_, task) in change.before.iter_mut().chain(change.after.iter_mut()) {
+                renumber(task);
+            }
+        }
+    }
+}
+
+fn push_bounded(stack: &mut VecDeque<Change>, change: Change) {
+    if stack.len() == HISTORY_LIMIT {
+        stack.pop_front();
+    }
+    stack.push_back(change);
+}
+
+impl TaskManager {
+    pub fn undo(&mut self) -> Result<String, CommandError> {
+        let change = self.history.undo.back().cloned();
+        let change = change.ok_or(CommandError::NothingToUndo)?;
+        self.swap(&change.after, &change.before)?;
+        self.next_id = self.next_id.max(change.next_id.0);
+        self.history.undo.pop_back();
+        let message = format!("Undid: {}", change.summary);
+        push_bounded(&mut self.history.redo, change);
+        Ok(message)
+    }
+
+    pub fn redo(&mut self) -> Result<String, CommandError> {
+        let change = self.history.redo.back().cloned();
+        let change = change.ok_or(CommandError::NothingToRedo)?;
+        self.swap(&change.before, &change.after)?;
+        self.next_id = self.next_id.max(change.next_id.1);
+        self.history.redo.pop_back();
+        let message = format!("Redid: {}", change.summary);
+        push_bounded(&mut self.history.undo, change);
+        Ok(message)
+    }
+
+    /// Replaces the tasks in `from` with the ones in `to`, refusing if any of
+    /// them has changed since, e.g. by a save merged in from another session.
+    fn swap(
+        &mut self,
+        from: &[(usize, AnyTask)],
+        to: &[(usize, AnyTask)],
+    ) -> Result<(), CommandError> {
+        for (_, task) in from {
+            if !self.tasks.contains(task) {
+                return Err(CommandError::HistoryConflict(task.id()));
+            }
+        }
+        let from_ids: HashSet<u32> = from.iter().map(|(_, t)| t.id()).collect();
+        if let Some(task) = self
+            .tasks
+            .iter()
+            .find(|t| !from_ids.contains(&t.id()) && to.iter().any(|(_, u)| u.id() == t.id()))
+        {
+            return Err(CommandError::HistoryConflict(task.id()));
+        }
+
+        self.tasks.retain(|t| !from_ids.contains(&t.id()));
+        for (pos, task) in to {
+            let pos = (*pos).min(self.tasks.len());
+            self.tasks.insert(pos, task.clone());
+        }
+        for id in from.iter().chain(to).map(|(_, t)| t.id()) {
+            self.reindex(id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HISTORY_LIMIT;
+    use crate::store::MemoryStore;
+    use crate::tests::{descriptions, manager, run, state};
+    use crate::{CommandError, TaskManager};
+
+    #[test]
+    fn undo_and_redo_step_through_changes() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        run(&mut manager, &mut store, "add walk dog").unwrap();
+        run(&mut manager, &mut store, "complete 1").unwrap();
+
+        run(&mut manager, &mut store, "undo").unwrap();
+        assert_eq!(state(&manager, 1), "pending");
+        run(&mut manager, &mut store, "undo").unwrap();
+        assert_eq!(descriptions(&manager), ["buy milk"]);
+        run(&mut manager, &mut store, "redo").unwrap();
+        run(&mut manager, &mut store, "redo").unwrap();
+        assert_eq!(descriptions(&manager), ["buy milk", "walk dog"]);
+        assert_eq!(state(&manager, 1), "completed");
+        assert!(matches!(
+            run(&mut manager, &mut store, "redo"),
+            Err(CommandError::NothingToRedo)
+        ));
+    }
+
+    #[test]
+    fn a_new_command_clears_what_was_undone() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        run(&mut manager, &mut store, "undo").unwrap();
+        run(&mut manager, &mut store, "add walk dog").unwrap();
+        assert!(matches!(
+            run(&mut manager, &mut store, "redo"),
+            Err(CommandError::NothingToRedo)
+        ));
+    }
+
+    #[test]
+    fn history_is_saved_and_bounded() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        for n in 0..HISTORY_LIMIT + 5 {
+            run(&mut manager, &mut store, &format!("add task {}", n)).unwrap();
+        }
+        manager.save(&mut store).unwrap();
+
+        let mut loaded = TaskManager::load(&mut store).unwrap();
+        for _ in 0..HISTORY_LIMIT {
+            run(&mut loaded, &mut store, "undo").unwrap();
+        }
+        assert_eq!(loaded.tasks.len(), 5);
+        assert!(matches!(
+            run(&mut loaded, &mut store, "undo"),
+            Err(CommandError::NothingToUndo)
+        ));
+    }
+}
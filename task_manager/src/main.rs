@@ -1,237 +1,1434 @@
+mod args;
+mod audit;
+mod autosave;
+mod bulk;
+mod dates;
+mod editor;
+mod graph;
+mod history;
+mod merge;
+mod output;
+mod query;
+mod recurrence;
+mod schema;
+mod search;
+mod server;
+mod store;
+mod task;
+mod timetrack;
+mod transfer;
+mod tui;
+
+use std::env;
 use std::fmt::{self, Display};
-use std::fs;
-use std::io::{self, BufRead, Write};
-use std::marker::PhantomData;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
 use std::time::Duration;
 
-use serde::{Deserialize, Serialize};
-
-const SAVE_FILE: &str = "tasks.json";
-
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub struct Pending;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use rustyline::error::ReadlineError;
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub struct Completed;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Task<State> {
-    id: u32,
-    description: String,
-    #[serde(skip)]
-    _phantom: PhantomData<State>,
-}
-
-impl Task<Pending> {
-    pub fn new(id: u32, description: String) -> Self {
-        Task {
-            id,
-            description,
-            _phantom: PhantomData,
-        }
-    }
+use args::{Arg, Args, Flag, Spec, Token};
+use audit::{Event, Stamp};
+use autosave::Autosaver;
+use bulk::Targets;
+use dates::Clock;
+use history::{Change, History};
+use output::{Format, TaskRecord};
+use query::Query;
+use recurrence::Recurrence;
+use search::SearchIndex;
+use store::{JournalEntry, LoadError, SharedStore, StoreKind, TaskStore};
+use task::{AnyTask, Details, Field, Priority, Task, Transition};
+use timetrack::{GroupBy, Period, TimeEntry};
+use transfer::{Imported, Transfer};
 
-    pub fn complete(self) -> Task<Completed> {
-        Task {
-            id: self.id,
-            description: self.description,
-            _phantom: PhantomData,
-        }
-    }
+#[derive(Debug)]
+pub enum CommandError {
+    InvalidCommand,
+    /// A command line that does not fit the command's arguments, with the
+    /// column where the problem starts.
+    Syntax {
+        column: usize,
+        message: String,
+    },
+    InvalidArgument(String),
+    TaskNotFound(u32),
+    InvalidTransition {
+        id: u32,
+        state: &'static str,
+        verb: &'static str,
+    },
+    NothingToUndo,
+    NothingToRedo,
+    HistoryConflict(u32),
+    InvalidQuery(String),
+    BlockedBy {
+        id: u32,
+        blockers: Vec<u32>,
+    },
+    DependencyCycle(Vec<u32>),
+    TimerRunning(u32),
+    NoTimer,
+    /// A query given in place of task ids matched nothing.
+    NoMatch(String),
+    /// Some of the tasks a bulk command named could not be changed, so none
+    /// were.
+    Batch {
+        succeeded: Vec<u32>,
+        failed: Vec<(u32, CommandError)>,
+    },
+    Io(String),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-enum AnyTask {
-    Pending(Task<Pending>),
-    Completed(Task<Completed>),
+fn join_ids(ids: &[u32], separator: &str) -> String {
+    ids.iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(separator)
 }
 
-impl AnyTask {
-    fn id(&self) -> u32 {
+impl CommandError {
+    /// The exit status for a one-shot command that failed this way: 2 for a
+    /// malformed command, 3 for an unknown task, 4 for a change the task's
+    /// state or dependencies do not allow, 5 for nothing to undo or redo and
+    /// 1 when the command could not be recorded.
+    pub fn exit_code(&self) -> i32 {
         match self {
-            AnyTask::Pending(t) => t.id,
-            AnyTask::Completed(t) => t.id,
+            CommandError::InvalidCommand
+            | CommandError::Syntax { .. }
+            | CommandError::InvalidArgument(_)
+            | CommandError::InvalidQuery(_) => 2,
+            CommandError::TaskNotFound(_) | CommandError::NoMatch(_) => 3,
+            CommandError::InvalidTransition { .. }
+            | CommandError::BlockedBy { .. }
+            | CommandError::DependencyCycle(_)
+            | CommandError::TimerRunning(_)
+            | CommandError::NoTimer => 4,
+            CommandError::NothingToUndo
+            | CommandError::NothingToRedo
+            | CommandError::HistoryConflict(_) => 5,
+            CommandError::Batch { failed, .. } => failed[0].1.exit_code(),
+            CommandError::Io(_) => 1,
         }
     }
 }
 
-impl Display for AnyTask {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            AnyTask::Pending(t) => write!(f, "[ ] {:<4} {}", t.id, t.description),
-            AnyTask::Completed(t) => write!(f, "[x] {:<4} {}", t.id, t.description),
-        }
+/// `5` or `5-9` for a run of consecutive ids.
+fn id_span(ids: &[u32]) -> String {
+    match ids {
+        [] => "nothing".to_string(),
+        [id] => format!("task {}", id),
+        [first, .., last] => format!("tasks {}-{}", first, last),
     }
 }
 
-#[derive(Debug)]
-pub enum CommandError {
-    InvalidCommand,
-    MissingArgument(String),
-    InvalidArgument(String),
-}
-
 impl Display for CommandError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             CommandError::InvalidCommand => write!(f, "Invalid command. Try 'help'."),
-            CommandError::MissingArgument(arg) => write!(f, "Missing argument for '{}'", arg),
+            CommandError::Syntax { column, message } => {
+                write!(f, "Column {}: {}", column, message)
+            }
             CommandError::InvalidArgument(val) => write!(f, "Invalid argument: '{}'", val),
+            CommandError::TaskNotFound(id) => write!(f, "Task {} not found.", id),
+            CommandError::InvalidTransition { id, state, verb } => {
+                write!(f, "Task {} is {} and cannot be {}.", id, state, verb)
+            }
+            CommandError::NothingToUndo => write!(f, "Nothing to undo."),
+            CommandError::NothingToRedo => write!(f, "Nothing to redo."),
+            CommandError::HistoryConflict(id) => write!(
+                f,
+                "Task {} has changed since, so this can no longer be undone or redone.",
+                id
+            ),
+            CommandError::InvalidQuery(message) => write!(f, "Invalid query: {}", message),
+            CommandError::BlockedBy { id, blockers } => write!(
+                f,
+                "Task {} is waiting on unfinished task(s) {}.",
+                id,
+                join_ids(blockers, ", ")
+            ),
+            CommandError::DependencyCycle(path) => write!(
+                f,
+                "That would make tasks depend on themselves: {}.",
+                join_ids(path, " -> ")
+            ),
+            CommandError::TimerRunning(id) => {
+                write!(f, "The timer is already running on task {}.", id)
+            }
+            CommandError::NoTimer => write!(f, "No timer is running."),
+            CommandError::NoMatch(query) => write!(f, "No tasks match '{}'.", query),
+            CommandError::Batch { succeeded, failed } => {
+                write!(
+                    f,
+                    "Nothing was changed, as {} of {} task(s) failed:",
+                    failed.len(),
+                    failed.len() + succeeded.len()
+                )?;
+                for (id, error) in failed {
+                    write!(f, "\n  task {}: {}", id, error)?;
+                }
+                if !succeeded.is_empty() {
+                    write!(
+                        f,
+                        "\nThe rest would have succeeded: {}.",
+                        bulk::id_ranges(succeeded)
+                    )?;
+                }
+                Ok(())
+            }
+            CommandError::Io(message) => write!(f, "{}", message),
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Command {
-    Add(String),
+    Add(#[serde(deserialize_with = "details_or_text")] Details),
+    Edit(u32, #[serde(deserialize_with = "details_or_text")] Details),
+    /// Empties fields of a task or takes tags off it.
+    Clear(u32, Vec<Field>),
+    Note(u32, String),
+    Delete(u32),
+    Parent(u32, Option<u32>),
+    Depend(u32, u32),
+    Undepend(u32, u32),
+    Start(u32),
     Complete(u32),
-    List,
+    Block(u32),
+    Unblock(u32),
+    Cancel(u32),
+    Reopen(u32),
+    Archive(u32),
+    /// Times work on a task from the given moment, starting it if pending.
+    StartTimer(u32, NaiveDateTime),
+    Stop(NaiveDateTime),
+    Log(u32, TimeEntry),
+    /// Several commands applied together, all or none.
+    Batch(Vec<Command>),
+    /// A command to repeat for each of the targeted tasks, whose id it holds
+    /// as 0 until the targets are looked up.
+    #[serde(skip)]
+    Bulk(Targets, Box<Command>),
+    /// Tasks read from a file when the command was parsed.
+    Import(Vec<Imported>),
+    #[serde(skip)]
+    ImportPreview(Vec<Imported>),
+    #[serde(skip)]
+    Export(PathBuf, Transfer),
+    Undo,
+    Redo,
+    /// Never journaled, so the parsed query need not be serializable.
+    #[serde(skip)]
+    List(Query, Format),
+    #[serde(skip)]
+    Report(Period, GroupBy, Format),
+    Search(String),
+    History(u32),
+    Next,
     Help,
 }
 
+/// Journals written before tasks had metadata hold a bare description.
+fn details_or_text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Details, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Saved {
+        Text(String),
+        Details(Details),
+    }
+    Ok(match Saved::deserialize(deserializer)? {
+        Saved::Text(description) => Details {
+            description,
+            ..Details::default()
+        },
+        Saved::Details(details) => details,
+    })
+}
+
+/// Time given to reminders set for a day without a time of day.
+const DEFAULT_REMIND_TIME: NaiveTime = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+
+/// Splits `+tag`, `!priority`, `due:<date>`, `remind:<date>`, `parent:<id>`,
+/// `every:<n><unit>` and `rrule:<RRULE>` words out of `words`; the rest make
+/// up the description, as do quoted words. Dates may run over several words,
+/// as in `due:next friday` or `remind:tomorrow 9am`, relative to `now`.
+fn parse_details(words: &[Token], now: NaiveDateTime) -> Result<Details, CommandError> {
+    let mut details = Details::default();
+    let mut description = Vec::new();
+    let mut i = 0;
+    while let Some(token) = words.get(i) {
+        i += 1;
+        let word = token.text.as_str();
+        if token.literal {
+            description.push(word);
+            continue;
+        }
+        if let Some((key, first)) = word.split_once(':')
+            && (key == "due" || key == "remind")
+        {
+            // Take the longest run of words that still reads as a date.
+            let (taken, when) = (0..dates::MAX_WORDS)
+                .rev()
+                .filter(|&extra| i + extra <= words.len())
+                .filter(|&extra| !words[i..i + extra].iter().any(|t| t.literal))
+                .find_map(|extra| {
+                    let mut phrase = vec![first];
+                    phrase.extend(words[i..i + extra].iter().map(|t| t.text.as_str()));
+                    dates::parse(&phrase.join(" "), now).map(|when| (extra, when))
+                })
+                .ok_or_else(|| token.error(format!("Invalid date in '{}'", word)))?;
+            i += taken;
+            if key == "due" {
+                details.due = Some(when.date);
+            } else {
+                details.remind = Some(when.at(DEFAULT_REMIND_TIME));
+            }
+            continue;
+        }
+
+        if let Some(tag) = word.strip_prefix('+').filter(|tag| !tag.is_empty()) {
+            if !details.tags.iter().any(|t| t == tag) {
+                details.tags.push(tag.to_string());
+            }
+        } else if let Some(priority) = word.strip_prefix('!').filter(|p| !p.is_empty()) {
+            let priority = Priority::try_from(priority)
+                .map_err(|_| token.error(format!("Unknown priority '{}'", word)))?;
+            details.priority = Some(priority);
+        } else if let Some(parent) = word.strip_prefix("parent:") {
+            let parent = parent
+                .parse()
+                .map_err(|_| token.error(format!("Invalid task id in '{}'", word)))?;
+            details.parent = Some(parent);
+        } else if let Some(spec) = word.strip_prefix("every:") {
+            let recurrence = Recurrence::parse_every(spec)
+                .map_err(|e| token.error(format!("{}: {}", word, e)))?;
+            details.recurrence = Some(recurrence);
+        } else if let Some(spec) = word.strip_prefix("rrule:") {
+            let recurrence = Recurrence::parse_rrule(spec)
+                .map_err(|e| token.error(format!("{}: {}", word, e)))?;
+            details.recurrence = Some(recurrence);
+        } else {
+            description.push(word);
+        }
+    }
+    details.description = description.join(" ");
+    Ok(details)
+}
+
+/// The `--format` given to `list` or `report`, if any.
+fn output_format(args: &Args) -> Result<Format, CommandError> {
+    match args.value("format") {
+        Some(name) => Format::try_from(name.text.as_str()).map_err(|e| name.error(e)),
+        None => Ok(Format::Table),
+    }
+}
+
 impl TryFrom<String> for Command {
     type Error = CommandError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let mut parts = value.trim().splitn(2, ' ');
-        let command_name = parts.next().ok_or(CommandError::InvalidCommand)?;
-        let args = parts.next();
+        Command::parse(&value, Clock::System)
+    }
+}
+
+/// Stands in for the tasks an id-taking command is given until they are
+/// known; see `Command::retarget`.
+const TARGET: u32 = 0;
+
+/// `--format <format>`, for commands that print or write tasks.
+const FORMAT: Flag = Flag::value("format", "format");
+
+impl Command {
+    /// What each command accepts after its name.
+    const SPECS: &'static [Spec] = &[
+        Spec::new("add", &[Arg::Text("description")], &[]),
+        Spec::new("edit", &[Arg::Tasks("ids"), Arg::Text("description")], &[]),
+        Spec::new("note", &[Arg::Tasks("ids"), Arg::Text("text")], &[]),
+        Spec::new("delete", &[Arg::Tasks("ids")], &[]),
+        Spec::new("parent", &[Arg::Tasks("ids"), Arg::IdOrNone("parent")], &[]),
+        Spec::new("depend", &[Arg::Tasks("ids"), Arg::Id("blocker")], &[]),
+        Spec::new("undepend", &[Arg::Tasks("ids"), Arg::Id("blocker")], &[]),
+        Spec::new("start", &[Arg::Tasks("ids")], &[]),
+        Spec::new("stop", &[], &[]),
+        Spec::new(
+            "log",
+            &[
+                Arg::Tasks("ids"),
+                Arg::Word("duration"),
+                Arg::OptionalText("date"),
+            ],
+            &[],
+        ),
+        Spec::new("report", &[Arg::OptionalText("period")], &[FORMAT]),
+        Spec::new("complete", &[Arg::Tasks("ids")], &[]),
+        Spec::new("block", &[Arg::Tasks("ids")], &[]),
+        Spec::new("unblock", &[Arg::Tasks("ids")], &[]),
+        Spec::new("cancel", &[Arg::Tasks("ids")], &[]),
+        Spec::new("reopen", &[Arg::Tasks("ids")], &[]),
+        Spec::new("archive", &[Arg::Tasks("ids")], &[]),
+        Spec::new(
+            "import",
+            &[Arg::Word("file")],
+            &[FORMAT, Flag::switch("dry-run")],
+        ),
+        Spec::new("export", &[Arg::Word("file")], &[FORMAT]),
+        Spec::new("search", &[Arg::Text("terms")], &[]),
+        Spec::new("history", &[Arg::Tasks("ids")], &[]),
+        Spec::new("next", &[], &[]),
+        Spec::new("undo", &[], &[]),
+        Spec::new("redo", &[], &[]),
+        Spec::new(
+            "list",
+            &[Arg::OptionalText("query")],
+            &[
+                FORMAT,
+                Flag::value("filter", "query"),
+                Flag::value("sort", "keys"),
+                Flag::value("limit", "n"),
+            ],
+        ),
+        Spec::new("help", &[], &[]),
+    ];
+
+    fn spec(name: &str) -> Option<&'static Spec> {
+        Command::SPECS.iter().find(|spec| spec.name == name)
+    }
+
+    /// Whether this command stops work on task `id`.
+    fn ends_work(&self, id: u32) -> bool {
+        match self {
+            Command::Complete(target)
+            | Command::Block(target)
+            | Command::Cancel(target)
+            | Command::Delete(target) => *target == id,
+            Command::Batch(commands) => commands.iter().any(|c| c.ends_work(id)),
+            _ => false,
+        }
+    }
+
+    /// Parses a command line, reading relative dates against `clock`.
+    fn parse(value: &str, clock: Clock) -> Result<Self, CommandError> {
+        Command::from_words(&args::tokenize(value)?, clock)
+    }
+
+    /// Builds a command from the words of a command line.
+    fn from_words(words: &[Token], clock: Clock) -> Result<Self, CommandError> {
+        let now = clock.now();
+        let (name, words) = words.split_first().ok_or(CommandError::InvalidCommand)?;
+        let spec = Command::spec(&name.text.to_lowercase()).ok_or(CommandError::InvalidCommand)?;
+        let args = spec.parse(name, words)?;
+        let targets = match spec.args.first() {
+            Some(Arg::Tasks(_)) => Some(Targets::parse(args.word(0), now)?),
+            _ => None,
+        };
+        let command = Command::build(spec, &args, now)?;
+        Ok(match targets {
+            Some(Targets::Ids(ids)) if ids.len() == 1 => command.retarget(ids[0]),
+            Some(targets) => Command::Bulk(targets, Box::new(command)),
+            None => command,
+        })
+    }
 
-        match command_name.to_lowercase().as_str() {
+    /// Builds the command `args` describe, for task `TARGET` if it takes ids.
+    fn build(spec: &Spec, args: &Args, now: NaiveDateTime) -> Result<Self, CommandError> {
+        match spec.name {
             "add" => {
-                let description = args
-                    .ok_or(CommandError::MissingArgument("add".to_string()))?
-                    .to_string();
-                Ok(Command::Add(description))
-            }
-            "complete" => {
-                let id_str = args.ok_or(CommandError::MissingArgument("complete".to_string()))?;
-                let id = id_str
-                    .parse::<u32>()
-                    .map_err(|_| CommandError::InvalidArgument(id_str.to_string()))?;
-                Ok(Command::Complete(id))
-            }
-            "list" => Ok(Command::List),
+                let mut details = parse_details(args.words(0), now)?;
+                if details.description.is_empty() {
+                    return Err(args.missing(0));
+                }
+                // Pin the first occurrence now so replaying the journal on
+                // another day gives the same dates.
+                if let (Some(recurrence), None) = (&details.recurrence, details.due) {
+                    details.due = Some(recurrence.first_from(now.date()));
+                }
+                Ok(Command::Add(details))
+            }
+            "edit" => Ok(Command::Edit(TARGET, parse_details(args.words(1), now)?)),
+            "note" => Ok(Command::Note(TARGET, args.text(1))),
+            "delete" => Ok(Command::Delete(TARGET)),
+            "parent" => Ok(Command::Parent(TARGET, args.id_or_none(1))),
+            "depend" => Ok(Command::Depend(TARGET, args.id(1))),
+            "undepend" => Ok(Command::Undepend(TARGET, args.id(1))),
+            "start" => Ok(Command::StartTimer(TARGET, now)),
+            "stop" => Ok(Command::Stop(now)),
+            "log" => {
+                let duration = args.word(1);
+                let seconds = timetrack::parse_duration(&duration.text).ok_or_else(|| {
+                    duration.error(format!("Invalid duration '{}'", duration.text))
+                })?;
+                // Without a date the logged work is taken to have just ended.
+                let start = match args.text(2) {
+                    date if date.is_empty() => i64::try_from(seconds)
+                        .ok()
+                        .and_then(chrono::Duration::try_seconds)
+                        .and_then(|duration| now.checked_sub_signed(duration))
+                        .ok_or_else(|| {
+                            duration.error(format!("Invalid duration '{}'", duration.text))
+                        })?,
+                    date => dates::parse(&date, now)
+                        .ok_or_else(|| args.error(2, format!("Invalid date '{}'", date)))?
+                        .at(NaiveTime::MIN),
+                };
+                Ok(Command::Log(TARGET, TimeEntry { start, seconds }))
+            }
+            "report" => {
+                let (period, group_by) = timetrack::parse_report(args.words(0))?;
+                Ok(Command::Report(period, group_by, output_format(args)?))
+            }
+            "complete" => Ok(Command::Complete(TARGET)),
+            "block" => Ok(Command::Block(TARGET)),
+            "unblock" => Ok(Command::Unblock(TARGET)),
+            "cancel" => Ok(Command::Cancel(TARGET)),
+            "reopen" => Ok(Command::Reopen(TARGET)),
+            "archive" => Ok(Command::Archive(TARGET)),
+            "import" => {
+                let (path, format) = transfer::parse_args(args)?;
+                let tasks = transfer::read(&path, format, now)?;
+                Ok(if args.flag("dry-run") {
+                    Command::ImportPreview(tasks)
+                } else {
+                    Command::Import(tasks)
+                })
+            }
+            "export" => {
+                let (path, format) = transfer::parse_args(args)?;
+                Ok(Command::Export(path, format))
+            }
+            "search" => Ok(Command::Search(args.text(0))),
+            "history" => Ok(Command::History(TARGET)),
+            "next" => Ok(Command::Next),
+            "undo" => Ok(Command::Undo),
+            "redo" => Ok(Command::Redo),
+            "list" => {
+                // The query has quotes and regexes of its own, so it is read
+                // as typed.
+                let mut query = vec![args::join(args.words(0), |t| &t.raw)];
+                if let Some(filter) = args.value("filter") {
+                    query.push(filter.text.clone());
+                }
+                if let Some(sort) = args.value("sort") {
+                    query.push(format!("sort:{}", sort.text));
+                }
+                if let Some(limit) = args.value("limit") {
+                    query.push(format!("limit:{}", limit.text));
+                }
+                let query =
+                    Query::parse(&query.join(" "), now).map_err(CommandError::InvalidQuery)?;
+                Ok(Command::List(query, output_format(args)?))
+            }
             "help" => Ok(Command::Help),
-            "" => Err(CommandError::InvalidCommand),
-            _ => Err(CommandError::InvalidCommand),
+            name => unreachable!("no parser for '{}'", name),
         }
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct TaskManager {
     tasks: Vec<AnyTask>,
     next_id: u32,
+    /// Number of commands applied so far. Journal entries at or below this
+    /// revision are already part of the saved snapshot.
+    revision: u64,
+    history: History,
+    /// Every change ever made to each task, oldest first.
+    audit: Vec<Event>,
+    #[serde(skip)]
+    index: SearchIndex,
+    #[serde(skip)]
+    clock: Clock,
+    /// `revision` as of the last load or save.
+    #[serde(skip)]
+    saved_revision: u64,
+}
+
+/// The parts of a `TaskManager` a command can change, taken before it runs.
+/// The audit log only grows, so its length is enough.
+struct Checkpoint {
+    tasks: Vec<AnyTask>,
+    next_id: u32,
+    revision: u64,
+    history: History,
+    audit_len: usize,
 }
 
 impl TaskManager {
-    fn load() -> Self {
-        fs::read_to_string(SAVE_FILE)
-            .ok()
-            .and_then(|content| serde_json::from_str(&content).ok())
-            .unwrap_or_else(|| TaskManager {
-                tasks: Vec::new(),
-                next_id: 1,
-            })
+    fn new() -> Self {
+        TaskManager {
+            tasks: Vec::new(),
+            next_id: 1,
+            revision: 0,
+            history: History::default(),
+            audit: Vec::new(),
+            index: SearchIndex::default(),
+            clock: Clock::System,
+            saved_revision: 0,
+        }
+    }
+
+    fn load(store: &mut dyn TaskStore) -> Result<Self, LoadError> {
+        let mut manager = match store.load() {
+            Ok(manager) => manager,
+            // The journal may still hold commands from a session that never saved.
+            Err(LoadError::Missing) => TaskManager::new(),
+            Err(e) => return Err(e),
+        };
+        manager.saved_revision = manager.revision;
+        manager.index = SearchIndex::build(&manager.tasks);
+        manager.replay(store.journal()?);
+        Ok(manager)
+    }
+
+    /// Salvages what it can from unreadable saved data, then moves that data to
+    /// a backup. Returns the recovered manager and the backup location.
+    fn recover(store: &mut dyn TaskStore) -> io::Result<(Self, PathBuf)> {
+        let mut manager = store.salvage()?;
+        let journal = store.journal();
+        let backup = store.quarantine()?;
+        manager.index = SearchIndex::build(&manager.tasks);
+        match journal {
+            Ok(entries) => manager.replay(entries),
+            Err(e) => eprintln!("Warning: Skipped unreadable journal: {}", e),
+        }
+        Ok((manager, backup))
+    }
+
+    fn save(&mut self, store: &mut dyn TaskStore) -> io::Result<()> {
+        store.save(self)?;
+        self.saved_revision = self.revision;
+        Ok(())
     }
 
-    fn save(&self) -> io::Result<()> {
-        let json = serde_json::to_string_pretty(self).unwrap();
-        fs::write(SAVE_FILE, json)
+    /// Merges in what another session saved since, leaving a manager with
+    /// nothing unsaved still clean. Returns whether anything was merged.
+    fn refresh(&mut self, store: &mut dyn TaskStore) -> io::Result<bool> {
+        let clean = !self.is_dirty();
+        let merged = store.refresh(self)?;
+        if merged && clean {
+            self.saved_revision = self.revision;
+        }
+        Ok(merged)
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.revision != self.saved_revision
     }
 
-    fn add_task(&mut self, description: String) {
-        let new_task = Task::new(self.next_id, description);
-        self.tasks.push(AnyTask::Pending(new_task));
-        println!("Added task {}.", self.next_id);
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            tasks: self.tasks.clone(),
+            next_id: self.next_id,
+            revision: self.revision,
+            history: self.history.clone(),
+            audit_len: self.audit.len(),
+        }
+    }
+
+    /// Puts back what a failed command may have changed since `checkpoint`.
+    fn roll_back(&mut self, checkpoint: Checkpoint) {
+        self.tasks = checkpoint.tasks;
+        self.next_id = checkpoint.next_id;
+        self.revision = checkpoint.revision;
+        self.history = checkpoint.history;
+        self.audit.truncate(checkpoint.audit_len);
+        self.index = SearchIndex::build(&self.tasks);
+    }
+
+    /// Applies journaled commands on top of the loaded snapshot. An entry that
+    /// no longer applies, or that crashes, is reported and skipped so the rest
+    /// of the data still loads.
+    fn replay(&mut self, entries: Vec<JournalEntry>) {
+        for entry in entries {
+            let stamp = entry.stamp.unwrap_or_else(|| Stamp {
+                at: self.clock.now(),
+                session: String::new(),
+            });
+            let checkpoint = self.checkpoint();
+            let applied =
+                panic::catch_unwind(AssertUnwindSafe(|| self.apply(entry.command, &stamp)));
+            let error = match applied {
+                Ok(Ok(_)) => continue,
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => "applying it crashed".to_string(),
+            };
+            self.roll_back(checkpoint);
+            eprintln!(
+                "Warning: Skipped journal entry {}: {}",
+                entry.revision, error
+            );
+        }
+    }
+
+    /// Applies a mutating command, then records it in the store's journal.
+    /// A command that fails leaves the manager as it was and is not recorded,
+    /// so every journal entry replays cleanly and has a revision of its own.
+    fn execute(
+        &mut self,
+        command: Command,
+        store: &mut dyn TaskStore,
+    ) -> Result<String, CommandError> {
+        // Stop the timer as a command of its own, so replaying the journal
+        // ends the interval at the same moment.
+        if let Some((running, _)) = self.running_timer()
+            && command.ends_work(running)
+        {
+            let stopped = self.execute(Command::Stop(self.clock.now()), store)?;
+            return self
+                .execute(command, store)
+                .map(|message| format!("{} {}", stopped, message));
+        }
+        let stamp = Stamp {
+            at: self.clock.now(),
+            session: store.session().to_string(),
+        };
+        let checkpoint = self.checkpoint();
+        let result = self.apply(command.clone(), &stamp).and_then(|message| {
+            let entry = JournalEntry {
+                revision: self.revision,
+                command,
+                stamp: Some(stamp),
+            };
+            store
+                .append(&entry)
+                .map_err(|e| CommandError::Io(format!("Failed to record command: {}", e)))?;
+            Ok(message)
+        });
+        if result.is_err() {
+            self.roll_back(checkpoint);
+        }
+        result
+    }
+
+    fn apply(&mut self, command: Command, stamp: &Stamp) -> Result<String, CommandError> {
+        let before = self.tasks.clone();
+        let message = match command {
+            Command::Undo | Command::Redo => {
+                let message = match command {
+                    Command::Undo => self.undo()?,
+                    _ => self.redo()?,
+                };
+                self.log_changes(&before, stamp, Some(&message));
+                message
+            }
+            Command::List(..)
+            | Command::Report(..)
+            | Command::ImportPreview(_)
+            | Command::Export(..)
+            | Command::Search(_)
+            | Command::History(_)
+            | Command::Next
+            | Command::Help => {
+                return Ok(String::new());
+            }
+            command => {
+                let next_id = self.next_id;
+                let message = self.apply_change(command, stamp.at.date())?;
+                self.touch(&before, stamp.at);
+                self.log_changes(&before, stamp, None);
+                // Undo names a batch by its first line.
+                let summary = message.lines().next().unwrap_or_default().to_string();
+                let change = Change::between(summary, &before, next_id, self);
+                self.history.record(change);
+                message
+            }
+        };
+        self.revision += 1;
+        Ok(message)
+    }
+
+    fn apply_change(&mut self, command: Command, today: NaiveDate) -> Result<String, CommandError> {
+        Ok(match command {
+            Command::Add(details) => {
+                if let Some(parent) = details.parent {
+                    self.position(parent)?;
+                }
+                format!("Added task {}.", self.add_task(details))
+            }
+            Command::Edit(id, mut changes) => {
+                if let Some(parent) = changes.parent.take() {
+                    self.set_parent(id, Some(parent))?;
+                }
+                self.task_mut(id)?.details_mut().update(changes);
+                self.reindex(id);
+                format!("Edited task {}.", id)
+            }
+            Command::Clear(id, fields) => {
+                let details = self.task_mut(id)?.details_mut();
+                for field in &fields {
+                    details.clear(field);
+                }
+                self.reindex(id);
+                format!("Edited task {}.", id)
+            }
+            Command::Note(id, note) => {
+                self.task_mut(id)?.details_mut().add_note(&note);
+                self.reindex(id);
+                format!("Added a note to task {}.", id)
+            }
+            Command::Delete(id) => {
+                let pos = self.position(id)?;
+                let task = self.tasks.remove(pos);
+                self.unlink(id, task.details().parent);
+                self.reindex(id);
+                format!("Deleted task {}.", id)
+            }
+            Command::Parent(id, parent) => {
+                self.set_parent(id, parent)?;
+                match parent {
+                    Some(parent) => format!("Task {} is now a subtask of task {}.", id, parent),
+                    None => format!("Task {} is no longer a subtask.", id),
+                }
+            }
+            Command::Depend(id, blocker) => {
+                self.add_dependency(id, blocker)?;
+                format!("Task {} now depends on task {}.", id, blocker)
+            }
+            Command::Undepend(id, blocker) => {
+                self.remove_dependency(id, blocker)?;
+                format!("Task {} no longer depends on task {}.", id, blocker)
+            }
+            Command::Start(id) => {
+                self.transition(id, "started", AnyTask::start)?;
+                format!("Started task {}.", id)
+            }
+            Command::Complete(id) => match self.complete_task(id, today)? {
+                Some((next, due)) => format!(
+                    "Completed task {}. Its next occurrence is task {}, due {}.",
+                    id, next, due
+                ),
+                None => format!("Completed task {}.", id),
+            },
+            Command::Block(id) => {
+                self.transition(id, "blocked", AnyTask::block)?;
+                format!("Blocked task {}.", id)
+            }
+            Command::Unblock(id) => {
+                self.transition(id, "unblocked", AnyTask::unblock)?;
+                format!("Unblocked task {}.", id)
+            }
+            Command::Cancel(id) => {
+                self.transition(id, "cancelled", AnyTask::cancel)?;
+                format!("Cancelled task {}.", id)
+            }
+            Command::Reopen(id) => {
+                self.transition(id, "reopened", AnyTask::reopen)?;
+                format!("Reopened task {}.", id)
+            }
+            Command::Archive(id) => {
+                self.transition(id, "archived", AnyTask::archive)?;
+                format!("Archived task {}.", id)
+            }
+            Command::StartTimer(id, at) => self.start_timer(id, at)?,
+            Command::Stop(at) => self.stop_timer(at)?,
+            Command::Log(id, entry) => self.log_time(id, entry)?,
+            Command::Batch(commands) => self.apply_batch(commands, today)?,
+            Command::Import(tasks) => {
+                let ids = self.import(tasks);
+                format!("Imported {} task(s) as {}.", ids.len(), id_span(&ids))
+            }
+            Command::Undo
+            | Command::Redo
+            | Command::Bulk(..)
+            | Command::List(..)
+            | Command::Report(..)
+            | Command::ImportPreview(_)
+            | Command::Export(..)
+            | Command::Search(_)
+            | Command::History(_)
+            | Command::Next
+            | Command::Help => {
+                unreachable!("not a task change")
+            }
+        })
+    }
+
+    fn add_task(&mut self, details: Details) -> u32 {
+        let id = self.next_id;
+        self.tasks.push(AnyTask::Pending(Task::new(id, details)));
+        self.reindex(id);
         self.next_id += 1;
+        id
     }
 
-    fn complete_task(&mut self, id: u32) {
-        let task_pos = self
-            .tasks
+    fn position(&self, id: u32) -> Result<usize, CommandError> {
+        self.tasks
             .iter()
-            .position(|t| t.id() == id && matches!(t, AnyTask::Pending(_)));
+            .position(|t| t.id() == id)
+            .ok_or(CommandError::TaskNotFound(id))
+    }
 
-        if let Some(pos) = task_pos {
-            let old_task = self.tasks.remove(pos);
-            if let AnyTask::Pending(pending_task) = old_task {
-                let completed_task = pending_task.complete();
-                self.tasks.push(AnyTask::Completed(completed_task));
-                println!("Completed task {}.", id);
+    fn task_mut(&mut self, id: u32) -> Result<&mut AnyTask, CommandError> {
+        let pos = self.position(id)?;
+        Ok(&mut self.tasks[pos])
+    }
+
+    /// Moves task `id` into a new state in place. `verb` describes the
+    /// transition in the error when the task's current state does not allow it.
+    fn transition(
+        &mut self,
+        id: u32,
+        verb: &'static str,
+        f: impl FnOnce(AnyTask) -> Transition,
+    ) -> Result<usize, CommandError> {
+        let pos = self.position(id)?;
+        match f(self.tasks.remove(pos)) {
+            Ok(task) => {
+                self.tasks.insert(pos, task);
+                Ok(pos)
             }
-        } else {
-            println!("Error: Task {} not found or is already completed.", id);
+            Err(task) => {
+                let state = task.state();
+                self.tasks.insert(pos, task);
+                Err(CommandError::InvalidTransition { id, state, verb })
+            }
+        }
+    }
+
+    /// Completes task `id`. If it recurs, its next occurrence takes its place
+    /// in the list and is returned as `(id, due date)`. A task with no due
+    /// date repeats from `today`, the day the command was first run, so a
+    /// replay spawns the same occurrence.
+    fn complete_task(
+        &mut self,
+        id: u32,
+        today: NaiveDate,
+    ) -> Result<Option<(u32, NaiveDate)>, CommandError> {
+        let blockers = self.unfinished_blockers(id);
+        if !blockers.is_empty() {
+            return Err(CommandError::BlockedBy { id, blockers });
+        }
+        let pos = self.transition(id, "completed", AnyTask::complete)?;
+        let completed_task = self.tasks.remove(pos);
+        let details = completed_task.details();
+        let next = details.recurrence.as_ref().and_then(|recurrence| {
+            let due = details.due.unwrap_or(today);
+            recurrence.next_after(due)
+        });
+        let spawned = next.map(|(due, recurrence)| {
+            let next_id = self.next_id;
+            self.next_id += 1;
+            let details = Details {
+                due: Some(due),
+                recurrence: Some(recurrence),
+                blocked_by: Vec::new(),
+                time_log: Vec::new(),
+                timer_since: None,
+                ..details.clone()
+            };
+            self.tasks
+                .insert(pos, AnyTask::Pending(Task::new(next_id, details)));
+            self.reindex(next_id);
+            (next_id, due)
+        });
+        self.tasks.push(completed_task);
+        Ok(spawned)
+    }
+
+    fn search_tasks(&self, terms: &str) -> String {
+        let results = self.index.search(terms);
+        if results.is_empty() {
+            return format!("No tasks match '{}'.", terms);
+        }
+        let mut out = String::from("--------------- RESULTS ---------------\n");
+        for (id, _) in &results {
+            if let Some(task) = self.tasks.iter().find(|t| t.id() == *id) {
+                out.push_str(&format!("{}\n", task));
+            }
+        }
+        out.push_str("---------------------------------------");
+        out
+    }
+
+    /// Unfinished tasks whose reminder falls after `after` (if given) and no
+    /// later than `now`.
+    fn reminders(&self, after: Option<NaiveDateTime>, now: NaiveDateTime) -> Vec<&AnyTask> {
+        self.tasks
+            .iter()
+            .filter(|t| !t.is_finished())
+            .filter(|t| {
+                t.details()
+                    .remind
+                    .is_some_and(|remind| remind <= now && after.is_none_or(|after| remind > after))
+            })
+            .collect()
+    }
+
+    fn next_tasks(&self) -> String {
+        let plan = self.plan();
+        if plan.is_empty() {
+            return "Nothing left to do.".to_string();
+        }
+        let mut out = String::from("---------------- NEXT -----------------\n");
+        for task in plan {
+            out.push_str(&format!("{}\n", task));
         }
+        out.push_str("---------------------------------------");
+        out
+    }
+
+    /// `tasks` as a tree, each with its notes beneath it, one line per
+    /// task or note.
+    fn tree_lines(&self, tasks: &[&AnyTask]) -> String {
+        let mut out = String::new();
+        for (depth, task) in self.tree(tasks) {
+            let indent = "  ".repeat(depth);
+            out.push_str(&format!("{}{}\n", indent, task));
+            for note in task.details().notes.lines() {
+                out.push_str(&format!("{}         {}\n", indent, note));
+            }
+        }
+        out
+    }
+
+    /// Shows what importing `tasks` would add, without changing anything.
+    fn preview_import(&self, tasks: Vec<Imported>) -> String {
+        let mut preview = self.clone();
+        let ids = preview.import(tasks);
+        let added: Vec<&AnyTask> = preview
+            .tasks
+            .iter()
+            .filter(|t| ids.contains(&t.id()))
+            .collect();
+        format!(
+            "--------------- PREVIEW ---------------\n{}\
+             ---------------------------------------\n\
+             (dry run: would import {} task(s) as {})",
+            preview.tree_lines(&added),
+            ids.len(),
+            id_span(&ids)
+        )
     }
 
-    fn list_tasks(&self) {
+    fn list_tasks(&self, query: &Query, format: Format) -> String {
+        if format != Format::Table {
+            let records: Vec<TaskRecord> = query
+                .select(&self.tasks)
+                .into_iter()
+                .map(TaskRecord::from)
+                .collect();
+            return output::render(&records, format);
+        }
         if self.tasks.is_empty() {
-            println!("No tasks yet. Add one with 'add <description>'.");
-            return;
+            return "No tasks yet. Add one with 'add <description>'.".to_string();
         }
-        println!("---------------- TASKS ----------------");
-        self.tasks.iter().for_each(|t| println!("{}", t));
-        println!("---------------------------------------");
+        let selected = query.select(&self.tasks);
+        let mut out = format!(
+            "---------------- TASKS ----------------\n{}\
+             ---------------------------------------",
+            self.tree_lines(&selected)
+        );
+        if query.filter.is_some() || query.limit.is_some() {
+            out.push_str(&format!(
+                "\n({} of {} task(s) shown)",
+                selected.len(),
+                self.tasks.len()
+            ));
+        } else {
+            let archived = self
+                .tasks
+                .iter()
+                .filter(|t| matches!(t, AnyTask::Archived(_)))
+                .count();
+            if archived > 0 {
+                out.push_str(&format!("\n({} archived task(s) hidden)", archived));
+            }
+        }
+        out
     }
 }
 
-fn print_help() {
-    println!("\nAvailable Commands:");
-    println!("  add <description>    - Add a new task");
-    println!("  complete <id>        - Mark a task as complete");
-    println!("  list                 - Show all tasks");
-    println!("  help                 - Show this help message");
-    println!("  exit / quit          - Exit the application\n");
+const HELP: &str = r#"Available Commands:
+  add <description>    - Add a new task. Anywhere in the text, these tokens set:
+                           +tag !high|!medium|!low parent:<id>
+                           due:<date> remind:<date>, where a date is YYYY-MM-DD or
+                           e.g. 'tomorrow 9am', 'next friday', 'in 3 days', 'eom'
+                           every:day|3days|weekday|2weeks|month:15|year
+                           rrule:FREQ=WEEKLY;BYDAY=MO,TH;COUNT=5
+  edit <id> <text>     - Replace a task's description, priority, dates or repeat,
+                         or add tags, using the same tokens as 'add'
+  note <id> <text>     - Add a line to a task's notes
+  parent <id> <id>     - Make a task a subtask of another ('none' to detach)
+  depend <id> <id>     - Make a task wait until another is finished
+  undepend <id> <id>   - Remove a dependency
+  delete <id>          - Remove a task
+  start <id>           - Start working on a task and time it, stopping any other
+                         timer; completing, blocking or cancelling stops it too
+  stop                 - Stop the running timer
+  log <id> <duration> [date]
+                       - Record time spent, e.g. 'log 3 1h30m yesterday'
+  report [day|week] [by:task|by:tag] [--format json|csv|tsv|table]
+                       - Sum up logged time per task or tag for each day or week
+  complete <id>        - Mark a pending or started task as complete
+  block <id>           - Mark a pending or started task as blocked
+  unblock <id>         - Return a blocked task to pending
+  cancel <id>          - Cancel an unfinished task
+  reopen <id>          - Return a completed or cancelled task to pending
+  archive <id>         - Hide a completed or cancelled task from the list
+  import <file> [--format todotxt|markdown|csv|ical] [--dry-run]
+                       - Add tasks from another tool's file under new ids;
+                         the format follows the extension unless given
+  export <file> [--format todotxt|markdown|csv|ical]
+                       - Write every task to a file
+  search <terms>       - Find tasks by words in their description or notes,
+                         best matches first, tolerating typos
+  history <id>         - Show when a task was added, changed and completed
+  next                 - Show unfinished tasks in the order they can be done
+  undo                 - Revert the last change
+  redo                 - Reapply the last undone change
+  list [query]         - Show tasks, optionally filtered and sorted:
+                           state:<state> +tag !high due:YYYY-MM-DD|none
+                           due<YYYY-MM-DD due>YYYY-MM-DD word "phrase" /regex/
+                           combined with and, or, not and parentheses,
+                           plus sort:priority,-due,id,state,text and limit:N
+                           (or --filter <query> --sort <keys> --limit <n>),
+                           and --format json|csv|tsv|table for other programs
+  help                 - Show this help message
+  exit / quit          - Exit the application
+The first <id> of a command can also be a list like 3,5,9-12 or a list query like
+tag:sprint42 or "+work and !high"; then every task changes or, if any fails, none.
+Plain words only match task text when quoted, as in delete "old draft".
+Quote words to keep spaces or to use +, !, due: or -- as text: 'a b' "a b" a\ b
+Tab completes commands, task ids and tags; Up/Down and Ctrl-R search history."#;
+
+/// Runs one parsed command against `manager`. Returns what it has to show,
+/// and whether it changed anything that needs saving.
+fn run_command(
+    manager: &mut TaskManager,
+    store: &mut dyn TaskStore,
+    command: Command,
+) -> Result<(String, bool), CommandError> {
+    let output = match command {
+        Command::List(query, format) => manager.list_tasks(&query, format),
+        Command::Report(period, group_by, Format::Table) => {
+            manager.report(period, group_by, manager.clock.now())
+        }
+        Command::Report(period, group_by, format) => {
+            let records = manager.report_records(period, group_by, manager.clock.now());
+            output::render(&records, format)
+        }
+        Command::ImportPreview(tasks) => manager.preview_import(tasks),
+        Command::Export(path, format) => manager.export(&path, format)?,
+        Command::Search(terms) => manager.search_tasks(&terms),
+        Command::History(id) => manager.task_history(id)?,
+        Command::Next => manager.next_tasks(),
+        Command::Help => format!("\n{}\n", HELP),
+        Command::Bulk(targets, template) => {
+            let ids = manager.select(&targets)?;
+            let mut commands: Vec<Command> = ids.iter().map(|&id| template.retarget(id)).collect();
+            if commands.len() == 1 {
+                return run_command(manager, store, commands.remove(0));
+            }
+            match *template {
+                Command::History(_) => {
+                    let mut histories = Vec::new();
+                    for command in commands {
+                        histories.push(run_command(manager, store, command)?.0);
+                    }
+                    histories.join("\n")
+                }
+                Command::StartTimer(..) => {
+                    return Err(CommandError::InvalidArgument(format!(
+                        "{}: only one task can be timed at a time",
+                        bulk::id_ranges(&ids)
+                    )));
+                }
+                _ => return run_command(manager, store, Command::Batch(commands)),
+            }
+        }
+        command => return Ok((manager.execute(command, store)?, true)),
+    };
+    Ok((output, false))
 }
 
-fn main() {
-    let task_manager = Arc::new(Mutex::new(TaskManager::load()));
+/// Prints `error`, underlining the column of `line` it points at.
+fn print_error(line: &str, error: &CommandError) {
+    eprintln!("Error: {}", error);
+    if let CommandError::Syntax { column, .. } = error {
+        eprintln!("  {}", line);
+        eprintln!("  {}^", " ".repeat(column - 1));
+    }
+}
 
-    let saver_manager = Arc::clone(&task_manager);
-    thread::spawn(move || {
-        loop {
-            thread::sleep(Duration::from_secs(15));
-            let manager = saver_manager.lock().unwrap();
-            if let Err(e) = manager.save() {
-                eprintln!("[AUTOSAVE ERROR] Failed to save tasks: {}", e);
+/// Runs the command given on the command line, saves if it changed
+/// anything, and returns the exit status.
+fn run_once(mut manager: TaskManager, store: &mut dyn TaskStore, args: &[String]) -> i32 {
+    let result = Command::from_words(&args::from_args(args), manager.clock)
+        .and_then(|command| run_command(&mut manager, store, command));
+    match result {
+        Ok((output, changed)) => {
+            println!("{}", output);
+            if changed && let Err(e) = manager.save(store) {
+                eprintln!("Error: Failed to save tasks: {}", e);
+                return 1;
             }
+            0
+        }
+        Err(e) => {
+            print_error(&args.join(" "), &e);
+            e.exit_code()
         }
+    }
+}
+
+const USAGE: &str = "Usage: task_manager [--store json|memory|sqlite] [--path <file>] [--recover] [--autosave <secs>] [--now <YYYY-MM-DDTHH:MM>] [--tui | serve [--addr <host:port>] | <command> [<args>...]]";
+
+struct Config {
+    store: StoreKind,
+    path: Option<PathBuf>,
+    recover: bool,
+    autosave_interval: Duration,
+    clock: Clock,
+    /// Start the full-screen UI instead of the REPL.
+    tui: bool,
+    /// Serve the HTTP API on this address instead of starting the REPL.
+    serve: Option<String>,
+    /// A command to run instead of starting the REPL, e.g. `complete 3`.
+    command: Vec<String>,
+}
+
+impl Config {
+    fn from_args() -> Result<Self, String> {
+        let mut config = Config {
+            store: StoreKind::Json,
+            path: None,
+            recover: false,
+            autosave_interval: autosave::DEFAULT_INTERVAL,
+            clock: Clock::System,
+            tui: false,
+            serve: None,
+            command: Vec::new(),
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--store" => {
+                    let kind = args.next().ok_or("Missing value for '--store'")?;
+                    config.store = StoreKind::try_from(kind.as_str())?;
+                }
+                "--path" => {
+                    let path = args.next().ok_or("Missing value for '--path'")?;
+                    config.path = Some(PathBuf::from(path));
+                }
+                "--recover" => config.recover = true,
+                "--tui" => config.tui = true,
+                "--autosave" => {
+                    let secs = args.next().ok_or("Missing value for '--autosave'")?;
+                    let secs = secs
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|&secs| secs > 0)
+                        .ok_or_else(|| format!("Invalid autosave interval '{}'", secs))?;
+                    config.autosave_interval = Duration::from_secs(secs);
+                }
+                "--now" => {
+                    let now = args.next().ok_or("Missing value for '--now'")?;
+                    config.clock = Clock::parse(&now)?;
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+                "serve" => {
+                    let mut address = server::DEFAULT_ADDRESS.to_string();
+                    while let Some(arg) = args.next() {
+                        match arg.as_str() {
+                            "--addr" => {
+                                address = args.next().ok_or("Missing value for '--addr'")?
+                            }
+                            _ => return Err(format!("Unknown option '{}' for 'serve'", arg)),
+                        }
+                    }
+                    config.serve = Some(address);
+                }
+                // Everything from the first word on is the command.
+                _ => {
+                    config.command.push(arg);
+                    config.command.extend(args.by_ref());
+                }
+            }
+        }
+        if config.tui && (config.serve.is_some() || !config.command.is_empty()) {
+            return Err("'--tui' cannot be combined with a command".to_string());
+        }
+        Ok(config)
+    }
+}
+
+fn main() {
+    let config = Config::from_args().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    });
+    let history_file = store::history_path(config.store, config.path.as_deref());
+    let mut store = store::open(config.store, config.path).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to open task store: {}", e);
+        process::exit(1);
     });
 
-    println!("Welcome to the Stateful Task Manager!");
-    print_help();
+    let mut manager = match TaskManager::load(store.as_mut()) {
+        Ok(manager) => manager,
+        Err(e) if e.is_corrupt() && config.recover => {
+            let (mut manager, backup) = TaskManager::recover(store.as_mut()).unwrap_or_else(|e| {
+                eprintln!("Error: Recovery failed: {}", e);
+                process::exit(1);
+            });
+            println!(
+                "Recovered {} task(s). The unreadable data was moved to {}.",
+                manager.tasks.len(),
+                backup.display()
+            );
+            if let Err(e) = manager.save(store.as_mut()) {
+                eprintln!("Error: Failed to save recovered tasks: {}", e);
+                process::exit(1);
+            }
+            manager
+        }
+        Err(e) => {
+            eprintln!("Error: Failed to load tasks: {}", e);
+            if e.is_corrupt() {
+                eprintln!(
+                    "Nothing was changed. Run with --recover to back up the data and salvage every readable task."
+                );
+            }
+            process::exit(1);
+        }
+    };
+    manager.clock = config.clock;
+    if !config.command.is_empty() {
+        process::exit(run_once(manager, store.as_mut(), &config.command));
+    }
+    let task_manager = Arc::new(Mutex::new(manager));
+    let store = Arc::new(Mutex::new(store));
+
+    let autosaver = Arc::new(Autosaver::spawn(
+        Arc::clone(&task_manager),
+        Arc::clone(&store),
+        config.autosave_interval,
+    ));
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let signal_saver = Arc::clone(&autosaver);
+    let signal_flag = Arc::clone(&interrupted);
+    let (tui, serving) = (config.tui, config.serve.is_some());
+    ctrlc::set_handler(move || {
+        if serving {
+            println!("\nSaving tasks and exiting...");
+            if let Err(e) = signal_saver.shutdown() {
+                eprintln!("Error: Failed to save tasks on exit: {}", e);
+                process::exit(1);
+            }
+            println!("Goodbye!");
+            process::exit(130);
+        }
+        // The terminal UI and the line editor have to put the terminal back
+        // before exiting, so they are only asked to stop and the shutdown
+        // below does the rest.
+        if signal_flag.swap(true, Ordering::SeqCst) || tui {
+            return;
+        }
+        // Keep nudging the editor until the process exits, in case the first
+        // signal lands while a command runs rather than during a read.
+        loop {
+            editor::interrupt();
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    })
+    .expect("Failed to install signal handler.");
+
+    let mut status = 0;
+    if let Some(address) = &config.serve {
+        if let Err(e) = server::serve(address, &task_manager, &store, &autosaver) {
+            eprintln!("Error: Failed to serve on {}: {}", address, e);
+            status = 1;
+        }
+    } else if config.tui {
+        if let Err(e) = tui::run(&task_manager, &store, &autosaver, &interrupted) {
+            eprintln!("Error: The terminal UI failed: {}", e);
+            status = 1;
+        }
+    } else {
+        repl(
+            &task_manager,
+            &store,
+            &autosaver,
+            config.clock,
+            history_file,
+            &interrupted,
+        );
+    }
+    if status == 0 && interrupted.load(Ordering::SeqCst) {
+        status = 130;
+    }
+
+    println!("Saving tasks and exiting...");
+    autosaver.shutdown().expect("Failed to save tasks on exit.");
+    println!("Goodbye!");
+    process::exit(status);
+}
 
-    let stdin = io::stdin();
-    let mut handle = stdin.lock();
+/// Reads commands from the line editor until `exit`, `quit`, end of input or
+/// a signal sets `interrupted`.
+fn repl(
+    manager: &Arc<Mutex<TaskManager>>,
+    store: &SharedStore,
+    autosaver: &Autosaver,
+    clock: Clock,
+    history_file: Option<PathBuf>,
+    interrupted: &AtomicBool,
+) {
+    println!("Welcome to the Stateful Task Manager!");
+    println!("\n{}\n", HELP);
 
+    let mut editor = editor::line_editor(Arc::clone(manager)).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to start the line editor: {}", e);
+        process::exit(1);
+    });
+    if let Some(path) = &history_file
+        && path.exists()
+        && let Err(e) = editor.load_history(path)
+    {
+        eprintln!("Warning: Failed to load command history: {}", e);
+    }
+    let mut reminded_until = None;
 
-    loop {
-        print!("> ");
-        io::stdout().flush().unwrap();
+    while !interrupted.load(Ordering::SeqCst) {
+        let now = clock.now();
+        let indicator = {
+            let manager = manager.lock().unwrap();
+            for task in manager.reminders(reminded_until, now) {
+                println!("Reminder: {}", task);
+            }
+            manager.timer_indicator(now).unwrap_or_default()
+        };
+        reminded_until = Some(now);
 
-        let mut buffer = String::new();
-        match handle.read_line(&mut buffer) {
-            Ok(0) => break,
-            Ok(_) => {
-                let input = buffer.trim();
+        match editor.readline(&format!("{}> ", indicator)) {
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Ok(line) => {
+                let input = line.trim();
                 if input.eq_ignore_ascii_case("exit") || input.eq_ignore_ascii_case("quit") {
                     break;
                 }
 
-                match Command::try_from(input.to_string()) {
+                match Command::parse(input, clock) {
                     Ok(command) => {
-                        let mut manager = task_manager.lock().unwrap();
-                        match command {
-                            Command::Add(desc) => manager.add_task(desc),
-                            Command::Complete(id) => manager.complete_task(id),
-                            Command::List => manager.list_tasks(),
-                            Command::Help => print_help(),
+                        let mut manager = manager.lock().unwrap();
+                        let mut store = store.lock().unwrap();
+                        match run_command(&mut manager, store.as_mut(), command) {
+                            Ok((output, changed)) => {
+                                println!("{}", output);
+                                if changed {
+                                    autosaver.notify();
+                                }
+                            }
+                            Err(e) => eprintln!("Error: {}", e),
                         }
                     }
-                    Err(e) => eprintln!("Error: {}", e),
+                    Err(e) => print_error(input, &e),
                 }
             }
             Err(error) => {
@@ -241,7 +1438,211 @@ fn main() {
         }
     }
 
-    println!("Saving tasks and exiting...");
-    task_manager.lock().unwrap().save().expect("Failed to save tasks on exit.");
-    println!("Goodbye!");
-}
\ No newline at end of file
+    if let Some(path) = &history_file
+        && let Err(e) = editor.append_history(path)
+    {
+        eprintln!("Warning: Failed to save command history: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    pub fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 14)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap()
+    }
+
+    /// An empty manager whose clock stands still at `now()`.
+    pub fn manager() -> TaskManager {
+        TaskManager {
+            clock: Clock::Fixed(now()),
+            ..TaskManager::new()
+        }
+    }
+
+    /// Parses and runs one line the way the REPL does.
+    pub fn run(
+        manager: &mut TaskManager,
+        store: &mut dyn TaskStore,
+        line: &str,
+    ) -> Result<String, CommandError> {
+        let command = Command::parse(line, manager.clock)?;
+        run_command(manager, store, command).map(|(output, _)| output)
+    }
+
+    /// Task descriptions in id order.
+    pub fn descriptions(manager: &TaskManager) -> Vec<&str> {
+        let mut tasks: Vec<&AnyTask> = manager.tasks.iter().collect();
+        tasks.sort_by_key(|task| task.id());
+        tasks
+            .iter()
+            .map(|task| task.details().description.as_str())
+            .collect()
+    }
+
+    pub fn state(manager: &TaskManager, id: u32) -> &'static str {
+        manager.tasks[manager.position(id).unwrap()].state()
+    }
+
+    /// A fresh directory for one test's files.
+    pub fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("task_manager-{}-{}", name, process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn the_memory_store_round_trips_a_save() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        assert!(matches!(store.load(), Err(LoadError::Missing)));
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        run(&mut manager, &mut store, "add walk dog").unwrap();
+        manager.save(&mut store).unwrap();
+        assert!(!manager.is_dirty());
+
+        let loaded = TaskManager::load(&mut store).unwrap();
+        assert_eq!(descriptions(&loaded), ["buy milk", "walk dog"]);
+        assert_eq!((loaded.next_id, loaded.revision), (3, 2));
+        assert!(!loaded.is_dirty());
+    }
+
+    #[test]
+    fn a_failed_command_leaves_the_manager_as_it_was() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        run(&mut manager, &mut store, "add walk dog").unwrap();
+        let before = (manager.tasks.clone(), manager.history.clone());
+        let audit = manager.audit.len();
+
+        assert!(run(&mut manager, &mut store, "complete 1,2,9").is_err());
+        assert!(run(&mut manager, &mut store, "parent 1 1").is_err());
+        assert_eq!((manager.tasks.clone(), manager.history.clone()), before);
+        assert_eq!((manager.next_id, manager.revision), (3, 2));
+        assert_eq!(manager.audit.len(), audit);
+        assert_eq!(manager.index.search("milk")[0].0, 1);
+    }
+
+    #[test]
+    fn failed_commands_are_not_journaled() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        assert!(run(&mut manager, &mut store, "complete 9").is_err());
+        assert!(run(&mut manager, &mut store, "undo").is_ok());
+        assert!(run(&mut manager, &mut store, "undo").is_err());
+        run(&mut manager, &mut store, "add walk dog").unwrap();
+
+        let revisions: Vec<u64> = store
+            .journal()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.revision)
+            .collect();
+        assert_eq!(revisions, [1, 2, 3]);
+        assert_eq!(manager.revision, 3);
+    }
+
+    #[test]
+    fn load_replays_the_journal_over_the_snapshot() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        manager.save(&mut store).unwrap();
+        run(&mut manager, &mut store, "add walk dog").unwrap();
+        run(&mut manager, &mut store, "complete 1").unwrap();
+
+        let loaded = TaskManager::load(&mut store).unwrap();
+        assert_eq!(descriptions(&loaded), ["buy milk", "walk dog"]);
+        assert_eq!(state(&loaded, 1), "completed");
+        assert_eq!(loaded.revision, 3);
+        assert!(loaded.is_dirty());
+    }
+
+    #[test]
+    fn replay_skips_entries_that_no_longer_apply() {
+        let entry = |revision, line| JournalEntry {
+            revision,
+            command: Command::parse(line, Clock::Fixed(now())).unwrap(),
+            stamp: None,
+        };
+        let mut manager = manager();
+        manager.replay(vec![
+            entry(1, "add buy milk"),
+            entry(2, "complete 7"),
+            entry(3, "add walk dog"),
+            entry(4, "complete 1"),
+        ]);
+        assert_eq!(descriptions(&manager), ["buy milk", "walk dog"]);
+        assert_eq!(state(&manager, 1), "completed");
+        assert_eq!(manager.revision, 3);
+    }
+
+    #[test]
+    fn unknown_ids_are_reported() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        for line in ["delete 4", "edit 4 walk dog", "reopen 4"] {
+            let error = run(&mut manager, &mut store, line).unwrap_err();
+            assert!(
+                matches!(error, CommandError::TaskNotFound(4)),
+                "{}: {:?}",
+                line,
+                error
+            );
+        }
+        run(&mut manager, &mut store, "delete 1").unwrap();
+        assert!(manager.tasks.is_empty());
+    }
+
+    #[test]
+    fn inline_tokens_set_details() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        let line = "add pay rent +home +bills +home !high due:friday every:month";
+        run(&mut manager, &mut store, line).unwrap();
+        let details = manager.tasks[0].details();
+        assert_eq!(details.description, "pay rent");
+        assert_eq!(details.tags, ["home", "bills"]);
+        assert_eq!(details.priority, Some(Priority::High));
+        assert_eq!(details.due, NaiveDate::from_ymd_opt(2026, 10, 16));
+        assert!(details.recurrence.is_some());
+    }
+
+    #[test]
+    fn malformed_tokens_point_at_their_column() {
+        let mut manager = manager();
+        let mut store = MemoryStore::default();
+        for (line, column) in [
+            ("add pay rent !urgent", 14),
+            ("add pay rent due:someday", 14),
+            ("add pay rent every:fortnightly", 14),
+            ("add pay rent parent:x", 14),
+        ] {
+            match run(&mut manager, &mut store, line) {
+                Err(CommandError::Syntax { column: got, .. }) => {
+                    assert_eq!(got, column, "for {}", line)
+                }
+                other => panic!("{} gave {:?}", line, other),
+            }
+        }
+        assert!(manager.tasks.is_empty());
+    }
+
+    #[test]
+    fn quoted_tokens_are_plain_text() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(
+            &mut manager,
+            &mut store,
+            r#"add email "+alice" about "due:friday" '!high'"#,
+        )
+        .unwrap();
+        let details = manager.tasks[0].details();
+        assert_eq!(details.description, "email +alice about due:friday !high");
+        assert!(details.tags.is_empty());
+        assert_eq!((details.due, details.priority), (None, None));
+    }
+}
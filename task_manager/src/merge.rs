@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::TaskManager;
+use crate::audit::Event;
+use crate::search::SearchIndex;
+use crate::task::AnyTask;
+
+impl TaskManager {
+    /// Three-way merges a snapshot another session saved (`theirs`) into this
+    /// one, where `base` is the snapshot both sides started from.
+    ///
+    /// A task changed on only one side takes that side's version; a task
+    /// changed on both keeps ours. Deleting a task loses to changing it. Tasks
+    /// both sides added under the same id keep theirs and ours is given a
+    /// fresh id, which our tasks, undo history and audit events then refer
+    /// to instead. Returns whether that happened, since commands journaled
+    /// before the merge still name the old ids.
+    pub fn merge(&mut self, base: &TaskManager, theirs: TaskManager) -> bool {
+        let base_tasks: HashMap<u32, &AnyTask> = base.tasks.iter().map(|t| (t.id(), t)).collect();
+        let mut their_tasks: HashMap<u32, AnyTask> =
+            theirs.tasks.iter().map(|t| (t.id(), t.clone())).collect();
+
+        let mut merged = Vec::with_capacity(self.tasks.len().max(theirs.tasks.len()));
+        // Ids of the merged tasks that are our version.
+        let mut mine = HashSet::new();
+        let mut renumber = Vec::new();
+        for ours in self.tasks.drain(..) {
+            let id = ours.id();
+            match (base_tasks.get(&id), their_tasks.remove(&id)) {
+                (Some(base), Some(theirs)) => {
+                    if ours == **base {
+                        merged.push(theirs);
+                    } else {
+                        mine.insert(id);
+                        merged.push(ours);
+                    }
+                }
+                // They deleted it; keep it only if we changed it since.
+                (Some(base), None) => {
+                    if ours != **base {
+                        mine.insert(id);
+                        merged.push(ours);
+                    }
+                }
+                (None, Some(theirs)) => {
+                    if ours != theirs {
+                        renumber.push(ours);
+                    }
+                    merged.push(theirs);
+                }
+                (None, None) => {
+                    mine.insert(id);
+                    merged.push(ours);
+                }
+            }
+        }
+
+        // Whatever is left only exists on their side.
+        for theirs in theirs.tasks {
+            let id = theirs.id();
+            if !their_tasks.contains_key(&id) {
+                continue;
+            }
+            match base_tasks.get(&id) {
+                // We deleted it; keep it only if they changed it since.
+                Some(base) if theirs == **base => {}
+                _ => merged.push(theirs),
+            }
+        }
+
+        let max_id = merged.iter().map(AnyTask::id).max().unwrap_or(0);
+        self.next_id = self.next_id.max(theirs.next_id).max(max_id + 1);
+        let mut renumbered = HashMap::new();
+        for mut task in renumber {
+            renumbered.insert(task.id(), self.next_id);
+            task.set_id(self.next_id);
+            mine.insert(self.next_id);
+            self.next_id += 1;
+            merged.push(task);
+        }
+        if !renumbered.is_empty() {
+            let new_id = |id: u32| renumbered.get(&id).copied().unwrap_or(id);
+            for task in merged.iter_mut().filter(|t| mine.contains(&t.id())) {
+                renumber_links(task, new_id);
+            }
+            self.history.renumber(|task| {
+                task.set_id(new_id(task.id()));
+                renumber_links(task, new_id);
+            });
+            for event in &mut self.audit {
+                event.task = new_id(event.task);
+            }
+        }
+
+        self.tasks = merged;
+        self.revision = self.revision.max(theirs.revision);
+        // The audit log only grows, so keep every event either side logged.
+        let logged: HashSet<&Event> = self.audit.iter().collect();
+        let missing: Vec<Event> = theirs
+            .audit
+            .into_iter()
+            .filter(|event| !logged.contains(event))
+            .collect();
+        self.audit.extend(missing);
+        self.audit.sort_by_key(|event| event.stamp.at);
+        self.index = SearchIndex::build(&self.tasks);
+        !renumbered.is_empty()
+    }
+}
+
+/// Points `task`'s parent and blockers at the ids `new_id` gives them.
+fn renumber_links(task: &mut AnyTask, new_id: impl Fn(u32) -> u32) {
+    let details = task.details_mut();
+    details.parent = details.parent.map(&new_id);
+    for blocker in &mut details.blocked_by {
+        *blocker = new_id(*blocker);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::TaskManager;
+    use crate::store::MemoryStore;
+    use crate::tests::{descriptions, manager, run};
+
+    fn events_for(manager: &TaskManager, id: u32) -> usize {
+        manager
+            .audit
+            .iter()
+            .filter(|event| event.task == id)
+            .count()
+    }
+
+    #[test]
+    fn changes_on_either_side_are_kept() {
+        let mut store = MemoryStore::default();
+        let mut base = manager();
+        run(&mut base, &mut store, "add buy milk").unwrap();
+        run(&mut base, &mut store, "add walk dog").unwrap();
+        let mut ours = base.clone();
+        let mut theirs = base.clone();
+        run(&mut ours, &mut store, "edit 1 buy oat milk").unwrap();
+        run(&mut theirs, &mut store, "complete 2").unwrap();
+
+        ours.merge(&base, theirs);
+        assert_eq!(descriptions(&ours), ["buy oat milk", "walk dog"]);
+        assert!(ours.tasks[1].is_finished());
+        assert_eq!(events_for(&ours, 1), 2);
+        assert_eq!(events_for(&ours, 2), 2);
+    }
+
+    #[test]
+    fn a_task_deleted_on_one_side_and_changed_on_the_other_survives() {
+        let mut store = MemoryStore::default();
+        let mut base = manager();
+        run(&mut base, &mut store, "add buy milk").unwrap();
+        let mut ours = base.clone();
+        let mut theirs = base.clone();
+        run(&mut ours, &mut store, "delete 1").unwrap();
+        run(&mut theirs, &mut store, "edit 1 !high").unwrap();
+
+        ours.merge(&base, theirs);
+        assert_eq!(descriptions(&ours), ["buy milk"]);
+    }
+
+    #[test]
+    fn tasks_added_under_the_same_id_keep_their_links() {
+        let mut store = MemoryStore::default();
+        let mut base = manager();
+        run(&mut base, &mut store, "add buy milk").unwrap();
+        let mut ours = base.clone();
+        let mut theirs = base.clone();
+        run(&mut ours, &mut store, "add plan trip").unwrap();
+        run(&mut ours, &mut store, "add book hotel parent:2").unwrap();
+        run(&mut ours, &mut store, "add pack bags").unwrap();
+        run(&mut ours, &mut store, "depend 4 2").unwrap();
+        run(&mut theirs, &mut store, "add call mum").unwrap();
+        let (our_events, their_events) = (events_for(&ours, 2), events_for(&theirs, 2));
+
+        ours.merge(&base, theirs);
+        assert_eq!(
+            descriptions(&ours),
+            [
+                "buy milk",
+                "call mum",
+                "book hotel",
+                "pack bags",
+                "plan trip"
+            ]
+        );
+        let details = |id| ours.tasks[ours.position(id).unwrap()].details();
+        assert_eq!(details(3).parent, Some(5));
+        assert_eq!(details(4).blocked_by, [5]);
+        assert_eq!(ours.next_id, 6);
+        assert_eq!(events_for(&ours, 5), our_events);
+        assert_eq!(events_for(&ours, 2), their_events);
+        assert_eq!(events_for(&ours, 1), 1);
+    }
+
+    #[test]
+    fn undo_follows_renumbered_tasks() {
+        let mut store = MemoryStore::default();
+        let mut base = manager();
+        run(&mut base, &mut store, "add buy milk").unwrap();
+        let mut ours = base.clone();
+        let mut theirs = base.clone();
+        run(&mut ours, &mut store, "add plan trip").unwrap();
+        run(&mut ours, &mut store, "add book hotel parent:2").unwrap();
+        run(&mut theirs, &mut store, "add call mum").unwrap();
+
+        assert!(ours.merge(&base, theirs));
+        run(&mut ours, &mut store, "undo").unwrap();
+        assert_eq!(descriptions(&ours), ["buy milk", "call mum", "plan trip"]);
+        run(&mut ours, &mut store, "undo").unwrap();
+        assert_eq!(descriptions(&ours), ["buy milk", "call mum"]);
+        run(&mut ours, &mut store, "redo").unwrap();
+        run(&mut ours, &mut store, "redo").unwrap();
+        let hotel = &ours.tasks[ours.position(3).unwrap()];
+        assert_eq!(hotel.details().parent, Some(4));
+    }
+}
@@ -0,0 +1,280 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+
+use crate::task::AnyTask;
+
+/// How `list` and `report` print their results. Everything but `Table` is
+/// meant for other programs and keeps its field names stable.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Format {
+    #[default]
+    Table,
+    Json,
+    Csv,
+    Tsv,
+}
+
+impl TryFrom<&str> for Format {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!(
+                "Unknown format '{}', expected json, csv, tsv or table",
+                value
+            )),
+        }
+    }
+}
+
+/// A row of machine-readable output.
+pub trait Record: Serialize {
+    const FIELDS: &'static [&'static str];
+
+    /// The values of `FIELDS`, in order, as CSV or TSV cells.
+    fn cells(&self) -> Vec<String>;
+}
+
+/// `records` as a JSON array or a CSV or TSV table with a header row.
+pub fn render<R: Record>(records: &[R], format: Format) -> String {
+    match format {
+        Format::Json => serde_json::to_string_pretty(records).expect("records serialize"),
+        Format::Csv | Format::Tsv => {
+            let (separator, escape): (&str, fn(&str) -> String) = match format {
+                Format::Csv => (",", csv_cell),
+                _ => ("\t", tsv_cell),
+            };
+            let mut lines = vec![R::FIELDS.join(separator)];
+            for record in records {
+                let cells: Vec<String> = record.cells().iter().map(|c| escape(c)).collect();
+                lines.push(cells.join(separator));
+            }
+            lines.join("\n")
+        }
+        Format::Table => unreachable!("tables are printed by their commands"),
+    }
+}
+
+/// Quotes a cell when RFC 4180 requires it.
+fn csv_cell(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// TSV cells cannot hold tabs or line breaks, so those are backslash-escaped.
+fn tsv_cell(cell: &str) -> String {
+    cell.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn timestamp(value: Option<NaiveDateTime>) -> String {
+    optional(value.map(|at| at.format("%Y-%m-%dT%H:%M:%S")))
+}
+
+/// A task as scripts see it. Lists are joined with spaces (tags) or commas
+/// (ids) in CSV and TSV.
+#[derive(Debug, Serialize)]
+pub struct TaskRecord {
+    pub id: u32,
+    /// As accepted by `state:` in list queries, e.g. `in-progress`.
+    pub state: String,
+    pub description: String,
+    pub priority: Option<&'static str>,
+    pub due: Option<NaiveDate>,
+    pub tags: Vec<String>,
+    pub notes: String,
+    pub parent: Option<u32>,
+    pub blocked_by: Vec<u32>,
+    pub recurrence: Option<String>,
+    pub remind: Option<NaiveDateTime>,
+    pub logged_seconds: u64,
+    pub timer_since: Option<NaiveDateTime>,
+    pub created: Option<NaiveDateTime>,
+    pub updated: Option<NaiveDateTime>,
+    pub completed: Option<NaiveDateTime>,
+}
+
+impl From<&AnyTask> for TaskRecord {
+    fn from(task: &AnyTask) -> Self {
+        let details = task.details();
+        let stamps = task.stamps();
+        TaskRecord {
+            id: task.id(),
+            state: task.state().replace(' ', "-"),
+            description: details.description.clone(),
+            priority: details.priority.map(|p| p.name()),
+            due: details.due,
+            tags: details.tags.clone(),
+            notes: details.notes.clone(),
+            parent: details.parent,
+            blocked_by: details.blocked_by.clone(),
+            recurrence: details.recurrence.as_ref().map(ToString::to_string),
+            remind: details.remind,
+            logged_seconds: details.logged_seconds(),
+            timer_since: details.timer_since,
+            created: stamps.created,
+            updated: stamps.updated,
+            completed: stamps.completed,
+        }
+    }
+}
+
+impl Record for TaskRecord {
+    const FIELDS: &'static [&'static str] = &[
+        "id",
+        "state",
+        "description",
+        "priority",
+        "due",
+        "tags",
+        "notes",
+        "parent",
+        "blocked_by",
+        "recurrence",
+        "remind",
+        "logged_seconds",
+        "timer_since",
+        "created",
+        "updated",
+        "completed",
+    ];
+
+    fn cells(&self) -> Vec<String> {
+        let blocked_by: Vec<String> = self.blocked_by.iter().map(u32::to_string).collect();
+        vec![
+            self.id.to_string(),
+            self.state.clone(),
+            self.description.clone(),
+            optional(self.priority),
+            optional(self.due),
+            self.tags.join(" "),
+            self.notes.clone(),
+            optional(self.parent),
+            blocked_by.join(","),
+            optional(self.recurrence.as_ref()),
+            timestamp(self.remind),
+            self.logged_seconds.to_string(),
+            timestamp(self.timer_since),
+            timestamp(self.created),
+            timestamp(self.updated),
+            timestamp(self.completed),
+        ]
+    }
+}
+
+/// Time logged in one period, for one task or one tag.
+#[derive(Debug, Serialize)]
+pub struct ReportRecord {
+    /// `YYYY-MM-DD` for a day, `YYYY-Www` for an ISO week.
+    pub period: String,
+    pub task: Option<u32>,
+    pub description: Option<String>,
+    /// Set when grouping by tag, except for time on untagged tasks.
+    pub tag: Option<String>,
+    pub seconds: u64,
+}
+
+impl Record for ReportRecord {
+    const FIELDS: &'static [&'static str] = &["period", "task", "description", "tag", "seconds"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.period.clone(),
+            optional(self.task),
+            optional(self.description.as_ref()),
+            optional(self.tag.as_ref()),
+            self.seconds.to_string(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::tests::{manager, run};
+
+    #[test]
+    fn csv_quotes_only_cells_that_need_it() {
+        assert_eq!(csv_cell("buy milk"), "buy milk");
+        assert_eq!(csv_cell("milk, eggs"), "\"milk, eggs\"");
+        assert_eq!(csv_cell("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_cell("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_cell("a\tb"), "a\tb");
+    }
+
+    #[test]
+    fn tsv_escapes_tabs_newlines_and_backslashes() {
+        assert_eq!(tsv_cell("a\tb\nc\r"), "a\\tb\\nc\\r");
+        assert_eq!(tsv_cell("C:\\tmp"), "C:\\\\tmp");
+        assert_eq!(tsv_cell("milk, \"eggs\""), "milk, \"eggs\"");
+    }
+
+    #[test]
+    fn rows_follow_the_header() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy milk +shop +dairy").unwrap();
+        run(&mut manager, &mut store, "add call mum").unwrap();
+        run(&mut manager, &mut store, "depend 2 1").unwrap();
+        manager.tasks[0].details_mut().description = "milk, \"eggs\"\tbread".to_string();
+        let records: Vec<TaskRecord> = manager.tasks.iter().map(TaskRecord::from).collect();
+
+        let csv = render(&records, Format::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], TaskRecord::FIELDS.join(","));
+        assert!(
+            lines[1].starts_with("1,pending,\"milk, \"\"eggs\"\"\tbread\",,,shop dairy,"),
+            "{}",
+            lines[1]
+        );
+        assert!(lines[2].starts_with("2,pending,call mum,,,,,,1,"));
+
+        let tsv = render(&records, Format::Tsv);
+        let row: Vec<&str> = tsv.lines().nth(1).unwrap().split('\t').collect();
+        assert_eq!(row.len(), TaskRecord::FIELDS.len());
+        assert_eq!(row[2], "milk, \"eggs\"\\tbread");
+        assert_eq!(row[13], "2026-10-14T09:00:00");
+    }
+
+    #[test]
+    fn json_keeps_field_names_and_types() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(
+            &mut manager,
+            &mut store,
+            "add buy milk +shop !high due:2026-10-20",
+        )
+        .unwrap();
+        run(&mut manager, &mut store, "start 1").unwrap();
+        let records: Vec<TaskRecord> = manager.tasks.iter().map(TaskRecord::from).collect();
+
+        let json: Value = serde_json::from_str(&render(&records, Format::Json)).unwrap();
+        let task = json[0].as_object().unwrap();
+        let keys: Vec<&str> = task.keys().map(String::as_str).collect();
+        assert_eq!(keys, TaskRecord::FIELDS);
+        assert_eq!(task["id"], 1);
+        assert_eq!(task["state"], "in-progress");
+        assert_eq!(task["priority"], "high");
+        assert_eq!(task["due"], "2026-10-20");
+        assert_eq!(task["tags"], serde_json::json!(["shop"]));
+        assert_eq!(task["parent"], Value::Null);
+        assert_eq!(task["blocked_by"], serde_json::json!([]));
+        assert_eq!(task["logged_seconds"], 0);
+        assert_eq!(task["created"], "2026-10-14T09:00:00");
+    }
+}
@@ -0,0 +1,510 @@
+use std::cmp::Ordering;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use regex::{Regex, RegexBuilder};
+
+use crate::dates;
+use crate::task::{AnyTask, Priority};
+
+/// A parsed `list` argument: an optional filter plus `sort:` and `limit:`
+/// clauses, which may appear anywhere at the top level. Dates can also be
+/// single words relative to now, as in `due<friday`.
+///
+/// ```text
+/// list state:pending and (+work or !high) due<2026-11-01 sort:-priority,due limit:10
+/// list not tag:home "grocery list" or /^fix(es)? /
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    pub filter: Option<Filter>,
+    pub sort: Vec<SortKey>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Filter {
+    /// Matches `AnyTask::state()`.
+    State(&'static str),
+    Tag(String),
+    Priority(Option<Priority>),
+    Due(Option<NaiveDate>),
+    DueBefore(NaiveDate),
+    DueAfter(NaiveDate),
+    /// Case-insensitive substring of the description.
+    Text(String),
+    Regex(Regex),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortField {
+    Id,
+    State,
+    Priority,
+    Due,
+    Description,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub reverse: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word(String),
+    Quoted(String),
+    Regex(String),
+}
+
+impl Query {
+    pub fn parse(input: &str, now: NaiveDateTime) -> Result<Self, String> {
+        let mut query = Query::default();
+        let mut tokens = Vec::new();
+        for token in tokenize(input)? {
+            match &token {
+                Token::Word(word) if word.starts_with("sort:") => {
+                    query.sort = parse_sort(&word["sort:".len()..])?;
+                }
+                Token::Word(word) if word.starts_with("limit:") => {
+                    let limit = &word["limit:".len()..];
+                    query.limit = Some(
+                        limit
+                            .parse()
+                            .map_err(|_| format!("Invalid limit '{}'", limit))?,
+                    );
+                }
+                _ => tokens.push(token),
+            }
+        }
+        if !tokens.is_empty() {
+            let mut parser = Parser {
+                tokens,
+                pos: 0,
+                now,
+            };
+            let filter = parser.or()?;
+            if let Some(token) = parser.tokens.get(parser.pos) {
+                return Err(format!("Unexpected {}", describe(token)));
+            }
+            query.filter = Some(filter);
+        }
+        Ok(query)
+    }
+
+    /// Archived tasks stay out of the way unless the filter asks for them.
+    pub fn matches(&self, task: &AnyTask) -> bool {
+        match &self.filter {
+            Some(filter) if matches!(task, AnyTask::Archived(_)) => {
+                filter.mentions_archived() && filter.matches(task)
+            }
+            Some(filter) => filter.matches(task),
+            None => !matches!(task, AnyTask::Archived(_)),
+        }
+    }
+
+    /// The tasks this query selects, sorted and limited.
+    pub fn select<'a>(&self, tasks: &'a [AnyTask]) -> Vec<&'a AnyTask> {
+        let mut selected: Vec<&AnyTask> = tasks.iter().filter(|t| self.matches(t)).collect();
+        if !self.sort.is_empty() {
+            selected.sort_by(|a, b| {
+                self.sort
+                    .iter()
+                    .map(|key| key.compare(a, b))
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+        if let Some(limit) = self.limit {
+            selected.truncate(limit);
+        }
+        selected
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, task: &AnyTask) -> bool {
+        let details = task.details();
+        match self {
+            Filter::State(state) => task.state() == *state,
+            Filter::Tag(tag) => details.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)),
+            Filter::Priority(priority) => details.priority == *priority,
+            Filter::Due(due) => details.due == *due,
+            Filter::DueBefore(date) => details.due.is_some_and(|due| due < *date),
+            Filter::DueAfter(date) => details.due.is_some_and(|due| due > *date),
+            Filter::Text(text) => details
+                .description
+                .to_lowercase()
+                .contains(&text.to_lowercase()),
+            Filter::Regex(regex) => regex.is_match(&details.description),
+            Filter::And(a, b) => a.matches(task) && b.matches(task),
+            Filter::Or(a, b) => a.matches(task) || b.matches(task),
+            Filter::Not(a) => !a.matches(task),
+        }
+    }
+
+    /// Whether this only looks for words in the description.
+    pub fn is_plain_text(&self) -> bool {
+        match self {
+            Filter::Text(_) => true,
+            Filter::And(a, b) | Filter::Or(a, b) => a.is_plain_text() && b.is_plain_text(),
+            Filter::Not(a) => a.is_plain_text(),
+            _ => false,
+        }
+    }
+
+    fn mentions_archived(&self) -> bool {
+        match self {
+            Filter::State(state) => *state == "archived",
+            Filter::And(a, b) | Filter::Or(a, b) => a.mentions_archived() || b.mentions_archived(),
+            Filter::Not(a) => a.mentions_archived(),
+            _ => false,
+        }
+    }
+}
+
+impl SortKey {
+    fn compare(&self, a: &AnyTask, b: &AnyTask) -> Ordering {
+        let (da, db) = (a.details(), b.details());
+        let ordering = match self.field {
+            SortField::Id => a.id().cmp(&b.id()),
+            SortField::State => state_rank(a).cmp(&state_rank(b)),
+            // Highest first, unprioritized last.
+            SortField::Priority => db.priority.cmp(&da.priority),
+            // Soonest first, undated last.
+            SortField::Due => match (da.due, db.due) {
+                (Some(x), Some(y)) => x.cmp(&y),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+            SortField::Description => da
+                .description
+                .to_lowercase()
+                .cmp(&db.description.to_lowercase()),
+        };
+        if self.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+fn state_rank(task: &AnyTask) -> u8 {
+    match task {
+        AnyTask::InProgress(_) => 0,
+        AnyTask::Pending(_) => 1,
+        AnyTask::Blocked(_) => 2,
+        AnyTask::Completed(_) => 3,
+        AnyTask::Cancelled(_) => 4,
+        AnyTask::Archived(_) => 5,
+    }
+}
+
+fn parse_sort(spec: &str) -> Result<Vec<SortKey>, String> {
+    spec.split(',')
+        .map(|key| {
+            let (reverse, name) = match key.strip_prefix('-') {
+                Some(name) => (true, name),
+                None => (false, key),
+            };
+            let field = match name.to_lowercase().as_str() {
+                "id" => SortField::Id,
+                "state" => SortField::State,
+                "priority" => SortField::Priority,
+                "due" => SortField::Due,
+                "description" | "text" => SortField::Description,
+                _ => return Err(format!("Unknown sort field '{}'", name)),
+            };
+            Ok(SortKey { field, reverse })
+        })
+        .collect()
+}
+
+fn parse_state(name: &str) -> Result<&'static str, String> {
+    match name.to_lowercase().as_str() {
+        "pending" | "todo" => Ok("pending"),
+        "in-progress" | "inprogress" | "started" => Ok("in progress"),
+        "blocked" => Ok("blocked"),
+        "completed" | "done" => Ok("completed"),
+        "cancelled" | "canceled" => Ok("cancelled"),
+        "archived" => Ok("archived"),
+        _ => Err(format!("Unknown state '{}'", name)),
+    }
+}
+
+fn parse_date(text: &str, now: NaiveDateTime) -> Result<NaiveDate, String> {
+    dates::parse(text, now)
+        .map(|when| when.date)
+        .ok_or_else(|| format!("Invalid date '{}', expected YYYY-MM-DD", text))
+}
+
+fn parse_priority(text: &str) -> Result<Option<Priority>, String> {
+    if text.eq_ignore_ascii_case("none") {
+        Ok(None)
+    } else {
+        Priority::try_from(text).map(Some)
+    }
+}
+
+/// Turns one unquoted word into a filter term.
+fn term(word: &str, now: NaiveDateTime) -> Result<Filter, String> {
+    if let Some(tag) = word.strip_prefix('+').filter(|tag| !tag.is_empty()) {
+        return Ok(Filter::Tag(tag.to_string()));
+    }
+    if let Some(priority) = word.strip_prefix('!').filter(|p| !p.is_empty()) {
+        return Ok(Filter::Priority(parse_priority(priority)?));
+    }
+    if let Some(date) = word.strip_prefix("due<") {
+        return Ok(Filter::DueBefore(parse_date(date, now)?));
+    }
+    if let Some(date) = word.strip_prefix("due>") {
+        return Ok(Filter::DueAfter(parse_date(date, now)?));
+    }
+    let Some((key, value)) = word.split_once(':') else {
+        return Ok(Filter::Text(word.to_string()));
+    };
+    match key.to_lowercase().as_str() {
+        "state" | "is" => Ok(Filter::State(parse_state(value)?)),
+        "tag" => Ok(Filter::Tag(value.to_string())),
+        "priority" => Ok(Filter::Priority(parse_priority(value)?)),
+        "due" if value.eq_ignore_ascii_case("none") => Ok(Filter::Due(None)),
+        "due" => Ok(Filter::Due(Some(parse_date(value, now)?))),
+        "before" => Ok(Filter::DueBefore(parse_date(value, now)?)),
+        "after" => Ok(Filter::DueAfter(parse_date(value, now)?)),
+        _ => Ok(Filter::Text(word.to_string())),
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' | '/' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') if chars.peek() == Some(&c) => text.push(chars.next().unwrap()),
+                        Some(next) if next == c => break,
+                        Some(next) => text.push(next),
+                        None => return Err(format!("Missing closing {}", c)),
+                    }
+                }
+                tokens.push(if c == '"' {
+                    Token::Quoted(text)
+                } else {
+                    Token::Regex(text)
+                });
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(match word.to_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Open => "'('".to_string(),
+        Token::Close => "')'".to_string(),
+        Token::And => "'and'".to_string(),
+        Token::Or => "'or'".to_string(),
+        Token::Not => "'not'".to_string(),
+        Token::Word(word) => format!("'{}'", word),
+        Token::Quoted(text) => format!("\"{}\"", text),
+        Token::Regex(pattern) => format!("/{}/", pattern),
+    }
+}
+
+/// Recursive descent over `or := and ("or" and)*`, `and := not ("and"? not)*`,
+/// `not := "not" not | "(" or ")" | term`. Adjacent terms are and-ed.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    now: NaiveDateTime,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut filter = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut filter = self.not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.pos += 1,
+                None | Some(Token::Or) | Some(Token::Close) => return Ok(filter),
+                Some(_) => {}
+            }
+            filter = Filter::And(Box::new(filter), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> Result<Filter, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Filter::Not(Box::new(self.not()?))),
+            Some(Token::Open) => {
+                let filter = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(filter),
+                    Some(token) => Err(format!("Expected ')' but found {}", describe(&token))),
+                    None => Err("Missing closing ')'".to_string()),
+                }
+            }
+            Some(Token::Word(word)) => term(&word, self.now),
+            Some(Token::Quoted(text)) => Ok(Filter::Text(text)),
+            Some(Token::Regex(pattern)) => RegexBuilder::new(&pattern)
+                .case_insensitive(true)
+                .build()
+                .map(Filter::Regex)
+                .map_err(|e| format!("Invalid regex /{}/: {}", pattern, e)),
+            Some(token) => Err(format!("Unexpected {}", describe(&token))),
+            None => Err("Expected a filter term".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::tests::{manager, now, run};
+
+    /// The ids `input` lists out of a small set of tasks.
+    fn ids(input: &str) -> Result<Vec<u32>, String> {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        for line in [
+            "add fix the sink +home !high due:2026-10-20",
+            "add write report +work !low due:2026-11-05",
+            "add fixes for the build +work !high",
+            "add grocery list +home",
+            "add old draft",
+            "complete 5",
+            "archive 5",
+        ] {
+            run(&mut manager, &mut store, line).unwrap();
+        }
+        let query = Query::parse(input, now())?;
+        Ok(query
+            .select(&manager.tasks)
+            .iter()
+            .map(|t| t.id())
+            .collect())
+    }
+
+    #[test]
+    fn terms_combine_with_and_or_not() {
+        assert_eq!(ids("+work").unwrap(), [2, 3]);
+        assert_eq!(ids("tag:home and !high").unwrap(), [1]);
+        assert_eq!(ids("+work or !high").unwrap(), [1, 2, 3]);
+        assert_eq!(ids("not +home and not +work").unwrap(), Vec::<u32>::new());
+        assert_eq!(
+            ids("state:pending and (+work or !high)").unwrap(),
+            [1, 2, 3]
+        );
+        assert_eq!(ids("+home +work").unwrap(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn dates_text_and_regexes() {
+        assert_eq!(ids("due<2026-11-01").unwrap(), [1]);
+        assert_eq!(ids("due>2026-10-20").unwrap(), [2]);
+        assert_eq!(ids("due:none").unwrap(), [3, 4]);
+        assert_eq!(ids("\"grocery list\"").unwrap(), [4]);
+        assert_eq!(ids("FIX").unwrap(), [1, 3]);
+        assert_eq!(ids("/^fix(es)? /").unwrap(), [1, 3]);
+    }
+
+    #[test]
+    fn archived_tasks_only_show_when_asked_for() {
+        assert_eq!(ids("").unwrap(), [1, 2, 3, 4]);
+        assert_eq!(ids("draft").unwrap(), Vec::<u32>::new());
+        assert_eq!(ids("state:archived").unwrap(), [5]);
+    }
+
+    #[test]
+    fn sort_and_limit() {
+        assert_eq!(ids("sort:-id").unwrap(), [4, 3, 2, 1]);
+        assert_eq!(ids("sort:priority,id").unwrap(), [1, 3, 2, 4]);
+        assert_eq!(ids("sort:due limit:2").unwrap(), [1, 2]);
+        assert_eq!(ids("sort:text").unwrap(), [1, 3, 4, 2]);
+    }
+
+    #[test]
+    fn malformed_queries_are_reported() {
+        for input in [
+            "(+work",
+            "+work)",
+            "state:sleeping",
+            "sort:colour",
+            "limit:many",
+            "due<someday",
+            "/(/",
+            "+work and",
+            "\"open",
+        ] {
+            assert!(ids(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn plain_text_filters_are_told_apart() {
+        let filter = |input: &str| Query::parse(input, now()).unwrap().filter.unwrap();
+        assert!(filter("milk").is_plain_text());
+        assert!(filter("milk or not bread").is_plain_text());
+        assert!(!filter("milk and +home").is_plain_text());
+        assert!(!filter("/milk/").is_plain_text());
+    }
+}
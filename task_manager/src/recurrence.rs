@@ -0,0 +1,451 @@
+use std::fmt::{self, Display};
+
+use chrono::{Datelike, Days, Months, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// The subset of an RFC 5545 RRULE that makes sense for due dates: FREQ,
+/// INTERVAL, BYDAY (plain weekdays), BYMONTHDAY (one day), COUNT and UNTIL.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub interval: u32,
+    #[serde(default)]
+    pub by_day: Vec<Weekday>,
+    #[serde(default)]
+    pub by_month_day: Option<u32>,
+    /// Occurrences left, counting the current one.
+    #[serde(default)]
+    pub count: Option<u32>,
+    #[serde(default)]
+    pub until: Option<NaiveDate>,
+}
+
+/// The longest INTERVAL accepted, which keeps every step well inside the
+/// dates chrono can represent.
+const MAX_INTERVAL: u32 = 1000;
+
+const WEEKDAYS: [Weekday; 5] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+];
+
+impl Recurrence {
+    fn new(frequency: Frequency, interval: u32) -> Self {
+        Recurrence {
+            frequency,
+            interval,
+            by_day: Vec::new(),
+            by_month_day: None,
+            count: None,
+            until: None,
+        }
+    }
+
+    /// Parses the value of an `every:` token: `day`, `3days`, `weekday`,
+    /// `week`, `2weeks`, `month`, `month:15`, `year` and so on.
+    pub fn parse_every(spec: &str) -> Result<Self, String> {
+        let spec = spec.to_lowercase();
+        let (unit, day) = match spec.split_once(':') {
+            Some((unit, day)) => (unit, Some(day)),
+            None => (spec.as_str(), None),
+        };
+        let digits = unit.chars().take_while(char::is_ascii_digit).count();
+        let interval = match &unit[..digits] {
+            "" => 1,
+            n => parse_interval(n).ok_or("Invalid interval")?,
+        };
+        let mut rule = match unit[digits..].trim_end_matches('s') {
+            "d" | "day" => Recurrence::new(Frequency::Daily, interval),
+            "weekday" if interval == 1 => Recurrence {
+                by_day: WEEKDAYS.to_vec(),
+                ..Recurrence::new(Frequency::Weekly, 1)
+            },
+            "w" | "week" => Recurrence::new(Frequency::Weekly, interval),
+            "m" | "month" => Recurrence::new(Frequency::Monthly, interval),
+            "y" | "year" => Recurrence::new(Frequency::Yearly, interval),
+            _ => return Err(format!("Unknown repeat interval '{}'", spec)),
+        };
+        if let Some(day) = day {
+            if rule.frequency != Frequency::Monthly {
+                return Err("Only monthly repeats take a day of the month".to_string());
+            }
+            rule.by_month_day = Some(parse_month_day(day)?);
+        }
+        Ok(rule)
+    }
+
+    /// Parses an RRULE such as `FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH;COUNT=6`.
+    pub fn parse_rrule(spec: &str) -> Result<Self, String> {
+        let mut frequency = None;
+        let mut rule = Recurrence::new(Frequency::Daily, 1);
+        for part in spec.trim_start_matches("RRULE:").split(';') {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Expected KEY=VALUE, found '{}'", part))?;
+            match key.to_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(format!("Unsupported FREQ '{}'", value)),
+                    })
+                }
+                "INTERVAL" => {
+                    rule.interval = parse_interval(value)
+                        .ok_or_else(|| format!("Invalid INTERVAL '{}'", value))?
+                }
+                "BYDAY" => {
+                    rule.by_day = value
+                        .split(',')
+                        .map(parse_weekday)
+                        .collect::<Result<_, _>>()?
+                }
+                "BYMONTHDAY" => rule.by_month_day = Some(parse_month_day(value)?),
+                "COUNT" => {
+                    rule.count = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or_else(|| format!("Invalid COUNT '{}'", value))?,
+                    )
+                }
+                "UNTIL" => {
+                    let date = value.get(..8).unwrap_or(value);
+                    rule.until = Some(
+                        NaiveDate::parse_from_str(date, "%Y%m%d")
+                            .map_err(|_| format!("Invalid UNTIL '{}'", value))?,
+                    )
+                }
+                _ => return Err(format!("Unsupported RRULE part '{}'", key)),
+            }
+        }
+        rule.frequency = frequency.ok_or("RRULE needs a FREQ")?;
+        Ok(rule)
+    }
+
+    /// The first occurrence on or after `date`.
+    pub fn first_from(&self, date: NaiveDate) -> NaiveDate {
+        let matches = match self.frequency {
+            Frequency::Weekly if !self.by_day.is_empty() => self.by_day.contains(&date.weekday()),
+            Frequency::Monthly | Frequency::Yearly => {
+                self.by_month_day.is_none_or(|day| date.day() == day)
+            }
+            _ => true,
+        };
+        match date.pred_opt() {
+            Some(yesterday) if !matches => self.step(yesterday, false).unwrap_or(date),
+            _ => date,
+        }
+    }
+
+    /// The occurrence after the one due on `date`, along with the rule that
+    /// applies from there on, or `None` once COUNT or UNTIL runs out or the
+    /// next date would be past the last one chrono can represent.
+    pub fn next_after(&self, date: NaiveDate) -> Option<(NaiveDate, Recurrence)> {
+        let mut rest = self.clone();
+        if matches!(self.frequency, Frequency::Monthly | Frequency::Yearly) {
+            // Keep to the original day even after a short month clamps it.
+            rest.by_month_day.get_or_insert(date.day());
+        }
+        if let Some(count) = self.count {
+            if count <= 1 {
+                return None;
+            }
+            rest.count = Some(count - 1);
+        }
+        let next = self.step(date, true)?;
+        if self.until.is_some_and(|until| next > until) {
+            return None;
+        }
+        Some((next, rest))
+    }
+
+    /// Steps forward from `date`. With `whole_interval` false only the next
+    /// matching day is sought, so a rule can be lined up with a start date.
+    /// `None` when the result is out of range.
+    fn step(&self, date: NaiveDate, whole_interval: bool) -> Option<NaiveDate> {
+        let interval = if whole_interval { self.interval } else { 1 };
+        match self.frequency {
+            Frequency::Daily => date.checked_add_days(Days::new(interval.into())),
+            Frequency::Weekly if self.by_day.is_empty() => {
+                date.checked_add_days(Days::new(7 * u64::from(interval)))
+            }
+            Frequency::Weekly => {
+                // Later days in the same week come first; after the last one
+                // the rule skips ahead `interval` weeks.
+                let week_start = date.week(Weekday::Mon).first_day();
+                let mut next = date.succ_opt()?;
+                loop {
+                    let weeks = (next - week_start).num_days() / 7;
+                    let behind = weeks % i64::from(interval);
+                    if weeks > 0 && behind != 0 {
+                        let skip = 7 * (i64::from(interval) - behind) as u64;
+                        next = next
+                            .week(Weekday::Mon)
+                            .first_day()
+                            .checked_add_days(Days::new(skip))?;
+                        continue;
+                    }
+                    if self.by_day.contains(&next.weekday()) {
+                        return Some(next);
+                    }
+                    next = next.succ_opt()?;
+                }
+            }
+            Frequency::Monthly => self.add_months(date, interval),
+            Frequency::Yearly => self.add_months(date, interval.checked_mul(12)?),
+        }
+    }
+
+    /// Moves `months` ahead onto the rule's day of the month (or `date`'s),
+    /// falling back to the last day of shorter months. A `date` before that
+    /// day in its month only moves up to it.
+    fn add_months(&self, date: NaiveDate, months: u32) -> Option<NaiveDate> {
+        let day = self.by_month_day.unwrap_or(date.day());
+        let mut first = date.with_day(1).unwrap();
+        // A short month's last day stands in for a later day of the month.
+        if date.day() >= day.min(days_in_month(first)?) {
+            first = first.checked_add_months(Months::new(months))?;
+        }
+        first.with_day(day.min(days_in_month(first)?))
+    }
+}
+
+/// The length of the month that starts on `first`.
+fn days_in_month(first: NaiveDate) -> Option<u32> {
+    Some(first.checked_add_months(Months::new(1))?.pred_opt()?.day())
+}
+
+fn parse_weekday(name: &str) -> Result<Weekday, String> {
+    match name.to_uppercase().as_str() {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => Err(format!("Unsupported BYDAY '{}'", name)),
+    }
+}
+
+fn parse_interval(n: &str) -> Option<u32> {
+    n.parse().ok().filter(|n| (1..=MAX_INTERVAL).contains(n))
+}
+
+fn parse_month_day(day: &str) -> Result<u32, String> {
+    day.parse()
+        .ok()
+        .filter(|day| (1..=31).contains(day))
+        .ok_or_else(|| format!("Invalid day of the month '{}'", day))
+}
+
+/// Writes the rule back out as an RRULE.
+impl Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", frequency)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self
+                .by_day
+                .iter()
+                .map(|day| day.to_string()[..2].to_uppercase())
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if let Some(day) = self.by_month_day {
+            write!(f, ";BYMONTHDAY={}", day)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%d"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TaskManager;
+    use crate::store::MemoryStore;
+    use crate::tests::{manager, run};
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    /// The first `n` dates after `start`.
+    fn dates(rule: &Recurrence, start: NaiveDate, n: usize) -> Vec<NaiveDate> {
+        let (mut date, mut rule) = (start, rule.clone());
+        let mut dates = Vec::new();
+        while dates.len() < n {
+            let Some((next, rest)) = rule.next_after(date) else {
+                break;
+            };
+            dates.push(next);
+            (date, rule) = (next, rest);
+        }
+        dates
+    }
+
+    #[test]
+    fn every_reads_units_and_intervals() {
+        let rule = Recurrence::parse_every("3days").unwrap();
+        assert_eq!((rule.frequency, rule.interval), (Frequency::Daily, 3));
+        let rule = Recurrence::parse_every("2Weeks").unwrap();
+        assert_eq!((rule.frequency, rule.interval), (Frequency::Weekly, 2));
+        let rule = Recurrence::parse_every("month:15").unwrap();
+        assert_eq!(rule.by_month_day, Some(15));
+        assert_eq!(Recurrence::parse_every("weekday").unwrap().by_day, WEEKDAYS);
+        for spec in [
+            "fortnight",
+            "0days",
+            "2weekdays",
+            "week:3",
+            "month:32",
+            "1001days",
+        ] {
+            assert!(Recurrence::parse_every(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn rrules_round_trip() {
+        for spec in [
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH;COUNT=6",
+            "FREQ=MONTHLY;BYMONTHDAY=31;UNTIL=20271231",
+            "FREQ=YEARLY",
+        ] {
+            assert_eq!(Recurrence::parse_rrule(spec).unwrap().to_string(), spec);
+        }
+        let rule = Recurrence::parse_rrule("RRULE:freq=daily;until=20261231T000000Z").unwrap();
+        assert_eq!(rule.until, Some(date(2026, 12, 31)));
+        for spec in [
+            "INTERVAL=2",
+            "FREQ=HOURLY",
+            "FREQ=DAILY;COUNT=0",
+            "FREQ=DAILY;BYDAY=XX",
+            "FREQ=DAILY;BYSETPOS=1",
+            "FREQ=DAILY;INTERVAL",
+        ] {
+            assert!(Recurrence::parse_rrule(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn intervals_too_large_to_step_are_refused() {
+        assert!(Recurrence::parse_every("1000years").is_ok());
+        assert!(Recurrence::parse_every("1001years").is_err());
+        assert!(Recurrence::parse_every("99999999999days").is_err());
+        assert!(Recurrence::parse_rrule("FREQ=YEARLY;INTERVAL=4294967295").is_err());
+        assert!(Recurrence::parse_rrule("FREQ=YEARLY;INTERVAL=0").is_err());
+    }
+
+    #[test]
+    fn monthly_rules_keep_their_day_through_short_months() {
+        let rule = Recurrence::parse_every("month").unwrap();
+        assert_eq!(
+            dates(&rule, date(2026, 1, 31), 3),
+            [date(2026, 2, 28), date(2026, 3, 31), date(2026, 4, 30)]
+        );
+        let rule = Recurrence::parse_every("month:31").unwrap();
+        assert_eq!(rule.first_from(date(2026, 3, 1)), date(2026, 3, 31));
+        assert_eq!(rule.first_from(date(2026, 2, 28)), date(2026, 2, 28));
+        let rule = Recurrence::parse_every("year").unwrap();
+        assert_eq!(dates(&rule, date(2028, 2, 29), 1), [date(2029, 2, 28)]);
+    }
+
+    #[test]
+    fn weekly_rules_visit_each_listed_day() {
+        let rule = Recurrence::parse_rrule("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH").unwrap();
+        assert_eq!(
+            dates(&rule, date(2026, 10, 12), 3),
+            [date(2026, 10, 15), date(2026, 10, 26), date(2026, 10, 29)]
+        );
+        let rule = Recurrence::parse_every("weekday").unwrap();
+        assert_eq!(dates(&rule, date(2026, 10, 16), 1), [date(2026, 10, 19)]);
+        assert_eq!(rule.first_from(date(2026, 10, 17)), date(2026, 10, 19));
+        assert_eq!(rule.first_from(date(2026, 10, 14)), date(2026, 10, 14));
+    }
+
+    #[test]
+    fn count_and_until_end_the_series() {
+        let rule = Recurrence::parse_rrule("FREQ=DAILY;COUNT=3").unwrap();
+        assert_eq!(dates(&rule, date(2026, 10, 14), 5).len(), 2);
+        let rule = Recurrence::parse_rrule("FREQ=WEEKLY;UNTIL=20261101").unwrap();
+        assert_eq!(
+            dates(&rule, date(2026, 10, 14), 5),
+            [date(2026, 10, 21), date(2026, 10, 28)]
+        );
+    }
+
+    #[test]
+    fn stepping_past_the_last_date_ends_the_series() {
+        let huge = |spec: &str| Recurrence {
+            interval: u32::MAX,
+            ..Recurrence::parse_every(spec).unwrap()
+        };
+        for spec in ["day", "week", "month", "year", "weekday"] {
+            assert_eq!(huge(spec).next_after(date(2026, 10, 16)), None, "{}", spec);
+        }
+        for spec in ["day", "weekday", "month:15", "year"] {
+            let rule = Recurrence::parse_every(spec).unwrap();
+            assert_eq!(rule.next_after(NaiveDate::MAX), None, "{}", spec);
+            rule.first_from(NaiveDate::MAX);
+            rule.first_from(NaiveDate::MIN);
+        }
+    }
+
+    #[test]
+    fn completing_a_rule_too_far_apart_to_repeat_does_not_panic() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add pay rent due:2026-11-01").unwrap();
+        // Older journals may hold intervals the parser now refuses.
+        manager.tasks[0].details_mut().recurrence = Some(Recurrence {
+            interval: u32::MAX,
+            ..Recurrence::parse_every("year").unwrap()
+        });
+        run(&mut manager, &mut store, "complete 1").unwrap();
+        assert_eq!(manager.tasks.len(), 1);
+    }
+
+    #[test]
+    fn an_undated_task_repeats_from_the_day_it_was_completed_on_replay() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add water plants").unwrap();
+        manager.tasks[0].details_mut().recurrence = Recurrence::parse_every("week").ok();
+        manager.save(&mut store).unwrap();
+        run(&mut manager, &mut store, "complete 1").unwrap();
+
+        // Loading runs on the wall clock; the journaled stamp still wins.
+        let loaded = TaskManager::load(&mut store).unwrap();
+        let next = &loaded.tasks[loaded.position(2).unwrap()];
+        assert_eq!(next.details().due, Some(date(2026, 10, 21)));
+        assert_eq!(manager.tasks[manager.position(2).unwrap()], *next);
+    }
+}
@@ -0,0 +1,193 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::TaskManager;
+use crate::store::LoadError;
+
+/// Version written by this build. Bump it together with a new entry in
+/// `MIGRATIONS` whenever the saved shape of `TaskManager` changes.
+pub const CURRENT_VERSION: u32 = 10;
+
+/// `MIGRATIONS[n]` upgrades a version `n` document to version `n + 1`.
+const MIGRATIONS: [fn(&mut Map<String, Value>); CURRENT_VERSION as usize] = [
+    v0_to_v1, v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7, v7_to_v8, v8_to_v9,
+    v9_to_v10,
+];
+
+/// What actually gets written: the manager with its schema version alongside.
+#[derive(Serialize)]
+pub struct Versioned<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    manager: &'a TaskManager,
+}
+
+pub fn versioned(manager: &TaskManager) -> Versioned<'_> {
+    Versioned {
+        schema_version: CURRENT_VERSION,
+        manager,
+    }
+}
+
+/// Upgrades a saved document to `CURRENT_VERSION` in place, returning the
+/// version it was written with. Files from before versioning count as 0.
+pub fn migrate(doc: &mut Value) -> Result<u32, LoadError> {
+    let doc = doc
+        .as_object_mut()
+        .ok_or_else(|| LoadError::UnknownSchema("expected a JSON object".to_string()))?;
+    let from = match doc.get("schema_version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| LoadError::UnknownSchema(format!("bad schema_version {}", version)))?,
+    };
+    if from > CURRENT_VERSION {
+        return Err(LoadError::UnknownSchema(format!(
+            "schema_version {} is newer than this build supports ({})",
+            from, CURRENT_VERSION
+        )));
+    }
+
+    for migration in &MIGRATIONS[from as usize..] {
+        migration(doc);
+    }
+    doc.insert("schema_version".to_string(), CURRENT_VERSION.into());
+    Ok(from)
+}
+
+/// Version 0 predates `schema_version` and the journal `revision` counter.
+fn v0_to_v1(doc: &mut Map<String, Value>) {
+    doc.entry("revision").or_insert(0.into());
+}
+
+/// Version 2 lets the JSON store record which session journals a snapshot
+/// already includes.
+fn v1_to_v2(doc: &mut Map<String, Value>) {
+    doc.entry("journals").or_insert(Value::Object(Map::new()));
+}
+
+/// Version 3 adds the in-progress, blocked, cancelled and archived states.
+/// Older documents only hold pending and completed tasks, which read as is.
+fn v2_to_v3(_doc: &mut Map<String, Value>) {}
+
+/// Version 4 saves the undo/redo history with the tasks.
+fn v3_to_v4(doc: &mut Map<String, Value>) {
+    doc.entry("history")
+        .or_insert(serde_json::json!({ "undo": [], "redo": [] }));
+}
+
+/// Version 5 gives every task a priority, due date, tags and notes. Copies of
+/// tasks in the undo history are filled in with the same defaults when read.
+fn v4_to_v5(doc: &mut Map<String, Value>) {
+    for fields in task_fields(doc) {
+        fields.entry("priority").or_insert(Value::Null);
+        fields.entry("due").or_insert(Value::Null);
+        fields.entry("tags").or_insert(Value::Array(Vec::new()));
+        fields.entry("notes").or_insert("".into());
+    }
+}
+
+/// Version 6 adds subtasks and dependencies between tasks.
+fn v5_to_v6(doc: &mut Map<String, Value>) {
+    for fields in task_fields(doc) {
+        fields.entry("parent").or_insert(Value::Null);
+        fields
+            .entry("blocked_by")
+            .or_insert(Value::Array(Vec::new()));
+    }
+}
+
+/// Version 7 adds recurring tasks.
+fn v6_to_v7(doc: &mut Map<String, Value>) {
+    for fields in task_fields(doc) {
+        fields.entry("recurrence").or_insert(Value::Null);
+    }
+}
+
+/// Version 8 adds reminders.
+fn v7_to_v8(doc: &mut Map<String, Value>) {
+    for fields in task_fields(doc) {
+        fields.entry("remind").or_insert(Value::Null);
+    }
+}
+
+/// Version 9 adds time tracking.
+fn v8_to_v9(doc: &mut Map<String, Value>) {
+    for fields in task_fields(doc) {
+        fields.entry("time_log").or_insert(Value::Array(Vec::new()));
+        fields.entry("timer_since").or_insert(Value::Null);
+    }
+}
+
+/// Version 10 adds task timestamps and the audit log.
+fn v9_to_v10(doc: &mut Map<String, Value>) {
+    doc.entry("audit").or_insert(Value::Array(Vec::new()));
+    for fields in task_fields(doc) {
+        for key in ["created", "updated", "completed"] {
+            fields.entry(key).or_insert(Value::Null);
+        }
+    }
+}
+
+/// The fields of each saved task, inside its `{"<State>": {...}}` wrapper.
+fn task_fields(doc: &mut Map<String, Value>) -> impl Iterator<Item = &mut Map<String, Value>> {
+    doc.get_mut("tasks")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object_mut)
+        .flat_map(|task| task.values_mut().filter_map(Value::as_object_mut))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn a_version_0_file_upgrades_through_every_migration() {
+        let mut doc = json!({
+            "tasks": [
+                { "Pending": { "id": 1, "description": "buy milk" } },
+                { "Completed": { "id": 2, "description": "walk dog" } }
+            ],
+            "next_id": 3
+        });
+        assert_eq!(migrate(&mut doc).unwrap(), 0);
+        assert_eq!(doc["schema_version"], CURRENT_VERSION);
+        assert_eq!(doc["revision"], 0);
+        assert_eq!(doc["audit"], json!([]));
+        assert_eq!(doc["tasks"][0]["Pending"]["tags"], json!([]));
+        assert_eq!(doc["tasks"][1]["Completed"]["time_log"], json!([]));
+
+        let manager: TaskManager = serde_json::from_value(doc).unwrap();
+        assert_eq!(manager.tasks.len(), 2);
+        assert_eq!(manager.tasks[1].state(), "completed");
+        assert_eq!(manager.next_id, 3);
+    }
+
+    #[test]
+    fn a_current_file_is_left_alone() {
+        let manager = TaskManager::new();
+        let mut doc = serde_json::to_value(versioned(&manager)).unwrap();
+        let before = doc.clone();
+        assert_eq!(migrate(&mut doc).unwrap(), CURRENT_VERSION);
+        assert_eq!(doc, before);
+    }
+
+    #[test]
+    fn newer_and_malformed_versions_are_refused() {
+        let mut newer = json!({ "schema_version": CURRENT_VERSION + 1, "tasks": [] });
+        assert!(matches!(
+            migrate(&mut newer),
+            Err(LoadError::UnknownSchema(_))
+        ));
+        let mut malformed = json!({ "schema_version": "ten", "tasks": [] });
+        assert!(matches!(
+            migrate(&mut malformed),
+            Err(LoadError::UnknownSchema(_))
+        ));
+        assert!(migrate(&mut json!([])).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use crate::TaskManager;
+use crate::task::AnyTask;
+
+/// Inverted index from each word in a task's description and notes to the
+/// tasks containing it. Kept in memory only and rebuilt on load.
+#[derive(Debug, Default, Clone)]
+pub struct SearchIndex {
+    /// term -> task id -> occurrences
+    postings: HashMap<String, HashMap<u32, u32>>,
+    /// task id -> its distinct terms, so a task can be dropped again.
+    terms: HashMap<u32, Vec<String>>,
+}
+
+/// Score given to a query word that matches an indexed term by prefix, or
+/// within the allowed edit distance, relative to an exact match.
+const PREFIX_WEIGHT: f64 = 0.7;
+const FUZZY_WEIGHT: f64 = 0.5;
+
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Typos tolerated for a query word of this length.
+fn max_distance(word: &str) -> usize {
+    match word.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Edit distance counting a swap of adjacent letters as one edit, giving up
+/// once it is certain to exceed `limit`.
+fn distance(a: &str, b: &str, limit: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > limit {
+        return None;
+    }
+    let mut before: Vec<usize> = Vec::new();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (prev[j - 1] + cost).min(prev[j] + 1).min(row[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        if row.iter().min().is_some_and(|&best| best > limit) {
+            return None;
+        }
+        before = std::mem::replace(&mut prev, row);
+    }
+    Some(prev[b.len()]).filter(|&d| d <= limit)
+}
+
+impl SearchIndex {
+    pub fn build(tasks: &[AnyTask]) -> Self {
+        let mut index = SearchIndex::default();
+        for task in tasks {
+            index.insert(task);
+        }
+        index
+    }
+
+    /// Indexes `task`, replacing whatever was indexed under its id before.
+    pub fn insert(&mut self, task: &AnyTask) {
+        self.remove(task.id());
+        let details = task.details();
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for term in tokenize(&details.description).chain(tokenize(&details.notes)) {
+            *counts.entry(term).or_default() += 1;
+        }
+        for (term, count) in &counts {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(task.id(), *count);
+        }
+        self.terms.insert(task.id(), counts.into_keys().collect());
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        for term in self.terms.remove(&id).unwrap_or_default() {
+            if let Some(posting) = self.postings.get_mut(&term) {
+                posting.remove(&id);
+                if posting.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Task ids matching any word of `query`, best first. Each query word
+    /// scores by its closest indexed term (exact, prefix or a near miss),
+    /// weighted by how rare that term is.
+    pub fn search(&self, query: &str) -> Vec<(u32, f64)> {
+        let total = self.terms.len() as f64;
+        let mut scores: HashMap<u32, f64> = HashMap::new();
+        for word in tokenize(query) {
+            let limit = max_distance(&word);
+            let mut best: HashMap<u32, f64> = HashMap::new();
+            for (term, posting) in &self.postings {
+                let weight = if *term == word {
+                    1.0
+                } else if word.chars().count() >= 3 && term.starts_with(&word) {
+                    PREFIX_WEIGHT
+                } else if let Some(d) = distance(&word, term, limit) {
+                    FUZZY_WEIGHT / d as f64
+                } else {
+                    continue;
+                };
+                let idf = (1.0 + total / posting.len() as f64).ln();
+                for (&id, &count) in posting {
+                    let score = weight * idf * (1.0 + (count as f64).ln());
+                    let entry = best.entry(id).or_default();
+                    *entry = entry.max(score);
+                }
+            }
+            for (id, score) in best {
+                *scores.entry(id).or_default() += score;
+            }
+        }
+        let mut results: Vec<(u32, f64)> = scores.into_iter().collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        results
+    }
+}
+
+impl TaskManager {
+    /// Brings the index up to date with task `id` after it was added,
+    /// changed or removed.
+    pub fn reindex(&mut self, id: u32) {
+        match self.tasks.iter().find(|t| t.id() == id) {
+            Some(task) => self.index.insert(task),
+            None => self.index.remove(id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::tests::{manager, run};
+
+    fn ids(manager: &TaskManager, query: &str) -> Vec<u32> {
+        manager
+            .index
+            .search(query)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn distance_counts_edits_and_swaps() {
+        assert_eq!(distance("milk", "milk", 1), Some(0));
+        assert_eq!(distance("milk", "mlik", 1), Some(1));
+        assert_eq!(distance("milk", "silk", 1), Some(1));
+        assert_eq!(distance("milk", "mil", 1), Some(1));
+        assert_eq!(distance("milk", "milks", 1), Some(1));
+        assert_eq!(distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(distance("kitten", "sitting", 2), None);
+        assert_eq!(distance("a", "abcd", 2), None);
+    }
+
+    #[test]
+    fn short_words_must_match_exactly() {
+        assert_eq!(max_distance("cat"), 0);
+        assert_eq!(max_distance("groceries"), 2);
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add feed the cat").unwrap();
+        assert!(ids(&manager, "cab").is_empty());
+    }
+
+    #[test]
+    fn typos_still_find_tasks() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy groceries").unwrap();
+        run(&mut manager, &mut store, "add write report").unwrap();
+        assert_eq!(ids(&manager, "grocereis"), [1]);
+        assert_eq!(ids(&manager, "reprot"), [2]);
+        assert_eq!(ids(&manager, "groc"), [1]);
+        assert!(ids(&manager, "holiday").is_empty());
+    }
+
+    #[test]
+    fn exact_matches_rank_above_prefixes_and_typos() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add painter invoice").unwrap();
+        run(&mut manager, &mut store, "add pain killers").unwrap();
+        run(&mut manager, &mut store, "add paint fence").unwrap();
+        assert_eq!(ids(&manager, "paint"), [3, 1, 2]);
+    }
+
+    #[test]
+    fn tasks_matching_more_and_rarer_words_rank_first() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add paint fence").unwrap();
+        run(&mut manager, &mut store, "add paint shed").unwrap();
+        run(&mut manager, &mut store, "add paint shed door").unwrap();
+        assert_eq!(ids(&manager, "shed door"), [3, 2]);
+        assert_eq!(ids(&manager, "paint fence")[0], 1);
+    }
+
+    #[test]
+    fn the_index_follows_edits_and_deletes() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        run(&mut manager, &mut store, "edit 1 buy bread").unwrap();
+        assert!(ids(&manager, "milk").is_empty());
+        assert_eq!(ids(&manager, "bread"), [1]);
+        run(&mut manager, &mut store, "delete 1").unwrap();
+        assert!(ids(&manager, "bread").is_empty());
+    }
+}
@@ -0,0 +1,516 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Cursor, Read};
+use std::sync::Mutex;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Deserializer, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::autosave::Autosaver;
+use crate::query::Query;
+use crate::recurrence::Recurrence;
+use crate::store::{SharedStore, TaskStore};
+use crate::task::{AnyTask, Details, Field, Priority};
+use crate::{Command, CommandError, TaskManager};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+
+/// Request bodies larger than this are refused rather than read.
+const MAX_BODY: u64 = 1024 * 1024;
+
+/// The fields of a task a client may set, named and formatted as in the task
+/// JSON the API returns. Anything else, such as `id` or `time_log`, is
+/// refused so a typo is not silently ignored.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaskBody {
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    priority: Option<Priority>,
+    #[serde(default)]
+    due: Option<NaiveDate>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    parent: Option<u32>,
+    #[serde(default)]
+    recurrence: Option<Recurrence>,
+    #[serde(default)]
+    remind: Option<NaiveDateTime>,
+}
+
+impl TryFrom<TaskBody> for Details {
+    type Error = Failure;
+
+    fn try_from(body: TaskBody) -> Result<Self, Self::Error> {
+        check_tags(&body.tags)?;
+        Ok(Details {
+            description: body.description.trim().to_string(),
+            priority: body.priority,
+            due: body.due,
+            tags: body.tags,
+            parent: body.parent,
+            recurrence: body.recurrence,
+            remind: body.remind,
+            ..Details::default()
+        })
+    }
+}
+
+/// The body of a `PATCH`: like [`TaskBody`], except that a field left out is
+/// kept and a field set to `null` is cleared. `tags` are added to the task's
+/// and `remove_tags` taken off.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PatchBody {
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    priority: Option<Option<Priority>>,
+    #[serde(default, deserialize_with = "nullable")]
+    due: Option<Option<NaiveDate>>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    remove_tags: Vec<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    parent: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    recurrence: Option<Option<Recurrence>>,
+    #[serde(default, deserialize_with = "nullable")]
+    remind: Option<Option<NaiveDateTime>>,
+}
+
+/// Tells a `null` (`Some(None)`) apart from a field left out (`None`).
+fn nullable<'de, T: Deserialize<'de>, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+impl PatchBody {
+    /// The commands that make these changes to task `id`: an edit for what
+    /// is set and a clear for what is `null` or removed, applied together.
+    fn into_command(self, id: u32) -> Result<Command, Failure> {
+        check_tags(&self.tags)?;
+        let mut cleared = Vec::new();
+        let changes = Details {
+            description: self.description.unwrap_or_default().trim().to_string(),
+            priority: set_or_clear(self.priority, Field::Priority, &mut cleared),
+            due: set_or_clear(self.due, Field::Due, &mut cleared),
+            tags: self.tags,
+            parent: set_or_clear(self.parent, Field::Parent, &mut cleared),
+            recurrence: set_or_clear(self.recurrence, Field::Recurrence, &mut cleared),
+            remind: set_or_clear(self.remind, Field::Remind, &mut cleared),
+            ..Details::default()
+        };
+        cleared.extend(self.remove_tags.into_iter().map(Field::Tag));
+        let edit = Command::Edit(id, changes);
+        Ok(if cleared.is_empty() {
+            edit
+        } else {
+            Command::Batch(vec![edit, Command::Clear(id, cleared)])
+        })
+    }
+}
+
+/// The new value of a patched field, noting `field` in `cleared` if it was
+/// `null`.
+fn set_or_clear<T>(value: Option<Option<T>>, field: Field, cleared: &mut Vec<Field>) -> Option<T> {
+    if let Some(None) = value {
+        cleared.push(field);
+    }
+    value.flatten()
+}
+
+fn check_tags(tags: &[String]) -> Result<(), Failure> {
+    match tags
+        .iter()
+        .find(|tag| tag.is_empty() || tag.contains(char::is_whitespace))
+    {
+        Some(tag) => Err(Failure::new(400, format!("Invalid tag '{}'", tag))),
+        None => Ok(()),
+    }
+}
+
+/// A request that could not be served, answered with `{"error": message}`.
+#[derive(Debug)]
+struct Failure {
+    status: u16,
+    message: String,
+    /// The task's current tag, when the failure was a stale `If-Match`.
+    etag: Option<String>,
+}
+
+impl Failure {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Failure {
+            status,
+            message: message.into(),
+            etag: None,
+        }
+    }
+}
+
+impl From<CommandError> for Failure {
+    /// Follows the exit codes of one-shot commands: a malformed request is
+    /// 400, a missing task 404 and a change the task's state does not allow
+    /// 409.
+    fn from(error: CommandError) -> Self {
+        let status = match error.exit_code() {
+            2 => 400,
+            3 => 404,
+            4 | 5 => 409,
+            _ => 500,
+        };
+        Failure::new(status, error.to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+/// What to answer a request with.
+struct Reply {
+    status: u16,
+    body: Option<String>,
+    etag: Option<String>,
+    location: Option<String>,
+}
+
+impl Reply {
+    fn json(status: u16, value: &impl Serialize) -> Self {
+        Reply {
+            status,
+            body: Some(serde_json::to_string(value).expect("tasks always serialize")),
+            etag: None,
+            location: None,
+        }
+    }
+
+    /// `task` along with its entity tag.
+    fn task(status: u16, task: &AnyTask) -> Self {
+        Reply {
+            etag: Some(etag(task)),
+            ..Reply::json(status, task)
+        }
+    }
+
+    fn empty(status: u16) -> Self {
+        Reply {
+            status,
+            body: None,
+            etag: None,
+            location: None,
+        }
+    }
+
+    fn into_response(self) -> Response<Cursor<Vec<u8>>> {
+        let mut response = match self.body {
+            Some(body) => {
+                Response::from_string(body).with_header(header("Content-Type", "application/json"))
+            }
+            None => Response::from_data(Vec::new()),
+        }
+        .with_status_code(self.status);
+        if let Some(etag) = self.etag {
+            response.add_header(header("ETag", &etag));
+        }
+        if let Some(location) = self.location {
+            response.add_header(header("Location", &location));
+        }
+        response
+    }
+}
+
+impl From<Failure> for Reply {
+    fn from(failure: Failure) -> Self {
+        Reply {
+            etag: failure.etag,
+            ..Reply::json(
+                failure.status,
+                &ErrorBody {
+                    error: &failure.message,
+                },
+            )
+        }
+    }
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("header is ASCII")
+}
+
+/// Changes whenever anything about the task does.
+fn etag(task: &AnyTask) -> String {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(task)
+        .expect("tasks always serialize")
+        .hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Whether an `If-Match` or `If-None-Match` value names `etag`.
+fn names(condition: &str, etag: &str) -> bool {
+    condition
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// Decodes `%XX` escapes and `+` for spaces, as in a query string.
+fn percent_decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' if rest.len() >= 2 => {
+                match u8::from_str_radix(&String::from_utf8_lossy(&rest[..2]), 16) {
+                    Ok(decoded) => {
+                        bytes.push(decoded);
+                        rest = &rest[2..];
+                    }
+                    Err(_) => bytes.push(byte),
+                }
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// One request, with its body already read.
+struct Call<'a> {
+    method: &'a Method,
+    path: &'a str,
+    query: Option<&'a str>,
+    body: String,
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+}
+
+impl Call<'_> {
+    fn parse_body<'de, T: Deserialize<'de>>(&'de self) -> Result<T, Failure> {
+        serde_json::from_str(&self.body)
+            .map_err(|e| Failure::new(400, format!("Invalid task JSON: {}", e)))
+    }
+
+    /// Fails with 412 unless `If-Match`, if given, names the task's current
+    /// tag, so a client cannot overwrite a change it has not seen.
+    fn check_precondition(&self, task: &AnyTask) -> Result<(), Failure> {
+        let current = etag(task);
+        match &self.if_match {
+            Some(condition) if !names(condition, &current) => Err(Failure {
+                etag: Some(current),
+                ..Failure::new(
+                    412,
+                    format!("Task {} has changed since it was read", task.id()),
+                )
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Serves the REST API on `address` until the process is stopped. Every
+/// change goes through [`crate::run_command`] like a command typed into the
+/// REPL, and is saved by the autosave thread.
+///
+/// - `GET /tasks?q=<query>` lists tasks matching a `list` query
+/// - `POST /tasks` adds a task
+/// - `GET /tasks/<id>` reads one task
+/// - `PATCH /tasks/<id>` edits a task, keeping fields left out and clearing
+///   those set to null
+/// - `POST /tasks/<id>/complete` completes a task
+/// - `DELETE /tasks/<id>` removes a task
+///
+/// Tasks are returned as they are saved, e.g. `{"Pending": {"id": 1, ...}}`,
+/// with an `ETag` that `If-Match` can name to make a change conditional.
+pub fn serve(
+    address: &str,
+    manager: &Mutex<TaskManager>,
+    store: &SharedStore,
+    autosaver: &Autosaver,
+) -> io::Result<()> {
+    let server = Server::http(address).map_err(io::Error::other)?;
+    println!("Listening on http://{}", server.server_addr());
+    for mut request in server.incoming_requests() {
+        let reply = match read_body(&mut request) {
+            Ok(body) => handle(&request, body, manager, store, autosaver),
+            Err(failure) => failure.into(),
+        };
+        if let Err(e) = request.respond(reply.into_response()) {
+            eprintln!("[SERVER ERROR] Failed to send a response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn read_body(request: &mut Request) -> Result<String, Failure> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_string(&mut body)
+        .map_err(|e| Failure::new(400, format!("Unreadable request body: {}", e)))?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(Failure::new(413, "The request body is too large"));
+    }
+    Ok(body)
+}
+
+fn handle(
+    request: &Request,
+    body: String,
+    manager: &Mutex<TaskManager>,
+    store: &SharedStore,
+    autosaver: &Autosaver,
+) -> Reply {
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv(name))
+            .map(|header| header.value.to_string())
+    };
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (request.url(), None),
+    };
+    let call = Call {
+        method: request.method(),
+        path,
+        query,
+        body,
+        if_match: header("If-Match"),
+        if_none_match: header("If-None-Match"),
+    };
+
+    let mut manager = manager.lock().unwrap();
+    let mut store = store.lock().unwrap();
+    // Answer with what other processes saved too, as the TUI shows it.
+    if let Err(e) = manager.refresh(store.as_mut()) {
+        eprintln!("[SERVER ERROR] Failed to check for changes: {}", e);
+    }
+    let revision = manager.revision;
+    let reply = route(&call, &mut manager, store.as_mut()).unwrap_or_else(Reply::from);
+    if manager.revision != revision {
+        autosaver.notify();
+    }
+    reply
+}
+
+fn route(
+    call: &Call,
+    manager: &mut TaskManager,
+    store: &mut dyn TaskStore,
+) -> Result<Reply, Failure> {
+    let segments: Vec<&str> = call.path.trim_matches('/').split('/').collect();
+    let not_found = || Failure::new(404, format!("No such resource '{}'", call.path));
+    let id = |segment: &str| segment.parse::<u32>().map_err(|_| not_found());
+    match (call.method, segments.as_slice()) {
+        (Method::Get, ["tasks"]) => list(call, manager),
+        (Method::Post, ["tasks"]) => add(call, manager, store),
+        (Method::Get, ["tasks", segment]) => get(call, manager, id(segment)?),
+        (Method::Patch, ["tasks", segment]) => {
+            let id = id(segment)?;
+            let patch: PatchBody = call.parse_body()?;
+            change(call, manager, store, id, patch.into_command(id)?)
+        }
+        (Method::Delete, ["tasks", segment]) => {
+            let id = id(segment)?;
+            change(call, manager, store, id, Command::Delete(id))
+        }
+        (Method::Post, ["tasks", segment, "complete"]) => {
+            let id = id(segment)?;
+            change(call, manager, store, id, Command::Complete(id))
+        }
+        (_, ["tasks"] | ["tasks", _] | ["tasks", _, "complete"]) => Err(Failure::new(
+            405,
+            format!("{} is not allowed on '{}'", call.method, call.path),
+        )),
+        _ => Err(not_found()),
+    }
+}
+
+fn find(manager: &TaskManager, id: u32) -> Result<&AnyTask, Failure> {
+    manager
+        .tasks
+        .iter()
+        .find(|task| task.id() == id)
+        .ok_or_else(|| CommandError::TaskNotFound(id).into())
+}
+
+fn list(call: &Call, manager: &TaskManager) -> Result<Reply, Failure> {
+    let text = call
+        .query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("q="))
+        .map(percent_decode)
+        .unwrap_or_default();
+    let query = Query::parse(&text, manager.clock.now())
+        .map_err(|e| Failure::from(CommandError::InvalidQuery(e)))?;
+    Ok(Reply::json(200, &query.select(&manager.tasks)))
+}
+
+fn get(call: &Call, manager: &TaskManager, id: u32) -> Result<Reply, Failure> {
+    let task = find(manager, id)?;
+    let current = etag(task);
+    if let Some(condition) = &call.if_none_match
+        && names(condition, &current)
+    {
+        return Ok(Reply {
+            etag: Some(current),
+            ..Reply::empty(304)
+        });
+    }
+    Ok(Reply::task(200, task))
+}
+
+fn add(
+    call: &Call,
+    manager: &mut TaskManager,
+    store: &mut dyn TaskStore,
+) -> Result<Reply, Failure> {
+    let body: TaskBody = call.parse_body()?;
+    let mut details = Details::try_from(body)?;
+    if details.description.is_empty() {
+        return Err(Failure::new(400, "A task needs a description"));
+    }
+    // Pin the first occurrence as `add` does on the command line, so
+    // replaying the journal on another day gives the same dates.
+    if let (Some(recurrence), None) = (&details.recurrence, details.due) {
+        details.due = Some(recurrence.first_from(manager.clock.today()));
+    }
+    let id = manager.next_id;
+    crate::run_command(manager, store, Command::Add(details))?;
+    Ok(Reply {
+        location: Some(format!("/tasks/{}", id)),
+        ..Reply::task(201, find(manager, id)?)
+    })
+}
+
+/// Runs `command` on task `id`, if `If-Match` allows, and answers with the
+/// task as it is afterwards.
+fn change(
+    call: &Call,
+    manager: &mut TaskManager,
+    store: &mut dyn TaskStore,
+    id: u32,
+    command: Command,
+) -> Result<Reply, Failure> {
+    call.check_precondition(find(manager, id)?)?;
+    let deleted = matches!(command, Command::Delete(_));
+    crate::run_command(manager, store, command)?;
+    if deleted {
+        return Ok(Reply::empty(204));
+    }
+    Ok(Reply::task(200, find(manager, id)?))
+}
@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde_json::Value;
+
+use super::{
+    JournalEntry, LoadError, TaskStore, append_line, backup_suffix, is_session_id, lock_file,
+    new_session_id, read_journal, salvage_json, try_lock_file, with_suffix, write_atomic,
+};
+use crate::TaskManager;
+use crate::schema::{self, CURRENT_VERSION};
+
+/// The whole manager as one pretty-printed JSON document. Each session
+/// journals to its own `<file>.journal.<session>` next to it, and saves are
+/// serialized through an advisory lock on `<file>.lock`.
+pub struct JsonFileStore {
+    path: PathBuf,
+    session: String,
+    /// This session's journal, locked while it holds unsaved entries so no
+    /// other session adopts it.
+    journal: Option<File>,
+    /// Journals of dead sessions whose entries were replayed into this one.
+    /// They are deleted once a snapshot containing those entries is on disk.
+    adopted: Vec<(PathBuf, File)>,
+    /// For each session journal, the highest revision already in the snapshot.
+    folded: BTreeMap<String, u64>,
+    /// The snapshot as of our last load or save, used as the merge base.
+    base: TaskManager,
+    /// The file as of our last load or save, to notice other writers.
+    seen: Option<Fingerprint>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Fingerprint {
+    modified: SystemTime,
+    len: u64,
+    hash: u64,
+}
+
+impl Fingerprint {
+    fn of(path: &Path, contents: &[u8]) -> io::Result<Self> {
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        Ok(Fingerprint {
+            modified: fs::metadata(path)?.modified()?,
+            len: contents.len() as u64,
+            hash: hasher.finish(),
+        })
+    }
+}
+
+/// A parsed snapshot along with the store bookkeeping saved next to it.
+struct Snapshot {
+    manager: TaskManager,
+    folded: BTreeMap<String, u64>,
+    version: u32,
+}
+
+impl JsonFileStore {
+    pub fn new(path: PathBuf) -> Self {
+        JsonFileStore {
+            path,
+            session: new_session_id(),
+            journal: None,
+            adopted: Vec::new(),
+            folded: BTreeMap::new(),
+            base: TaskManager::new(),
+            seen: None,
+        }
+    }
+
+    fn journal_path(&self, session: &str) -> PathBuf {
+        with_suffix(&self.path, &format!("journal.{}", session))
+    }
+
+    /// The single journal written before sessions had their own.
+    fn legacy_journal_path(&self) -> PathBuf {
+        with_suffix(&self.path, "journal")
+    }
+
+    fn parse(contents: &[u8]) -> Result<Snapshot, LoadError> {
+        let mut doc: Value = serde_json::from_slice(contents)?;
+        let version = schema::migrate(&mut doc)?;
+        let folded = match doc.as_object_mut().and_then(|doc| doc.remove("journals")) {
+            Some(folded) => serde_json::from_value(folded)?,
+            None => BTreeMap::new(),
+        };
+        Ok(Snapshot {
+            manager: serde_json::from_value(doc)?,
+            folded,
+            version,
+        })
+    }
+
+    fn write_snapshot(&mut self, manager: &TaskManager) -> io::Result<()> {
+        let mut doc = serde_json::to_value(schema::versioned(manager)).map_err(io::Error::other)?;
+        doc["journals"] = serde_json::to_value(&self.folded).map_err(io::Error::other)?;
+        let json = serde_json::to_string_pretty(&doc).map_err(io::Error::other)?;
+        write_atomic(&self.path, json.as_bytes())?;
+        self.seen = Some(Fingerprint::of(&self.path, json.as_bytes())?);
+        Ok(())
+    }
+
+    /// Returns the snapshot on disk, along with its fingerprint, if someone
+    /// other than us wrote it since our last load, save or refresh.
+    fn external_change(&self) -> Result<Option<(Snapshot, Fingerprint)>, LoadError> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(LoadError::Io(e)),
+        };
+        if let Some(seen) = &self.seen
+            && seen.modified == metadata.modified()?
+            && seen.len == metadata.len()
+        {
+            return Ok(None);
+        }
+        let contents = fs::read(&self.path)?;
+        let current = Fingerprint::of(&self.path, &contents)?;
+        if self
+            .seen
+            .as_ref()
+            .is_some_and(|seen| seen.hash == current.hash)
+        {
+            return Ok(None);
+        }
+        Ok(Some((Self::parse(&contents)?, current)))
+    }
+
+    /// Merges `theirs` into `manager`, keeping the journal bookkeeping of
+    /// both. Returns whether any of our tasks were renumbered.
+    fn merge(&mut self, manager: &mut TaskManager, theirs: Snapshot) -> bool {
+        let renumbered = manager.merge(&self.base, theirs.manager);
+        for (session, revision) in theirs.folded {
+            let folded = self.folded.entry(session).or_default();
+            *folded = (*folded).max(revision);
+        }
+        renumbered
+    }
+
+    /// Opens this session's journal, locked before it becomes visible under its
+    /// real name so no other session can mistake it for an orphan.
+    fn open_journal(&mut self) -> io::Result<&mut File> {
+        if self.journal.is_none() {
+            let path = self.journal_path(&self.session);
+            let tmp_path = with_suffix(&path, "tmp");
+            let file = lock_file(&tmp_path)?;
+            fs::rename(&tmp_path, &path)?;
+            self.journal = Some(file);
+        }
+        Ok(self.journal.as_mut().unwrap())
+    }
+
+    /// Journals in the same directory that belong to other sessions.
+    fn other_journals(&self) -> io::Result<Vec<(PathBuf, Option<String>)>> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let legacy = self.legacy_journal_path();
+        let prefix = format!("{}.", legacy.file_name().unwrap().to_string_lossy());
+
+        let mut journals = Vec::new();
+        for dir_entry in fs::read_dir(dir)? {
+            let path = dir_entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if path.file_name() == legacy.file_name() {
+                journals.push((path, None));
+            } else if let Some(session) = name.strip_prefix(&prefix)
+                && is_session_id(session)
+                && session != self.session
+            {
+                journals.push((path, Some(session.to_string())));
+            }
+        }
+        journals.sort();
+        Ok(journals)
+    }
+}
+
+impl TaskStore for JsonFileStore {
+    fn load(&mut self) -> Result<TaskManager, LoadError> {
+        let contents = fs::read(&self.path)?;
+        let snapshot = Self::parse(&contents)?;
+        self.seen = Some(Fingerprint::of(&self.path, &contents)?);
+        self.folded = snapshot.folded;
+        if snapshot.version < CURRENT_VERSION {
+            // Upgrade in place, but leave the journals alone: they have not
+            // been replayed yet.
+            let backup = with_suffix(&self.path, &format!("v{}.bak", snapshot.version));
+            fs::copy(&self.path, backup)?;
+            let _lock = lock_file(&with_suffix(&self.path, "lock"))?;
+            self.write_snapshot(&snapshot.manager)?;
+        }
+        self.base = snapshot.manager.clone();
+        Ok(snapshot.manager)
+    }
+
+    fn save(&mut self, manager: &mut TaskManager) -> io::Result<()> {
+        let _lock = lock_file(&with_suffix(&self.path, "lock"))?;
+
+        if let Some((theirs, _)) = self.external_change()? {
+            self.merge(manager, theirs);
+        }
+        if self.journal.is_some() {
+            self.folded.insert(self.session.clone(), manager.revision);
+        }
+        self.folded
+            .retain(|session, _| with_suffix(&self.path, &format!("journal.{}", session)).exists());
+
+        self.write_snapshot(manager)?;
+        // Only safe to drop journal entries once the snapshot containing them
+        // is on disk. The next append starts a fresh journal, so a session
+        // that exits without journaling again leaves nothing behind.
+        if let Some(_journal) = self.journal.take() {
+            fs::remove_file(self.journal_path(&self.session))?;
+        }
+        for (path, _lock) in self.adopted.drain(..) {
+            fs::remove_file(path)?;
+        }
+        self.base = manager.clone();
+        Ok(())
+    }
+
+    fn refresh(&mut self, manager: &mut TaskManager) -> io::Result<bool> {
+        let Some((theirs, current)) = self.external_change()? else {
+            return Ok(false);
+        };
+        let base = theirs.manager.clone();
+        let renumbered = self.merge(manager, theirs);
+        self.base = base;
+        self.seen = Some(current);
+        // Our journal names tasks by their old ids, so fold it into a
+        // snapshot now rather than replay it against the new ones.
+        if renumbered {
+            self.save(manager)?;
+        }
+        Ok(true)
+    }
+
+    fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        append_line(self.open_journal()?, entry)
+    }
+
+    fn journal(&mut self) -> Result<Vec<JournalEntry>, LoadError> {
+        let mut entries = Vec::new();
+        for (path, session) in self.other_journals()? {
+            // A journal that is still locked belongs to a running session.
+            let Some(file) = try_lock_file(&path)? else {
+                continue;
+            };
+            let replayed = match &session {
+                Some(session) => self.folded.get(session).copied().unwrap_or(0),
+                None => self.base.revision,
+            };
+            let journal = read_journal(file.try_clone()?)?;
+            if let (Some(session), Some(last)) = (&session, journal.last()) {
+                self.folded
+                    .insert(session.clone(), last.revision.max(replayed));
+            }
+            entries.extend(journal.into_iter().filter(|e| e.revision > replayed));
+            self.adopted.push((path, file));
+        }
+        Ok(entries)
+    }
+
+    fn salvage(&mut self) -> io::Result<TaskManager> {
+        let bytes = fs::read(&self.path)?;
+        Ok(salvage_json(&String::from_utf8_lossy(&bytes)))
+    }
+
+    fn quarantine(&mut self) -> io::Result<PathBuf> {
+        let suffix = backup_suffix();
+        let backup = with_suffix(&self.path, &suffix);
+        fs::rename(&self.path, &backup)?;
+        for (path, _lock) in self.adopted.drain(..) {
+            fs::rename(&path, with_suffix(&path, &suffix))?;
+        }
+        self.folded.clear();
+        self.seen = None;
+        Ok(backup)
+    }
+
+    fn session(&self) -> &str {
+        &self.session
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{descriptions, manager, run, scratch_dir, state};
+
+    #[test]
+    fn a_saved_file_loads_in_another_session() {
+        let path = scratch_dir("json-save").join("tasks.json");
+        let mut store = JsonFileStore::new(path.clone());
+        assert!(matches!(store.load(), Err(LoadError::Missing)));
+        let mut manager = manager();
+        run(&mut manager, &mut store, "add buy milk +home").unwrap();
+        run(&mut manager, &mut store, "add walk dog").unwrap();
+        manager.save(&mut store).unwrap();
+
+        let loaded = TaskManager::load(&mut JsonFileStore::new(path)).unwrap();
+        assert_eq!(descriptions(&loaded), ["buy milk", "walk dog"]);
+        assert_eq!(loaded.tasks[0].details().tags, ["home"]);
+        assert_eq!((loaded.next_id, loaded.revision), (3, 2));
+    }
+
+    #[test]
+    fn saving_drops_the_journal() {
+        let path = scratch_dir("json-journal-saved").join("tasks.json");
+        let mut store = JsonFileStore::new(path);
+        let mut manager = manager();
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        let journal = store.journal_path(&store.session);
+        assert!(journal.exists());
+        manager.save(&mut store).unwrap();
+        assert!(!journal.exists());
+    }
+
+    #[test]
+    fn a_session_that_never_saved_is_replayed_by_the_next() {
+        let path = scratch_dir("json-journal").join("tasks.json");
+        let mut manager = manager();
+        {
+            let mut store = JsonFileStore::new(path.clone());
+            run(&mut manager, &mut store, "add buy milk").unwrap();
+            manager.save(&mut store).unwrap();
+            run(&mut manager, &mut store, "add walk dog").unwrap();
+            run(&mut manager, &mut store, "complete 1").unwrap();
+        }
+
+        let mut store = JsonFileStore::new(path);
+        let mut loaded = TaskManager::load(&mut store).unwrap();
+        assert_eq!(descriptions(&loaded), ["buy milk", "walk dog"]);
+        assert_eq!(state(&loaded, 1), "completed");
+        loaded.save(&mut store).unwrap();
+        let left: Vec<_> = fs::read_dir(store.path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.contains(".journal"))
+            .collect();
+        assert!(left.is_empty(), "{:?}", left);
+    }
+
+    #[test]
+    fn a_corrupt_file_is_backed_up_and_salvaged() {
+        let path = scratch_dir("json-recover").join("tasks.json");
+        let mut manager = manager();
+        let mut store = JsonFileStore::new(path.clone());
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        run(&mut manager, &mut store, "add walk dog").unwrap();
+        manager.save(&mut store).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &text[..text.find("walk dog").unwrap()]).unwrap();
+
+        let mut store = JsonFileStore::new(path.clone());
+        let error = TaskManager::load(&mut store).unwrap_err();
+        assert!(error.is_corrupt(), "{}", error);
+        let (recovered, backup) = TaskManager::recover(&mut store).unwrap();
+        assert_eq!(descriptions(&recovered), ["buy milk"]);
+        assert_eq!(recovered.next_id, 2);
+        assert!(!path.exists());
+        assert!(
+            fs::read_to_string(backup)
+                .unwrap()
+                .ends_with("\"description\": \"")
+        );
+    }
+
+    #[test]
+    fn a_refresh_that_renumbers_folds_the_journal_in() {
+        let path = scratch_dir("json-renumber").join("tasks.json");
+        let (mut ours, mut theirs) = (manager(), manager());
+        let mut our_store = JsonFileStore::new(path.clone());
+        let mut their_store = JsonFileStore::new(path.clone());
+        their_store.session.push_str("-2");
+        run(&mut ours, &mut our_store, "add plan trip").unwrap();
+        run(&mut ours, &mut our_store, "add book hotel").unwrap();
+        run(&mut ours, &mut our_store, "depend 2 1").unwrap();
+        run(&mut theirs, &mut their_store, "add call mum").unwrap();
+        their_store.save(&mut theirs).unwrap();
+
+        assert!(our_store.refresh(&mut ours).unwrap());
+        assert!(!our_store.journal_path(&our_store.session).exists());
+        drop(our_store);
+        let loaded = TaskManager::load(&mut JsonFileStore::new(path)).unwrap();
+        assert_eq!(
+            descriptions(&loaded),
+            ["call mum", "book hotel", "plan trip"]
+        );
+        assert_eq!(
+            loaded.tasks[loaded.position(2).unwrap()]
+                .details()
+                .blocked_by,
+            [3]
+        );
+    }
+}
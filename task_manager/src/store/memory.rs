@@ -0,0 +1,60 @@
+use std::io;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use super::{JournalEntry, LoadError, TaskStore, salvage_json};
+use crate::TaskManager;
+use crate::schema;
+
+/// Keeps the last saved state in memory only. Round-trips through JSON so it
+/// behaves like the file store.
+#[derive(Default)]
+pub struct MemoryStore {
+    snapshot: Option<String>,
+    journal: Vec<JournalEntry>,
+}
+
+impl TaskStore for MemoryStore {
+    fn load(&mut self) -> Result<TaskManager, LoadError> {
+        let snapshot = self.snapshot.as_deref().ok_or(LoadError::Missing)?;
+        let mut doc: Value = serde_json::from_str(snapshot)?;
+        schema::migrate(&mut doc)?;
+        Ok(serde_json::from_value(doc)?)
+    }
+
+    fn save(&mut self, manager: &mut TaskManager) -> io::Result<()> {
+        let snapshot = serde_json::to_string(&schema::versioned(manager));
+        self.snapshot = Some(snapshot.map_err(io::Error::other)?);
+        self.journal.clear();
+        Ok(())
+    }
+
+    fn refresh(&mut self, _manager: &mut TaskManager) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        self.journal.push(entry.clone());
+        Ok(())
+    }
+
+    fn journal(&mut self) -> Result<Vec<JournalEntry>, LoadError> {
+        Ok(self.journal.clone())
+    }
+
+    fn salvage(&mut self) -> io::Result<TaskManager> {
+        Ok(salvage_json(self.snapshot.as_deref().unwrap_or_default()))
+    }
+
+    fn quarantine(&mut self) -> io::Result<PathBuf> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the memory store has no file to back up",
+        ))
+    }
+
+    fn session(&self) -> &str {
+        ""
+    }
+}
@@ -0,0 +1,429 @@
+mod json;
+mod memory;
+mod sqlite;
+
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use chrono::Local;
+use rusqlite::ErrorCode;
+use serde::{Deserialize, Serialize};
+use serde_json::error::Category;
+
+use crate::audit::Stamp;
+use crate::task::AnyTask;
+use crate::{Command, TaskManager};
+
+pub use json::JsonFileStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+pub const JSON_FILE: &str = "tasks.json";
+pub const SQLITE_FILE: &str = "tasks.db";
+
+/// A command recorded once it has applied cleanly, tagged with the manager
+/// revision it produced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub revision: u64,
+    pub command: Command,
+    /// Missing from entries journaled before the audit log existed.
+    #[serde(default)]
+    pub stamp: Option<Stamp>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// Nothing has been saved yet.
+    Missing,
+    Io(io::Error),
+    /// The saved data could not be parsed at all, e.g. a truncated file.
+    Corrupt(String),
+    /// The saved data parsed but does not have a shape we know how to read.
+    UnknownSchema(String),
+}
+
+impl LoadError {
+    /// Whether the data itself is bad, as opposed to being unreachable.
+    pub fn is_corrupt(&self) -> bool {
+        matches!(self, LoadError::Corrupt(_) | LoadError::UnknownSchema(_))
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Missing => write!(f, "No saved tasks found"),
+            LoadError::Io(e) => write!(f, "I/O error: {}", e),
+            LoadError::Corrupt(msg) => write!(f, "Saved tasks are corrupt: {}", msg),
+            LoadError::UnknownSchema(msg) => {
+                write!(f, "Saved tasks have an unknown format: {}", msg)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => LoadError::Missing,
+            io::ErrorKind::InvalidData => LoadError::Corrupt(e.to_string()),
+            _ => LoadError::Io(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(e: serde_json::Error) -> Self {
+        match e.classify() {
+            Category::Data => LoadError::UnknownSchema(e.to_string()),
+            Category::Syntax | Category::Eof => LoadError::Corrupt(e.to_string()),
+            Category::Io => LoadError::Io(e.into()),
+        }
+    }
+}
+
+impl From<LoadError> for io::Error {
+    fn from(e: LoadError) -> Self {
+        match e {
+            LoadError::Io(e) => e,
+            LoadError::Missing => io::Error::new(io::ErrorKind::NotFound, e.to_string()),
+            _ => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for LoadError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e.sqlite_error_code() {
+            Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => {
+                LoadError::Corrupt(e.to_string())
+            }
+            _ => LoadError::Io(io::Error::other(e)),
+        }
+    }
+}
+
+/// Somewhere a `TaskManager` can be loaded from and saved to.
+///
+/// Between snapshots, every mutating command is appended to a journal so that
+/// it survives a crash before the next `save`. Each running session keeps its
+/// own journal, so several processes can share one store.
+pub trait TaskStore: Send {
+    fn load(&mut self) -> Result<TaskManager, LoadError>;
+    /// Writes a full snapshot and clears this session's journal.
+    ///
+    /// If another process saved since our last load or save, its snapshot is
+    /// first merged into `manager`, so both sessions converge on the result.
+    fn save(&mut self, manager: &mut TaskManager) -> io::Result<()>;
+    /// Merges whatever another process saved since our last load, save or
+    /// refresh into `manager`, without writing anything. Returns whether
+    /// there was anything to merge.
+    fn refresh(&mut self, manager: &mut TaskManager) -> io::Result<bool>;
+    /// Durably appends one entry to this session's journal.
+    fn append(&mut self, entry: &JournalEntry) -> io::Result<()>;
+    /// Entries that are not part of the loaded snapshot and were left behind
+    /// by sessions that are no longer running, oldest first per session.
+    fn journal(&mut self) -> Result<Vec<JournalEntry>, LoadError>;
+    /// Builds a manager from every task that can still be read out of data
+    /// that failed to `load`.
+    fn salvage(&mut self) -> io::Result<TaskManager>;
+    /// Moves the saved data aside to a timestamped backup so the next `save`
+    /// starts fresh, returning where it went.
+    fn quarantine(&mut self) -> io::Result<PathBuf>;
+    /// Names this session in the audit log; empty for stores without one.
+    fn session(&self) -> &str;
+}
+
+pub type SharedStore = Arc<Mutex<Box<dyn TaskStore>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreKind {
+    Json,
+    Memory,
+    Sqlite,
+}
+
+impl TryFrom<&str> for StoreKind {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "json" => Ok(StoreKind::Json),
+            "memory" => Ok(StoreKind::Memory),
+            "sqlite" => Ok(StoreKind::Sqlite),
+            _ => Err(format!(
+                "Unknown store '{}'. Expected json, memory or sqlite.",
+                value
+            )),
+        }
+    }
+}
+
+pub fn open(kind: StoreKind, path: Option<PathBuf>) -> io::Result<Box<dyn TaskStore>> {
+    match kind {
+        StoreKind::Json => Ok(Box::new(JsonFileStore::new(
+            path.unwrap_or_else(|| PathBuf::from(JSON_FILE)),
+        ))),
+        StoreKind::Memory => Ok(Box::new(MemoryStore::default())),
+        StoreKind::Sqlite => Ok(Box::new(SqliteStore::open(
+            path.unwrap_or_else(|| PathBuf::from(SQLITE_FILE)),
+        )?)),
+    }
+}
+
+/// Where the REPL keeps its command history: next to the data file, or
+/// nowhere for the memory store.
+pub fn history_path(kind: StoreKind, path: Option<&Path>) -> Option<PathBuf> {
+    let default = match kind {
+        StoreKind::Json => JSON_FILE,
+        StoreKind::Sqlite => SQLITE_FILE,
+        StoreKind::Memory => return None,
+    };
+    Some(with_suffix(path.unwrap_or(Path::new(default)), "history"))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Identifies this process's journal among those of other sessions.
+fn new_session_id() -> String {
+    format!("{}-{}", process::id(), Local::now().timestamp_millis())
+}
+
+fn is_session_id(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_digit() || c == '-')
+}
+
+/// Takes the exclusive advisory lock on `path`, creating it if needed, and
+/// waits for any other holder to let go. Dropping the file releases it.
+fn lock_file(path: &Path) -> io::Result<File> {
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Like `lock_file`, but gives up with `None` if another process holds it.
+fn try_lock_file(path: &Path) -> io::Result<Option<File>> {
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(fs::TryLockError::WouldBlock) => Ok(None),
+        Err(fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Reads journal lines written by `append_line`.
+fn read_journal(file: File) -> Result<Vec<JournalEntry>, LoadError> {
+    let lines = BufReader::new(file)
+        .lines()
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut entries = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            // A crash mid-append leaves a torn final line; that command was
+            // never acknowledged, so it is safe to drop.
+            Err(_) if i + 1 == lines.len() => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(entries)
+}
+
+fn append_line(file: &mut File, entry: &JournalEntry) -> io::Result<()> {
+    let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    file.sync_data()
+}
+
+fn backup_suffix() -> String {
+    format!("corrupt-{}", Local::now().format("%Y%m%d-%H%M%S"))
+}
+
+/// Picks every `AnyTask` object out of `text`, however mangled the document
+/// around them is. The first occurrence of each id wins. The undo history is
+/// left out, since the old copies of tasks it holds are not current, and so
+/// is the audit log saved after it.
+fn salvage_json(text: &str) -> TaskManager {
+    let mut manager = TaskManager::new();
+    let (text, rest) = text.split_at(tasks_end(text));
+    let mut seen = HashSet::new();
+    let mut pos = 0;
+    while let Some(offset) = text[pos..].find('{') {
+        let start = pos + offset;
+        let mut stream = serde_json::Deserializer::from_str(&text[start..]).into_iter::<AnyTask>();
+        match stream.next() {
+            Some(Ok(task)) => {
+                if seen.insert(task.id()) {
+                    manager.tasks.push(task);
+                }
+                pos = start + stream.byte_offset();
+            }
+            _ => pos = start + 1,
+        }
+    }
+
+    let max_id = manager.tasks.iter().map(AnyTask::id).max().unwrap_or(0);
+    let find_number = |key| find_number(text, key).or_else(|| find_number(rest, key));
+    let saved_next_id = find_number("next_id").unwrap_or(0) as u32;
+    manager.next_id = saved_next_id.max(max_id + 1);
+    manager.revision = find_number("revision").unwrap_or(0);
+    manager
+}
+
+/// Where the top-level `tasks` array of a saved document ends: just past its
+/// closing bracket, or where the `history` key starts if the array never
+/// closes, or else the end of `text`. Strings are skipped over, so a
+/// task that mentions "history" does not cut the tasks short.
+fn tasks_end(text: &str) -> usize {
+    let mut depth = 0;
+    let mut in_tasks = false;
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let mut escaped = false;
+                let end = chars
+                    .by_ref()
+                    .find(|&(_, c)| {
+                        let closes = c == '"' && !escaped;
+                        escaped = c == '\\' && !escaped;
+                        closes
+                    })
+                    .map_or(text.len(), |(end, _)| end);
+                let is_key = text[end..]
+                    .get(1..)
+                    .is_some_and(|rest| rest.trim_start().starts_with(':'));
+                // Tasks have no `history` field, so that key is the top-level
+                // one even where mangled brackets have thrown off the depth.
+                match &text[i + 1..end] {
+                    "tasks" if depth == 1 && is_key => in_tasks = true,
+                    "history" if is_key => return i,
+                    _ => {}
+                }
+            }
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth -= 1;
+                if in_tasks && depth == 1 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+fn find_number(text: &str, key: &str) -> Option<u64> {
+    let rest = &text[text.find(&format!("\"{}\"", key))? + key.len() + 2..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+/// Replaces `path` with `contents` so that readers only ever see the old or
+/// the new file, never a partial write.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp_path = with_suffix(path, "tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)?;
+    sync_parent_dir(path)
+}
+
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema;
+    use crate::tests::{descriptions, manager, run};
+
+    /// The document the JSON store would write for these commands.
+    fn saved(lines: &[&str]) -> String {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        for line in lines {
+            run(&mut manager, &mut store, line).unwrap();
+        }
+        serde_json::to_string_pretty(&schema::versioned(&manager)).unwrap()
+    }
+
+    #[test]
+    fn salvage_keeps_every_whole_task_of_a_truncated_file() {
+        let text = saved(&["add buy milk", "add walk dog", "add file taxes"]);
+        let cut = text.find("file taxes").unwrap();
+        let manager = salvage_json(&text[..cut]);
+        assert_eq!(descriptions(&manager), ["buy milk", "walk dog"]);
+        assert_eq!(manager.next_id, 3);
+    }
+
+    #[test]
+    fn salvage_reads_past_tasks_that_mention_history() {
+        let text = saved(&["add history", "add walk dog +history", "add file taxes"]);
+        let manager = salvage_json(&text);
+        assert_eq!(
+            descriptions(&manager),
+            ["history", "walk dog", "file taxes"]
+        );
+        assert_eq!(manager.revision, 3);
+    }
+
+    #[test]
+    fn salvage_leaves_out_old_copies_in_the_undo_history() {
+        let text = saved(&["add buy milk", "add walk dog", "delete 2"]);
+        let manager = salvage_json(&text);
+        assert_eq!(descriptions(&manager), ["buy milk"]);
+        assert_eq!(manager.next_id, 3);
+
+        // Without its closing bracket the task list runs up to the history.
+        let text = text.replacen("\n  ],", "\n  ", 1);
+        assert_eq!(descriptions(&salvage_json(&text)), ["buy milk"]);
+    }
+
+    #[test]
+    fn salvage_of_garbage_is_empty() {
+        for text in ["", "not json", "{\"tasks\": [", "[{]"] {
+            let manager = salvage_json(text);
+            assert!(manager.tasks.is_empty(), "{}", text);
+            assert_eq!(manager.next_id, 1);
+        }
+    }
+}
@@ -0,0 +1,555 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params};
+use serde_json::Value;
+
+use super::{
+    JournalEntry, LoadError, TaskStore, backup_suffix, is_session_id, lock_file, new_session_id,
+    try_lock_file, with_suffix,
+};
+use crate::TaskManager;
+use crate::audit::Event;
+use crate::schema::{self, CURRENT_VERSION};
+use crate::task::AnyTask;
+
+/// One row per task, so a save only touches the tasks that changed since the
+/// previous one, and one row per audit event, appended as they happen.
+/// SQLite's own locking serializes saves between processes, and
+/// each running session holds a `<file>.<session>.lock` next to the database
+/// so other sessions leave its journal rows alone.
+pub struct SqliteStore {
+    path: PathBuf,
+    conn: Connection,
+    session: String,
+    /// Held for as long as this session lives, once it has journaled anything.
+    session_lock: Option<File>,
+    /// Dead sessions whose journal rows were replayed into this one. Their rows
+    /// are deleted by the next save.
+    adopted: Vec<(String, Option<File>)>,
+    /// Row contents as of the last load/save, keyed by task id.
+    saved: HashMap<u32, (usize, String)>,
+    /// Audit events that already have a row.
+    logged: HashSet<Event>,
+    /// The snapshot as of the last load/save, used as the merge base.
+    base: TaskManager,
+    /// Bumped by every save from any process.
+    generation: Option<u64>,
+}
+
+type Row = (u32, usize, String);
+
+struct Snapshot {
+    manager: TaskManager,
+    version: u32,
+    rows: Vec<Row>,
+    logged: HashSet<Event>,
+    generation: Option<u64>,
+}
+
+impl SqliteStore {
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let conn = connect(&path).map_err(io::Error::other)?;
+        Ok(SqliteStore {
+            path,
+            conn,
+            session: new_session_id(),
+            session_lock: None,
+            adopted: Vec::new(),
+            saved: HashMap::new(),
+            logged: HashSet::new(),
+            base: TaskManager::new(),
+            generation: None,
+        })
+    }
+
+    fn session_lock_path(&self, session: &str) -> PathBuf {
+        with_suffix(&self.path, &format!("{}.lock", session))
+    }
+
+    /// Sessions that have left a lock file next to the database.
+    fn locked_sessions(&self) -> io::Result<BTreeSet<String>> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let prefix = format!("{}.", self.path.file_name().unwrap().to_string_lossy());
+        let mut sessions = BTreeSet::new();
+        for dir_entry in fs::read_dir(dir)? {
+            let name = dir_entry?.file_name().to_string_lossy().into_owned();
+            let session = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".lock"));
+            if let Some(session) = session.filter(|session| is_session_id(session)) {
+                sessions.insert(session.to_string());
+            }
+        }
+        Ok(sessions)
+    }
+
+    /// Writes `manager` in one transaction, first merging in whatever another
+    /// process saved since our last load or save.
+    fn write(&mut self, manager: &mut TaskManager, clear_journal: bool) -> Result<(), LoadError> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let generation: Option<u64> = meta(&tx, "generation")?;
+        if generation != self.generation
+            && let Some(theirs) = read(&tx)?
+        {
+            manager.merge(&self.base, theirs.manager);
+            self.saved = by_id(theirs.rows);
+            self.logged = theirs.logged;
+        }
+
+        let mut current = HashMap::with_capacity(manager.tasks.len());
+        for (position, task) in manager.tasks.iter().enumerate() {
+            current.insert(task.id(), (position, serde_json::to_string(task)?));
+        }
+        for id in self.saved.keys().filter(|id| !current.contains_key(id)) {
+            tx.execute("DELETE FROM tasks WHERE id = ?1", params![id])?;
+        }
+        for (id, row) in &current {
+            if self.saved.get(id) != Some(row) {
+                tx.execute(
+                    "INSERT OR REPLACE INTO tasks (id, position, data) VALUES (?1, ?2, ?3)",
+                    params![id, row.0, row.1],
+                )?;
+            }
+        }
+        let mut logged = Vec::new();
+        for event in manager.audit.iter().filter(|e| !self.logged.contains(e)) {
+            tx.execute(
+                "INSERT INTO audit (task, at, event) VALUES (?1, ?2, ?3)",
+                params![
+                    event.task,
+                    event.stamp.at.to_string(),
+                    serde_json::to_string(event)?
+                ],
+            )?;
+            logged.push(event.clone());
+        }
+        // Databases from before the audit table kept the whole log here; it
+        // has just been copied over.
+        tx.execute("DELETE FROM meta WHERE key = 'audit'", [])?;
+        let generation = generation.unwrap_or(0) + 1;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value)
+             VALUES ('next_id', ?1), ('revision', ?2), ('schema_version', ?3), ('generation', ?4),
+                    ('history', ?5)",
+            params![
+                manager.next_id.to_string(),
+                manager.revision.to_string(),
+                CURRENT_VERSION.to_string(),
+                generation.to_string(),
+                serde_json::to_string(&manager.history)?
+            ],
+        )?;
+        if clear_journal {
+            tx.execute(
+                "DELETE FROM journal WHERE session = ?1",
+                params![self.session],
+            )?;
+            for (session, _) in &self.adopted {
+                tx.execute("DELETE FROM journal WHERE session = ?1", params![session])?;
+            }
+        }
+        tx.commit()?;
+
+        self.saved = current;
+        self.logged.extend(logged);
+        self.generation = Some(generation);
+        self.base = manager.clone();
+        if clear_journal {
+            for (session, lock) in self.adopted.drain(..) {
+                if lock.is_some() {
+                    let path = with_suffix(&self.path, &format!("{}.lock", session));
+                    let _ = fs::remove_file(path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn reread(&mut self, manager: &mut TaskManager) -> Result<bool, LoadError> {
+        let tx = self.conn.transaction()?;
+        let generation: Option<u64> = meta(&tx, "generation")?;
+        if generation == self.generation {
+            return Ok(false);
+        }
+        let Some(theirs) = read(&tx)? else {
+            return Ok(false);
+        };
+        drop(tx);
+        let renumbered = manager.merge(&self.base, theirs.manager.clone());
+        self.saved = by_id(theirs.rows);
+        self.logged = theirs.logged;
+        self.generation = theirs.generation;
+        self.base = theirs.manager;
+        // Our journal rows name tasks by their old ids, so fold them into the
+        // tables now rather than replay them against the new ones.
+        if renumbered {
+            self.write(manager, true)?;
+        }
+        Ok(true)
+    }
+}
+
+fn connect(path: &Path) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tasks (
+             id       INTEGER PRIMARY KEY,
+             position INTEGER NOT NULL,
+             data     TEXT NOT NULL
+         );
+         CREATE TABLE IF NOT EXISTS meta (
+             key   TEXT PRIMARY KEY,
+             value TEXT NOT NULL
+         );
+         CREATE TABLE IF NOT EXISTS audit (
+             task  INTEGER NOT NULL,
+             at    TEXT NOT NULL,
+             event TEXT NOT NULL
+         );
+         CREATE INDEX IF NOT EXISTS audit_by_task ON audit (task, at);
+         CREATE TABLE IF NOT EXISTS journal (
+             seq     INTEGER PRIMARY KEY AUTOINCREMENT,
+             entry   TEXT NOT NULL,
+             session TEXT NOT NULL DEFAULT ''
+         );",
+    )?;
+    // Journals from before sessions existed lack the column.
+    let has_session: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('journal') WHERE name = 'session'",
+        [],
+        |row| row.get(0),
+    )?;
+    if has_session == 0 {
+        conn.execute_batch("ALTER TABLE journal ADD COLUMN session TEXT NOT NULL DEFAULT ''")?;
+    }
+    Ok(conn)
+}
+
+/// The `meta` value under `key`, or `None` if there is none. A value that
+/// does not parse means the database is corrupt, not empty.
+fn meta<T: FromStr>(conn: &Connection, key: &str) -> Result<Option<T>, LoadError> {
+    let value = conn
+        .query_row(
+            "SELECT value FROM meta WHERE key = ?1",
+            params![key],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+    value
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| LoadError::Corrupt(format!("bad {} '{}'", key, value)))
+        })
+        .transpose()
+}
+
+fn rows(conn: &Connection) -> rusqlite::Result<Vec<Row>> {
+    let mut stmt = conn.prepare("SELECT id, position, data FROM tasks ORDER BY position")?;
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect()
+}
+
+/// Every event in the audit table, oldest first.
+fn events(conn: &Connection) -> Result<Vec<Event>, LoadError> {
+    let mut stmt = conn.prepare("SELECT event FROM audit ORDER BY at, rowid")?;
+    let rows = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut events = Vec::with_capacity(rows.len());
+    for row in rows {
+        events.push(serde_json::from_str(&row)?);
+    }
+    Ok(events)
+}
+
+fn by_id(rows: Vec<Row>) -> HashMap<u32, (usize, String)> {
+    rows.into_iter()
+        .map(|(id, position, data)| (id, (position, data)))
+        .collect()
+}
+
+/// Reassembles the same document the JSON store would hold, so both stores
+/// share one migration chain.
+fn read(conn: &Connection) -> Result<Option<Snapshot>, LoadError> {
+    let Some(next_id) = meta::<u32>(conn, "next_id")? else {
+        return Ok(None);
+    };
+    let revision: Option<u64> = meta(conn, "revision")?;
+    let version: Option<u32> = meta(conn, "schema_version")?;
+    let generation = meta(conn, "generation")?;
+    let history: Option<String> = meta(conn, "history")?;
+    let legacy_audit: Option<String> = meta(conn, "audit")?;
+    let logged = events(conn)?;
+
+    let rows = rows(conn)?;
+    let mut tasks = Vec::with_capacity(rows.len());
+    for (_, _, data) in &rows {
+        tasks.push(serde_json::from_str::<Value>(data)?);
+    }
+    let mut doc = serde_json::json!({ "tasks": tasks, "next_id": next_id });
+    if let Some(revision) = revision {
+        doc["revision"] = revision.into();
+    }
+    if let Some(version) = version {
+        doc["schema_version"] = version.into();
+    }
+    if let Some(history) = history {
+        doc["history"] = serde_json::from_str(&history)?;
+    }
+    let mut audit = match legacy_audit {
+        Some(audit) => serde_json::from_str(&audit)?,
+        None => Vec::new(),
+    };
+    audit.extend(logged.iter().cloned());
+    if !audit.is_empty() {
+        doc["audit"] = serde_json::to_value(audit)?;
+    }
+    let version = schema::migrate(&mut doc)?;
+    Ok(Some(Snapshot {
+        manager: serde_json::from_value(doc)?,
+        version,
+        rows,
+        logged: logged.into_iter().collect(),
+        generation,
+    }))
+}
+
+impl TaskStore for SqliteStore {
+    fn load(&mut self) -> Result<TaskManager, LoadError> {
+        let snapshot = read(&self.conn)?.ok_or(LoadError::Missing)?;
+        let mut manager = snapshot.manager;
+        self.saved = by_id(snapshot.rows);
+        self.logged = snapshot.logged;
+        self.generation = snapshot.generation;
+        self.base = manager.clone();
+        if snapshot.version < CURRENT_VERSION {
+            let backup = with_suffix(&self.path, &format!("v{}.bak", snapshot.version));
+            fs::copy(&self.path, backup)?;
+            self.saved.clear();
+            self.write(&mut manager, false)?;
+        }
+        Ok(manager)
+    }
+
+    fn save(&mut self, manager: &mut TaskManager) -> io::Result<()> {
+        Ok(self.write(manager, true)?)
+    }
+
+    fn refresh(&mut self, manager: &mut TaskManager) -> io::Result<bool> {
+        Ok(self.reread(manager)?)
+    }
+
+    fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        if self.session_lock.is_none() {
+            self.session_lock = Some(lock_file(&self.session_lock_path(&self.session))?);
+        }
+        let entry = serde_json::to_string(entry).map_err(io::Error::other)?;
+        self.conn
+            .execute(
+                "INSERT INTO journal (entry, session) VALUES (?1, ?2)",
+                params![entry, self.session],
+            )
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+
+    fn journal(&mut self) -> Result<Vec<JournalEntry>, LoadError> {
+        let mut sessions = self.locked_sessions()?;
+        let mut stmt = self.conn.prepare("SELECT DISTINCT session FROM journal")?;
+        for session in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            sessions.insert(session?);
+        }
+        drop(stmt);
+        sessions.remove(&self.session);
+
+        let mut entries = Vec::new();
+        for session in sessions {
+            // Rows from before sessions existed have no owner left to wait for.
+            let lock = if session.is_empty() {
+                None
+            } else {
+                match try_lock_file(&self.session_lock_path(&session))? {
+                    Some(lock) => Some(lock),
+                    None => continue,
+                }
+            };
+            let mut stmt = self
+                .conn
+                .prepare("SELECT entry FROM journal WHERE session = ?1 ORDER BY seq")?;
+            let rows = stmt
+                .query_map(params![session], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            for row in rows {
+                entries.push(serde_json::from_str(&row)?);
+            }
+            self.adopted.push((session, lock));
+        }
+        Ok(entries)
+    }
+
+    fn salvage(&mut self) -> io::Result<TaskManager> {
+        let rows = rows(&self.conn).map_err(io::Error::other)?;
+        let mut manager = TaskManager::new();
+        manager.tasks = rows
+            .iter()
+            .filter_map(|(_, _, data)| serde_json::from_str(data).ok())
+            .collect();
+        let max_id = manager.tasks.iter().map(AnyTask::id).max().unwrap_or(0);
+        let saved_next_id = meta(&self.conn, "next_id").ok().flatten().unwrap_or(0);
+        manager.next_id = saved_next_id.max(max_id + 1);
+        manager.revision = meta(&self.conn, "revision").ok().flatten().unwrap_or(0);
+        Ok(manager)
+    }
+
+    fn quarantine(&mut self) -> io::Result<PathBuf> {
+        let backup = with_suffix(&self.path, &backup_suffix());
+        // Close the database before moving it out from under the connection.
+        self.conn = Connection::open_in_memory().map_err(io::Error::other)?;
+        fs::rename(&self.path, &backup)?;
+        self.conn = connect(&self.path).map_err(io::Error::other)?;
+        self.saved.clear();
+        self.logged.clear();
+        self.adopted.clear();
+        self.base = TaskManager::new();
+        self.generation = None;
+        Ok(backup)
+    }
+
+    fn session(&self) -> &str {
+        &self.session
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{descriptions, manager, run, scratch_dir, state};
+
+    fn audit_rows(store: &SqliteStore) -> usize {
+        store
+            .conn
+            .query_row("SELECT COUNT(*) FROM audit", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn a_saved_database_loads_in_another_session() {
+        let path = scratch_dir("sqlite-save").join("tasks.db");
+        let mut store = SqliteStore::open(path.clone()).unwrap();
+        assert!(matches!(store.load(), Err(LoadError::Missing)));
+        let mut manager = manager();
+        run(&mut manager, &mut store, "add buy milk !high").unwrap();
+        run(&mut manager, &mut store, "add walk dog").unwrap();
+        manager.save(&mut store).unwrap();
+        run(&mut manager, &mut store, "delete 1").unwrap();
+        manager.save(&mut store).unwrap();
+
+        let loaded = TaskManager::load(&mut SqliteStore::open(path).unwrap()).unwrap();
+        assert_eq!(descriptions(&loaded), ["walk dog"]);
+        assert_eq!((loaded.next_id, loaded.revision), (3, 3));
+    }
+
+    #[test]
+    fn a_session_that_never_saved_is_replayed_by_the_next() {
+        let path = scratch_dir("sqlite-journal").join("tasks.db");
+        let mut manager = manager();
+        {
+            let mut store = SqliteStore::open(path.clone()).unwrap();
+            run(&mut manager, &mut store, "add buy milk").unwrap();
+            manager.save(&mut store).unwrap();
+            run(&mut manager, &mut store, "add walk dog").unwrap();
+            run(&mut manager, &mut store, "complete 1").unwrap();
+        }
+
+        let mut store = SqliteStore::open(path).unwrap();
+        let mut loaded = TaskManager::load(&mut store).unwrap();
+        assert_eq!(descriptions(&loaded), ["buy milk", "walk dog"]);
+        assert_eq!(state(&loaded, 1), "completed");
+        assert_eq!(loaded.revision, 3);
+        loaded.save(&mut store).unwrap();
+        let rows: i64 = store
+            .conn
+            .query_row("SELECT COUNT(*) FROM journal", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[test]
+    fn an_unreadable_counter_is_corruption_not_an_empty_store() {
+        let path = scratch_dir("sqlite-corrupt-meta").join("tasks.db");
+        let mut manager = manager();
+        {
+            let mut store = SqliteStore::open(path.clone()).unwrap();
+            run(&mut manager, &mut store, "add buy milk").unwrap();
+            run(&mut manager, &mut store, "add walk dog").unwrap();
+            manager.save(&mut store).unwrap();
+            store
+                .conn
+                .execute("UPDATE meta SET value = 'x' WHERE key = 'next_id'", [])
+                .unwrap();
+        }
+
+        let mut store = SqliteStore::open(path).unwrap();
+        let error = TaskManager::load(&mut store).unwrap_err();
+        assert!(error.is_corrupt(), "{}", error);
+        let (recovered, backup) = TaskManager::recover(&mut store).unwrap();
+        assert_eq!(descriptions(&recovered), ["buy milk", "walk dog"]);
+        assert_eq!(recovered.next_id, 3);
+        assert!(backup.exists());
+    }
+
+    #[test]
+    fn audit_events_are_appended_once_each() {
+        let path = scratch_dir("sqlite-audit").join("tasks.db");
+        let mut store = SqliteStore::open(path).unwrap();
+        let mut manager = manager();
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        manager.save(&mut store).unwrap();
+        assert_eq!(audit_rows(&store), 1);
+        run(&mut manager, &mut store, "complete 1").unwrap();
+        manager.save(&mut store).unwrap();
+        manager.save(&mut store).unwrap();
+        assert_eq!(audit_rows(&store), manager.audit.len());
+        assert_eq!(audit_rows(&store), 2);
+    }
+
+    #[test]
+    fn a_log_kept_in_meta_moves_into_the_audit_table() {
+        let path = scratch_dir("sqlite-legacy-audit").join("tasks.db");
+        let mut manager = manager();
+        {
+            let mut store = SqliteStore::open(path.clone()).unwrap();
+            run(&mut manager, &mut store, "add buy milk").unwrap();
+            run(&mut manager, &mut store, "complete 1").unwrap();
+            manager.save(&mut store).unwrap();
+            let audit = serde_json::to_string(&manager.audit).unwrap();
+            store.conn.execute("DELETE FROM audit", []).unwrap();
+            store
+                .conn
+                .execute(
+                    "INSERT INTO meta (key, value) VALUES ('audit', ?1)",
+                    params![audit],
+                )
+                .unwrap();
+        }
+
+        let mut store = SqliteStore::open(path).unwrap();
+        let mut loaded = TaskManager::load(&mut store).unwrap();
+        assert_eq!(loaded.audit, manager.audit);
+        loaded.save(&mut store).unwrap();
+        assert_eq!(audit_rows(&store), 2);
+        assert_eq!(meta::<String>(&store.conn, "audit").unwrap(), None);
+    }
+}
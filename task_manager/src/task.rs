@@ -0,0 +1,509 @@
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::recurrence::Recurrence;
+use crate::timetrack::{self, TimeEntry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Pending;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct InProgress;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Blocked;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Completed;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Cancelled;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Archived;
+
+/// States a task can be completed from.
+pub trait Completable {}
+impl Completable for Pending {}
+impl Completable for InProgress {}
+
+/// States a task can be blocked from.
+pub trait Blockable {}
+impl Blockable for Pending {}
+impl Blockable for InProgress {}
+
+/// States a task can be cancelled from.
+pub trait Cancellable {}
+impl Cancellable for Pending {}
+impl Cancellable for InProgress {}
+impl Cancellable for Blocked {}
+
+/// Finished states, which can be reopened or archived.
+pub trait Finished {}
+impl Finished for Completed {}
+impl Finished for Cancelled {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+impl Priority {
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+        }
+    }
+}
+
+impl TryFrom<&str> for Priority {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "low" | "l" => Ok(Priority::Low),
+            "medium" | "med" | "m" => Ok(Priority::Medium),
+            "high" | "h" => Ok(Priority::High),
+            _ => Err(format!("Unknown priority '{}'", value)),
+        }
+    }
+}
+
+/// Something [`Details::clear`] can take off a task, which
+/// [`Details::update`] only ever sets or adds to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Field {
+    Priority,
+    Due,
+    Parent,
+    Recurrence,
+    Remind,
+    Tag(String),
+}
+
+/// Everything about a task other than its id and state.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Details {
+    pub description: String,
+    #[serde(default)]
+    pub priority: Option<Priority>,
+    #[serde(default)]
+    pub due: Option<NaiveDate>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Free-form text, one note per line.
+    #[serde(default)]
+    pub notes: String,
+    /// The task this is a subtask of.
+    #[serde(default)]
+    pub parent: Option<u32>,
+    /// Tasks that have to be finished before this one can be completed.
+    #[serde(default)]
+    pub blocked_by: Vec<u32>,
+    /// Completing a recurring task adds its next occurrence.
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+    /// When to remind about the task while the REPL is open.
+    #[serde(default)]
+    pub remind: Option<NaiveDateTime>,
+    /// Finished work intervals and manually logged time.
+    #[serde(default)]
+    pub time_log: Vec<TimeEntry>,
+    /// When the running timer on this task was started.
+    #[serde(default)]
+    pub timer_since: Option<NaiveDateTime>,
+}
+
+impl Details {
+    /// Overwrites whatever `changes` sets: a non-empty description, a
+    /// priority, due date, reminder or recurrence, and any tags not already
+    /// present.
+    pub fn update(&mut self, changes: Details) {
+        if !changes.description.is_empty() {
+            self.description = changes.description;
+        }
+        self.priority = changes.priority.or(self.priority);
+        self.due = changes.due.or(self.due);
+        self.recurrence = changes.recurrence.or(self.recurrence.take());
+        self.remind = changes.remind.or(self.remind);
+        for tag in changes.tags {
+            if !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+    }
+
+    /// Empties `field`, or takes the tag off.
+    pub fn clear(&mut self, field: &Field) {
+        match field {
+            Field::Priority => self.priority = None,
+            Field::Due => self.due = None,
+            Field::Parent => self.parent = None,
+            Field::Recurrence => self.recurrence = None,
+            Field::Remind => self.remind = None,
+            Field::Tag(tag) => self.tags.retain(|t| t != tag),
+        }
+    }
+
+    /// Time logged so far, not counting a running timer.
+    pub fn logged_seconds(&self) -> u64 {
+        self.time_log.iter().map(|entry| entry.seconds).sum()
+    }
+
+    /// Names of the fields that differ between `self` and `other`.
+    pub fn changed_fields(&self, other: &Details) -> Vec<&'static str> {
+        let fields = [
+            ("description", self.description != other.description),
+            ("priority", self.priority != other.priority),
+            ("due", self.due != other.due),
+            ("tags", self.tags != other.tags),
+            ("notes", self.notes != other.notes),
+            ("parent", self.parent != other.parent),
+            ("dependencies", self.blocked_by != other.blocked_by),
+            ("recurrence", self.recurrence != other.recurrence),
+            ("reminder", self.remind != other.remind),
+            (
+                "time",
+                self.time_log != other.time_log || self.timer_since != other.timer_since,
+            ),
+        ];
+        fields
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    pub fn add_note(&mut self, note: &str) {
+        if !self.notes.is_empty() {
+            self.notes.push('\n');
+        }
+        self.notes.push_str(note);
+    }
+}
+
+/// When a task was added, last changed and completed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stamps {
+    #[serde(default)]
+    pub created: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub updated: Option<NaiveDateTime>,
+    /// Cleared again when the task is reopened.
+    #[serde(default)]
+    pub completed: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Task<State> {
+    pub(crate) id: u32,
+    /// Boxed so moving a task between states, which hands it back on
+    /// failure, only copies a pointer.
+    #[serde(flatten)]
+    pub(crate) details: Box<Details>,
+    #[serde(flatten)]
+    pub(crate) stamps: Stamps,
+    #[serde(skip)]
+    _phantom: PhantomData<State>,
+}
+
+impl<State> Task<State> {
+    fn into_state<Next>(self) -> Task<Next> {
+        Task {
+            id: self.id,
+            details: self.details,
+            stamps: self.stamps,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl Task<Pending> {
+    pub fn new(id: u32, details: Details) -> Self {
+        Task {
+            id,
+            details: Box::new(details),
+            stamps: Stamps::default(),
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn start(self) -> Task<InProgress> {
+        self.into_state()
+    }
+}
+
+impl<State: Completable> Task<State> {
+    pub fn complete(self) -> Task<Completed> {
+        self.into_state()
+    }
+}
+
+impl<State: Blockable> Task<State> {
+    pub fn block(self) -> Task<Blocked> {
+        self.into_state()
+    }
+}
+
+impl Task<Blocked> {
+    pub fn unblock(self) -> Task<Pending> {
+        self.into_state()
+    }
+}
+
+impl<State: Cancellable> Task<State> {
+    pub fn cancel(self) -> Task<Cancelled> {
+        self.into_state()
+    }
+}
+
+impl<State: Finished> Task<State> {
+    pub fn reopen(self) -> Task<Pending> {
+        self.into_state()
+    }
+
+    pub fn archive(self) -> Task<Archived> {
+        self.into_state()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AnyTask {
+    Pending(Task<Pending>),
+    InProgress(Task<InProgress>),
+    Blocked(Task<Blocked>),
+    Completed(Task<Completed>),
+    Cancelled(Task<Cancelled>),
+    Archived(Task<Archived>),
+}
+
+/// Runs `$body` with `$task` bound to the inner `Task<_>`, whatever its state.
+macro_rules! with_task {
+    ($any:expr, $task:ident => $body:expr) => {
+        match $any {
+            AnyTask::Pending($task) => $body,
+            AnyTask::InProgress($task) => $body,
+            AnyTask::Blocked($task) => $body,
+            AnyTask::Completed($task) => $body,
+            AnyTask::Cancelled($task) => $body,
+            AnyTask::Archived($task) => $body,
+        }
+    };
+}
+
+/// The result of trying a transition on an `AnyTask`: the task in its new
+/// state, or the task handed back untouched if its state does not allow it.
+pub type Transition = Result<AnyTask, AnyTask>;
+
+impl AnyTask {
+    pub fn id(&self) -> u32 {
+        with_task!(self, t => t.id)
+    }
+
+    pub fn set_id(&mut self, id: u32) {
+        with_task!(self, t => t.id = id)
+    }
+
+    pub fn details(&self) -> &Details {
+        with_task!(self, t => &t.details)
+    }
+
+    pub fn details_mut(&mut self) -> &mut Details {
+        with_task!(self, t => &mut t.details)
+    }
+
+    pub fn stamps(&self) -> &Stamps {
+        with_task!(self, t => &t.stamps)
+    }
+
+    pub fn stamps_mut(&mut self) -> &mut Stamps {
+        with_task!(self, t => &mut t.stamps)
+    }
+
+    /// Completed, cancelled or archived.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            AnyTask::Completed(_) | AnyTask::Cancelled(_) | AnyTask::Archived(_)
+        )
+    }
+
+    pub fn state(&self) -> &'static str {
+        match self {
+            AnyTask::Pending(_) => "pending",
+            AnyTask::InProgress(_) => "in progress",
+            AnyTask::Blocked(_) => "blocked",
+            AnyTask::Completed(_) => "completed",
+            AnyTask::Cancelled(_) => "cancelled",
+            AnyTask::Archived(_) => "archived",
+        }
+    }
+
+    pub fn marker(&self) -> &'static str {
+        match self {
+            AnyTask::Pending(_) => "[ ]",
+            AnyTask::InProgress(_) => "[/]",
+            AnyTask::Blocked(_) => "[!]",
+            AnyTask::Completed(_) => "[x]",
+            AnyTask::Cancelled(_) => "[-]",
+            AnyTask::Archived(_) => "[#]",
+        }
+    }
+
+    pub fn start(self) -> Transition {
+        match self {
+            AnyTask::Pending(t) => Ok(AnyTask::InProgress(t.start())),
+            other => Err(other),
+        }
+    }
+
+    pub fn complete(self) -> Transition {
+        match self {
+            AnyTask::Pending(t) => Ok(AnyTask::Completed(t.complete())),
+            AnyTask::InProgress(t) => Ok(AnyTask::Completed(t.complete())),
+            other => Err(other),
+        }
+    }
+
+    pub fn block(self) -> Transition {
+        match self {
+            AnyTask::Pending(t) => Ok(AnyTask::Blocked(t.block())),
+            AnyTask::InProgress(t) => Ok(AnyTask::Blocked(t.block())),
+            other => Err(other),
+        }
+    }
+
+    pub fn unblock(self) -> Transition {
+        match self {
+            AnyTask::Blocked(t) => Ok(AnyTask::Pending(t.unblock())),
+            other => Err(other),
+        }
+    }
+
+    pub fn cancel(self) -> Transition {
+        match self {
+            AnyTask::Pending(t) => Ok(AnyTask::Cancelled(t.cancel())),
+            AnyTask::InProgress(t) => Ok(AnyTask::Cancelled(t.cancel())),
+            AnyTask::Blocked(t) => Ok(AnyTask::Cancelled(t.cancel())),
+            other => Err(other),
+        }
+    }
+
+    pub fn reopen(self) -> Transition {
+        match self {
+            AnyTask::Completed(t) => Ok(AnyTask::Pending(t.reopen())),
+            AnyTask::Cancelled(t) => Ok(AnyTask::Pending(t.reopen())),
+            other => Err(other),
+        }
+    }
+
+    pub fn archive(self) -> Transition {
+        match self {
+            AnyTask::Completed(t) => Ok(AnyTask::Archived(t.archive())),
+            AnyTask::Cancelled(t) => Ok(AnyTask::Archived(t.archive())),
+            other => Err(other),
+        }
+    }
+}
+
+impl Display for AnyTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let details = self.details();
+        write!(f, "{} {:<4} ", self.marker(), self.id())?;
+        if let Some(priority) = details.priority {
+            write!(f, "({}) ", priority.name())?;
+        }
+        write!(f, "{}", details.description)?;
+        for tag in &details.tags {
+            write!(f, " +{}", tag)?;
+        }
+        if let Some(due) = details.due {
+            write!(f, " due:{}", due)?;
+        }
+        if let Some(remind) = details.remind {
+            write!(f, " remind:{}", remind.format("%Y-%m-%dT%H:%M"))?;
+        }
+        if let Some(recurrence) = &details.recurrence {
+            write!(f, " rrule:{}", recurrence)?;
+        }
+        if !details.blocked_by.is_empty() {
+            let ids: Vec<String> = details.blocked_by.iter().map(u32::to_string).collect();
+            write!(f, " needs:{}", ids.join(","))?;
+        }
+        if details.logged_seconds() > 0 {
+            write!(
+                f,
+                " time:{}",
+                timetrack::format_duration(details.logged_seconds())
+            )?;
+        }
+        if details.timer_since.is_some() {
+            write!(f, " (timing)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CommandError;
+    use crate::store::MemoryStore;
+    use crate::tests::{manager, run, state};
+
+    #[test]
+    fn tasks_move_through_their_lifecycle() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        for (line, expected) in [
+            ("start 1", "in progress"),
+            ("block 1", "blocked"),
+            ("unblock 1", "pending"),
+            ("complete 1", "completed"),
+            ("reopen 1", "pending"),
+            ("cancel 1", "cancelled"),
+            ("archive 1", "archived"),
+        ] {
+            run(&mut manager, &mut store, line).unwrap();
+            assert_eq!(state(&manager, 1), expected, "after {}", line);
+        }
+    }
+
+    #[test]
+    fn transitions_the_state_does_not_allow_are_refused() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add buy milk").unwrap();
+        run(&mut manager, &mut store, "add walk dog").unwrap();
+        run(&mut manager, &mut store, "cancel 1").unwrap();
+        run(&mut manager, &mut store, "complete 2").unwrap();
+        run(&mut manager, &mut store, "archive 2").unwrap();
+
+        for (line, id, from, verb) in [
+            ("complete 1", 1, "cancelled", "completed"),
+            ("start 1", 1, "cancelled", "started"),
+            ("unblock 1", 1, "cancelled", "unblocked"),
+            ("reopen 2", 2, "archived", "reopened"),
+            ("cancel 2", 2, "archived", "cancelled"),
+            ("archive 2", 2, "archived", "archived"),
+        ] {
+            match run(&mut manager, &mut store, line) {
+                Err(CommandError::InvalidTransition {
+                    id: got,
+                    state,
+                    verb: v,
+                }) => {
+                    assert_eq!((got, state, v), (id, from, verb), "for {}", line)
+                }
+                other => panic!("{} gave {:?}", line, other),
+            }
+        }
+        assert_eq!(state(&manager, 1), "cancelled");
+        assert_eq!(state(&manager, 2), "archived");
+    }
+}
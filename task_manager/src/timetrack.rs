@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::args::Token;
+use crate::output::ReportRecord;
+use crate::task::AnyTask;
+use crate::{CommandError, TaskManager};
+
+/// Time spent on a task, either a finished timer or a manual `log` entry.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TimeEntry {
+    pub start: NaiveDateTime,
+    pub seconds: u64,
+}
+
+/// The longest duration `parse_duration` accepts, far more than any one
+/// stretch of work.
+const MAX_SECONDS: u64 = 1000 * 3600;
+
+/// Parses durations like `1h30m`, `45m`, `2h` or `90` (minutes), up to 1000
+/// hours.
+pub fn parse_duration(text: &str) -> Option<u64> {
+    let seconds = match text.parse::<u64>() {
+        Ok(minutes) => minutes.checked_mul(60)?,
+        Err(_) => {
+            let mut seconds: u64 = 0;
+            let mut number = String::new();
+            for c in text.to_lowercase().chars() {
+                if c.is_ascii_digit() {
+                    number.push(c);
+                    continue;
+                }
+                let value: u64 = number.parse().ok()?;
+                number.clear();
+                let unit = match c {
+                    'h' => 3600,
+                    'm' => 60,
+                    's' => 1,
+                    _ => return None,
+                };
+                seconds = seconds.checked_add(value.checked_mul(unit)?)?;
+            }
+            if !number.is_empty() {
+                return None;
+            }
+            seconds
+        }
+    };
+    (1..=MAX_SECONDS).contains(&seconds).then_some(seconds)
+}
+
+pub fn format_duration(seconds: u64) -> String {
+    let minutes = seconds / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, m) => format!("{}m", m),
+        (h, 0) => format!("{}h", h),
+        (h, m) => format!("{}h{:02}m", h, m),
+    }
+}
+
+/// How `report` buckets and labels logged time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Day,
+    Week,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupBy {
+    Task,
+    Tag,
+}
+
+/// Reads `report` arguments: `day` or `week`, and `by:task` or `by:tag`.
+pub fn parse_report(words: &[Token]) -> Result<(Period, GroupBy), CommandError> {
+    let mut period = Period::Day;
+    let mut group_by = GroupBy::Task;
+    for word in words {
+        match word.text.to_lowercase().as_str() {
+            "day" | "daily" => period = Period::Day,
+            "week" | "weekly" => period = Period::Week,
+            "by:task" => group_by = GroupBy::Task,
+            "by:tag" => group_by = GroupBy::Tag,
+            _ => {
+                return Err(word.error(format!(
+                    "Unknown report option '{}', expected day, week, by:task or by:tag",
+                    word.text
+                )));
+            }
+        }
+    }
+    Ok((period, group_by))
+}
+
+fn period_label(date: NaiveDate, period: Period) -> String {
+    match period {
+        Period::Day => date.format("%Y-%m-%d").to_string(),
+        Period::Week => {
+            let week = date.iso_week();
+            format!("{}-W{:02}", week.year(), week.week())
+        }
+    }
+}
+
+impl TaskManager {
+    /// The task with a running timer and when it started.
+    pub fn running_timer(&self) -> Option<(u32, NaiveDateTime)> {
+        self.tasks
+            .iter()
+            .find_map(|t| t.details().timer_since.map(|since| (t.id(), since)))
+    }
+
+    pub fn start_timer(&mut self, id: u32, at: NaiveDateTime) -> Result<String, CommandError> {
+        if self
+            .running_timer()
+            .is_some_and(|(running, _)| running == id)
+        {
+            return Err(CommandError::TimerRunning(id));
+        }
+        // A task already in progress just picks up a new timer.
+        let pos = self.position(id)?;
+        if !matches!(self.tasks[pos], AnyTask::InProgress(_)) {
+            self.transition(id, "started", AnyTask::start)?;
+        }
+        let mut message = String::new();
+        if self.running_timer().is_some() {
+            message = self.stop_timer(at)? + " ";
+        }
+        self.task_mut(id)?.details_mut().timer_since = Some(at);
+        message.push_str(&format!("Started the timer on task {}.", id));
+        Ok(message)
+    }
+
+    pub fn stop_timer(&mut self, at: NaiveDateTime) -> Result<String, CommandError> {
+        let (id, since) = self.running_timer().ok_or(CommandError::NoTimer)?;
+        let seconds = (at - since).num_seconds().max(0) as u64;
+        let details = self.task_mut(id)?.details_mut();
+        details.timer_since = None;
+        details.time_log.push(TimeEntry {
+            start: since,
+            seconds,
+        });
+        Ok(format!(
+            "Stopped the timer on task {} after {}.",
+            id,
+            format_duration(seconds)
+        ))
+    }
+
+    pub fn log_time(&mut self, id: u32, entry: TimeEntry) -> Result<String, CommandError> {
+        self.task_mut(id)?.details_mut().time_log.push(entry);
+        Ok(format!(
+            "Logged {} on task {}.",
+            format_duration(entry.seconds),
+            id
+        ))
+    }
+
+    /// Shown before the prompt while a timer runs, e.g. `[task 3 12m]`.
+    pub fn timer_indicator(&self, now: NaiveDateTime) -> Option<String> {
+        let (id, since) = self.running_timer()?;
+        let seconds = (now - since).num_seconds().max(0) as u64;
+        Some(format!("[task {} {}] ", id, format_duration(seconds)))
+    }
+
+    /// Logged time summed per task or tag within each day or week, oldest
+    /// first, with a running timer counted up to `now`.
+    pub fn report_records(
+        &self,
+        period: Period,
+        group_by: GroupBy,
+        now: NaiveDateTime,
+    ) -> Vec<ReportRecord> {
+        let mut buckets: BTreeMap<(NaiveDate, Option<u32>, Option<String>), u64> = BTreeMap::new();
+        let mut descriptions = HashMap::new();
+        for task in &self.tasks {
+            let details = task.details();
+            descriptions.insert(task.id(), &details.description);
+            let running = details.timer_since.map(|since| TimeEntry {
+                start: since,
+                seconds: (now - since).num_seconds().max(0) as u64,
+            });
+            for entry in details.time_log.iter().chain(running.as_ref()) {
+                let date = entry.start.date();
+                let start = match period {
+                    Period::Day => date,
+                    Period::Week => {
+                        date - Duration::days(date.weekday().num_days_from_monday().into())
+                    }
+                };
+                let groups: Vec<(Option<u32>, Option<String>)> = match group_by {
+                    GroupBy::Task => vec![(Some(task.id()), None)],
+                    GroupBy::Tag if details.tags.is_empty() => vec![(None, None)],
+                    GroupBy::Tag => details
+                        .tags
+                        .iter()
+                        .map(|t| (None, Some(t.clone())))
+                        .collect(),
+                };
+                for (id, tag) in groups {
+                    *buckets.entry((start, id, tag)).or_default() += entry.seconds;
+                }
+            }
+        }
+        buckets
+            .into_iter()
+            .map(|((start, task, tag), seconds)| ReportRecord {
+                period: period_label(start, period),
+                task,
+                description: task.map(|id| descriptions[&id].clone()),
+                tag,
+                seconds,
+            })
+            .collect()
+    }
+
+    /// `report_records` as a table with a heading per period.
+    pub fn report(&self, period: Period, group_by: GroupBy, now: NaiveDateTime) -> String {
+        let records = self.report_records(period, group_by, now);
+        if records.is_empty() {
+            return "No time logged yet. Use 'start <id>' or 'log <id> <duration>'.".to_string();
+        }
+        let mut out = String::new();
+        for rows in records.chunk_by(|a, b| a.period == b.period) {
+            out.push_str(&format!("{}\n", rows[0].period));
+            for row in rows {
+                let group = match (row.task, &row.tag) {
+                    (Some(id), _) => format!(
+                        "{:<4} {}",
+                        id,
+                        row.description.as_deref().unwrap_or_default()
+                    ),
+                    (None, Some(tag)) => format!("+{}", tag),
+                    (None, None) => "(untagged)".to_string(),
+                };
+                out.push_str(&format!(
+                    "  {:<40} {:>8}\n",
+                    group,
+                    format_duration(row.seconds)
+                ));
+            }
+            // Tags can overlap, so only tasks add up to a meaningful total.
+            if group_by == GroupBy::Task {
+                let total = rows.iter().map(|row| row.seconds).sum();
+                out.push_str(&format!(
+                    "  {:<40} {:>8}\n",
+                    "total",
+                    format_duration(total)
+                ));
+            }
+        }
+        out.pop();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::tests::{manager, run};
+
+    #[test]
+    fn durations_in_hours_minutes_and_seconds() {
+        assert_eq!(parse_duration("1h30m"), Some(5400));
+        assert_eq!(parse_duration("45M"), Some(2700));
+        assert_eq!(parse_duration("2h"), Some(7200));
+        assert_eq!(parse_duration("90"), Some(5400));
+        assert_eq!(parse_duration("30s"), Some(30));
+        for text in ["", "h", "1x", "1h30", "-5", "0", "0h"] {
+            assert_eq!(parse_duration(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn durations_past_the_cap_are_refused() {
+        assert_eq!(parse_duration("1000h"), Some(MAX_SECONDS));
+        assert_eq!(parse_duration("1000h1s"), None);
+        assert_eq!(parse_duration("99999999999999999999h"), None);
+        assert_eq!(parse_duration("307445734561825861"), None);
+        assert_eq!(parse_duration("18446744073709551615s1s"), None);
+    }
+
+    #[test]
+    fn formatting_rounds_down_to_minutes() {
+        assert_eq!(format_duration(5400), "1h30m");
+        assert_eq!(format_duration(7200), "2h");
+        assert_eq!(format_duration(59), "0m");
+        assert_eq!(format_duration(3660), "1h01m");
+    }
+
+    #[test]
+    fn log_refuses_durations_past_the_cap() {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add write report").unwrap();
+        assert!(run(&mut manager, &mut store, "log 1 9999999999999h").is_err());
+        assert!(run(&mut manager, &mut store, "log 1 1001h").is_err());
+        run(&mut manager, &mut store, "log 1 1h30m").unwrap();
+        assert_eq!(manager.tasks[0].details().logged_seconds(), 5400);
+    }
+}
@@ -0,0 +1,1064 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::args::{self, Args, Token};
+use crate::output::{self, Format, TaskRecord};
+use crate::recurrence::Recurrence;
+use crate::store;
+use crate::task::{AnyTask, Details, Priority, Task};
+use crate::{CommandError, TaskManager, parse_details};
+
+/// File formats `import` and `export` understand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transfer {
+    TodoTxt,
+    Markdown,
+    Csv,
+    ICal,
+}
+
+impl TryFrom<&str> for Transfer {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "todo" | "todotxt" | "todo.txt" | "txt" => Ok(Transfer::TodoTxt),
+            "markdown" | "md" => Ok(Transfer::Markdown),
+            "csv" => Ok(Transfer::Csv),
+            "ical" | "ics" | "vtodo" => Ok(Transfer::ICal),
+            _ => Err(format!(
+                "Unknown file format '{}', expected todotxt, markdown, csv or ical",
+                value
+            )),
+        }
+    }
+}
+
+impl Transfer {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "txt" => Some(Transfer::TodoTxt),
+            "md" | "markdown" => Some(Transfer::Markdown),
+            "csv" => Some(Transfer::Csv),
+            "ics" | "ical" => Some(Transfer::ICal),
+            _ => None,
+        }
+    }
+}
+
+/// The file and format given to `import` or `export`. Without `--format`
+/// the file's extension decides.
+pub fn parse_args(args: &Args) -> Result<(PathBuf, Transfer), CommandError> {
+    let file = args.word(0);
+    let path = PathBuf::from(&file.text);
+    let format = match args.value("format") {
+        Some(name) => Transfer::try_from(name.text.as_str()).map_err(|e| name.error(e))?,
+        None => Transfer::from_path(&path).ok_or_else(|| {
+            file.error(format!(
+                "Cannot tell the format of '{}' from its extension, add --format",
+                file.text
+            ))
+        })?,
+    };
+    Ok((path, format))
+}
+
+/// A task read from another tool's file, before it is given an id here.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Imported {
+    /// How the file refers to this task, e.g. its id there or its UID.
+    pub key: Option<String>,
+    /// One of the names `AnyTask::state` returns.
+    pub state: String,
+    pub details: Details,
+    /// Keys of other imported tasks.
+    pub parent: Option<String>,
+    pub blocked_by: Vec<String>,
+}
+
+impl Imported {
+    fn new(state: &str, details: Details) -> Self {
+        Imported {
+            key: None,
+            state: state.to_string(),
+            details,
+            parent: None,
+            blocked_by: Vec::new(),
+        }
+    }
+}
+
+/// Reads and parses `path`, so the tasks can be journaled as they are now.
+pub fn read(
+    path: &Path,
+    format: Transfer,
+    now: NaiveDateTime,
+) -> Result<Vec<Imported>, CommandError> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| CommandError::Io(format!("Failed to read {}: {}", path.display(), e)))?;
+    let tasks = match format {
+        Transfer::TodoTxt => Ok(read_todo_txt(&text)),
+        Transfer::Markdown => Ok(read_markdown(&text, now)),
+        Transfer::Csv => read_csv(&text),
+        Transfer::ICal => read_ical(&text),
+    }
+    .map_err(|e| CommandError::InvalidArgument(format!("{}: {}", path.display(), e)))?;
+    if tasks.is_empty() {
+        return Err(CommandError::InvalidArgument(format!(
+            "{}: no tasks found",
+            path.display()
+        )));
+    }
+    // `add` refuses a task without a description, so import does too.
+    if let Some(n) = tasks
+        .iter()
+        .position(|task| task.details.description.trim().is_empty())
+    {
+        return Err(CommandError::InvalidArgument(format!(
+            "{}: task {} has no description",
+            path.display(),
+            n + 1
+        )));
+    }
+    Ok(tasks)
+}
+
+impl TaskManager {
+    /// Adds `tasks` under fresh ids from `next_id` on, in file order, and
+    /// points their parents and dependencies at the new ids. References to
+    /// tasks that were not imported are dropped. Returns the new ids.
+    pub fn import(&mut self, tasks: Vec<Imported>) -> Vec<u32> {
+        let first = self.next_id;
+        let ids: HashMap<String, u32> = tasks
+            .iter()
+            .zip(first..)
+            .filter_map(|(task, id)| Some((task.key.clone()?, id)))
+            .collect();
+        let resolve = |key: &String, own: u32| ids.get(key).copied().filter(|&id| id != own);
+
+        let mut added = Vec::with_capacity(tasks.len());
+        for (imported, id) in tasks.into_iter().zip(first..) {
+            let mut details = imported.details;
+            details.parent = imported.parent.as_ref().and_then(|key| resolve(key, id));
+            details.blocked_by = imported
+                .blocked_by
+                .iter()
+                .filter_map(|key| resolve(key, id))
+                .collect();
+            details.time_log.clear();
+            details.timer_since = None;
+            self.tasks.push(in_state(id, details, &imported.state));
+            self.reindex(id);
+            added.push(id);
+        }
+        self.next_id = first + added.len() as u32;
+        added
+    }
+
+    /// Writes every task to `path`, returning a message saying so.
+    pub fn export(&self, path: &Path, format: Transfer) -> Result<String, CommandError> {
+        let text = match format {
+            Transfer::TodoTxt => self.write_todo_txt(),
+            Transfer::Markdown => self.write_markdown(),
+            Transfer::Csv => {
+                let records: Vec<TaskRecord> = self.tasks.iter().map(TaskRecord::from).collect();
+                output::render(&records, Format::Csv) + "\n"
+            }
+            Transfer::ICal => self.write_ical(),
+        };
+        store::write_atomic(path, text.as_bytes())
+            .map_err(|e| CommandError::Io(format!("Failed to write {}: {}", path.display(), e)))?;
+        Ok(format!(
+            "Exported {} task(s) to {}.",
+            self.tasks.len(),
+            path.display()
+        ))
+    }
+}
+
+/// A new task moved into the named state. Unknown names leave it pending.
+fn in_state(id: u32, details: Details, state: &str) -> AnyTask {
+    let task = AnyTask::Pending(Task::new(id, details));
+    let moved = match state {
+        "in progress" => task.start(),
+        "blocked" => task.block(),
+        "completed" => task.complete(),
+        "cancelled" => task.cancel(),
+        "archived" => task.complete().and_then(AnyTask::archive),
+        _ => Ok(task),
+    };
+    moved.unwrap_or_else(|task| task)
+}
+
+/// Accepts both `AnyTask::state` names and the dashed ones in CSV output.
+fn state_name(name: &str) -> Option<&'static str> {
+    match name.trim().to_lowercase().replace('-', " ").as_str() {
+        "" | "pending" => Some("pending"),
+        "in progress" => Some("in progress"),
+        "blocked" => Some("blocked"),
+        "completed" => Some("completed"),
+        "cancelled" => Some("cancelled"),
+        "archived" => Some("archived"),
+        _ => None,
+    }
+}
+
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+}
+
+fn parse_timestamp(text: &str) -> Option<NaiveDateTime> {
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+}
+
+/// Writes a word of text that would read back as syntax, such as a tag, with
+/// a leading backslash. The readers drop it again and keep the word as text.
+fn escape_word(word: &str, syntax: bool) -> String {
+    if syntax || word.starts_with('\\') {
+        format!("\\{}", word)
+    } else {
+        word.to_string()
+    }
+}
+
+/// Whether `word` starts with `prefix` and has something after it.
+fn is_marked(word: &str, prefix: char) -> bool {
+    word.len() > prefix.len_utf8() && word.starts_with(prefix)
+}
+
+/// Whether `word` reads as `key:value` for one of `keys`.
+fn is_key_value(word: &str, keys: &[&str]) -> bool {
+    word.split_once(':')
+        .is_some_and(|(key, _)| keys.contains(&key))
+}
+
+// todo.txt: http://todotxt.org, with `due:`, `id:`, `parent:`, `dep:`,
+// `rrule:`, `pri:` and `state:` as key:value extensions. Notes have no
+// place in the format and are left out. Description words that would read
+// as a tag, context or extension are written with a backslash in front.
+
+const TODO_KEYS: [&str; 7] = ["due", "id", "parent", "dep", "rrule", "pri", "state"];
+
+const TODO_PRIORITIES: [(Priority, &str); 3] = [
+    (Priority::High, "A"),
+    (Priority::Medium, "B"),
+    (Priority::Low, "C"),
+];
+
+fn todo_priority(letter: &str) -> Option<Priority> {
+    match letter {
+        "A" => Some(Priority::High),
+        "B" => Some(Priority::Medium),
+        l if l.len() == 1 && l.chars().all(|c| c.is_ascii_uppercase()) => Some(Priority::Low),
+        _ => None,
+    }
+}
+
+/// A priority like `(A)`, as it starts a todo.txt line.
+fn todo_priority_word(word: &str) -> bool {
+    word.len() == 3 && word.starts_with('(') && word.ends_with(')')
+}
+
+fn read_todo_txt(text: &str) -> Vec<Imported> {
+    let mut tasks = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let mut words = line.split_whitespace().peekable();
+        let done = words.next_if_eq(&"x").is_some();
+        if done {
+            words.next_if(|word| parse_date(word).is_some());
+        }
+        let mut details = Details {
+            priority: words
+                .next_if(|word| todo_priority_word(word))
+                .and_then(|word| todo_priority(&word[1..2])),
+            ..Details::default()
+        };
+        // The creation date; this task gets its own.
+        words.next_if(|word| parse_date(word).is_some());
+
+        let mut task = Imported::new(
+            if done { "completed" } else { "pending" },
+            Details::default(),
+        );
+        let mut description = Vec::new();
+        for word in words {
+            if let Some(text) = word.strip_prefix('\\') {
+                description.push(text);
+                continue;
+            }
+            let (key, value) = word.split_once(':').unwrap_or(("", ""));
+            match key {
+                "due" if parse_date(value).is_some() => details.due = parse_date(value),
+                "id" if !value.is_empty() => task.key = Some(value.to_string()),
+                "parent" if !value.is_empty() => task.parent = Some(value.to_string()),
+                "dep" if !value.is_empty() => {
+                    task.blocked_by = value.split(',').map(str::to_string).collect()
+                }
+                "rrule" if Recurrence::parse_rrule(value).is_ok() => {
+                    details.recurrence = Recurrence::parse_rrule(value).ok()
+                }
+                "pri" if todo_priority(value).is_some() => details.priority = todo_priority(value),
+                "state" if state_name(value).is_some() => {
+                    task.state = state_name(value).unwrap().to_string()
+                }
+                _ => match word.strip_prefix('+').filter(|tag| !tag.is_empty()) {
+                    Some(tag) => details.tags.push(tag.to_string()),
+                    None => description.push(word),
+                },
+            }
+        }
+        details.description = description.join(" ");
+        task.details = details;
+        tasks.push(task);
+    }
+    tasks
+}
+
+impl TaskManager {
+    fn write_todo_txt(&self) -> String {
+        let mut out = String::new();
+        for task in &self.tasks {
+            let details = task.details();
+            let letter = details.priority.map(|priority| {
+                TODO_PRIORITIES
+                    .iter()
+                    .find(|(p, _)| *p == priority)
+                    .map(|(_, letter)| *letter)
+                    .unwrap()
+            });
+            let done = matches!(task, AnyTask::Completed(_));
+            let mut words = Vec::new();
+            if done {
+                words.push("x".to_string());
+                if let Some(completed) = task.stamps().completed {
+                    words.push(completed.date().to_string());
+                }
+            } else if let Some(letter) = letter {
+                words.push(format!("({})", letter));
+            }
+            if let Some(created) = task.stamps().created {
+                words.push(created.date().to_string());
+            }
+            for (i, word) in details.description.split_whitespace().enumerate() {
+                // Only the start of a line says whether it is done and when.
+                let leading = i == 0
+                    && (word == "x" || parse_date(word).is_some() || todo_priority_word(word));
+                let syntax = leading
+                    || is_marked(word, '+')
+                    || is_marked(word, '@')
+                    || is_key_value(word, &TODO_KEYS);
+                words.push(escape_word(word, syntax));
+            }
+            words.extend(details.tags.iter().map(|tag| format!("+{}", tag)));
+            if let Some(due) = details.due {
+                words.push(format!("due:{}", due));
+            }
+            if let Some(recurrence) = &details.recurrence {
+                words.push(format!("rrule:{}", recurrence));
+            }
+            words.push(format!("id:{}", task.id()));
+            if let Some(parent) = details.parent {
+                words.push(format!("parent:{}", parent));
+            }
+            if !details.blocked_by.is_empty() {
+                let ids: Vec<String> = details.blocked_by.iter().map(u32::to_string).collect();
+                words.push(format!("dep:{}", ids.join(",")));
+            }
+            // Completed tasks lose their "(A)", so keep it as an extension.
+            if let (true, Some(letter)) = (done, letter) {
+                words.push(format!("pri:{}", letter));
+            }
+            if !done && !matches!(task, AnyTask::Pending(_)) {
+                words.push(format!("state:{}", task.state().replace(' ', "-")));
+            }
+            out.push_str(&words.join(" "));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+// Markdown: GitHub-style checklists using the `Display` markers, with
+// subtasks nested two spaces deeper and notes indented beneath their task.
+// As in todo.txt, a backslash keeps a word that looks like a tag or a
+// `due:` as text, and keeps a note that looks like a list item a note.
+
+const MARKDOWN_KEYS: [&str; 5] = ["due", "remind", "parent", "every", "rrule"];
+
+/// The list item `line` starts, as its state and the text after the marker.
+fn markdown_item(line: &str) -> Option<(&'static str, &str)> {
+    let rest = line
+        .strip_prefix("- [")
+        .or_else(|| line.strip_prefix("* ["))?;
+    let mut chars = rest.chars();
+    let state = marker_state(chars.next()?)?;
+    let text = chars.as_str().strip_prefix(']')?;
+    Some((state, text.trim()))
+}
+
+fn marker_state(marker: char) -> Option<&'static str> {
+    match marker {
+        ' ' => Some("pending"),
+        '/' => Some("in progress"),
+        '!' => Some("blocked"),
+        'x' | 'X' => Some("completed"),
+        '-' => Some("cancelled"),
+        '#' => Some("archived"),
+        _ => None,
+    }
+}
+
+fn read_markdown(text: &str, now: NaiveDateTime) -> Vec<Imported> {
+    let mut tasks: Vec<Imported> = Vec::new();
+    // Indentation and key of each open list item, outermost first.
+    let mut open: Vec<(usize, String)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        match markdown_item(trimmed) {
+            Some((state, text)) => {
+                while open.last().is_some_and(|(depth, _)| *depth >= indent) {
+                    open.pop();
+                }
+                // Escaped words are text, as if they had been quoted.
+                let words: Vec<Token> = args::split(text)
+                    .into_iter()
+                    .map(|mut word| {
+                        if let Some(text) = word.text.strip_prefix('\\') {
+                            word.text = text.to_string();
+                            word.literal = true;
+                        }
+                        word
+                    })
+                    .collect();
+                // Text that does not read as task syntax is kept as it is.
+                let mut details = parse_details(&words, now).unwrap_or_else(|_| Details {
+                    description: text.to_string(),
+                    ..Details::default()
+                });
+                details.parent = None;
+                let key = format!("line{}", number + 1);
+                let mut task = Imported::new(state, details);
+                task.key = Some(key.clone());
+                task.parent = open.last().map(|(_, key)| key.clone());
+                open.push((indent, key));
+                tasks.push(task);
+            }
+            None if !trimmed.is_empty() => {
+                if let (Some(task), Some((depth, _))) = (tasks.last_mut(), open.last())
+                    && indent > *depth
+                {
+                    task.details
+                        .add_note(trimmed.strip_prefix('\\').unwrap_or(trimmed));
+                }
+            }
+            None => {}
+        }
+    }
+    tasks
+}
+
+impl TaskManager {
+    fn write_markdown(&self) -> String {
+        let mut out = String::new();
+        let all: Vec<&AnyTask> = self.tasks.iter().collect();
+        for (depth, task) in self.tree(&all) {
+            let details = task.details();
+            let indent = "  ".repeat(depth);
+            let mut line = format!("{}- {}", indent, task.marker());
+            for word in details.description.split_whitespace() {
+                let syntax = is_marked(word, '+')
+                    || is_marked(word, '!')
+                    || is_key_value(word, &MARKDOWN_KEYS);
+                line.push(' ');
+                line.push_str(&escape_word(word, syntax));
+            }
+            if let Some(priority) = details.priority {
+                line.push_str(&format!(" !{}", priority.name()));
+            }
+            for tag in &details.tags {
+                line.push_str(&format!(" +{}", tag));
+            }
+            if let Some(due) = details.due {
+                line.push_str(&format!(" due:{}", due));
+            }
+            if let Some(remind) = details.remind {
+                line.push_str(&format!(" remind:{}", remind.format("%Y-%m-%dT%H:%M")));
+            }
+            if let Some(recurrence) = &details.recurrence {
+                line.push_str(&format!(" rrule:{}", recurrence));
+            }
+            out.push_str(&line);
+            out.push('\n');
+            for note in details.notes.lines() {
+                // A note that looks like a list item would come back as a task.
+                let note = escape_word(note, markdown_item(note.trim_start()).is_some());
+                out.push_str(&format!("{}  {}\n", indent, note));
+            }
+        }
+        out
+    }
+}
+
+// CSV: the columns `list --format csv` writes. Only `description` is
+// required; `id`, `parent` and `blocked_by` link rows to each other.
+
+/// Splits RFC 4180 text into records of cells.
+fn csv_records(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if cell.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut cell)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut cell));
+                records.push(std::mem::take(&mut record));
+            }
+            c => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted cell".to_string());
+    }
+    if !cell.is_empty() || !record.is_empty() {
+        record.push(cell);
+        records.push(record);
+    }
+    records.retain(|record| record.iter().any(|cell| !cell.is_empty()));
+    Ok(records)
+}
+
+fn read_csv(text: &str) -> Result<Vec<Imported>, String> {
+    let mut records = csv_records(text)?.into_iter();
+    let header = records.next().ok_or("the file is empty")?;
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(name))
+    };
+    let description = column("description").ok_or("no description column")?;
+
+    let mut tasks = Vec::new();
+    for (number, record) in records.enumerate() {
+        let row = number + 2;
+        let cell = |name: &str| {
+            column(name)
+                .and_then(|i| record.get(i))
+                .map(|cell| cell.trim())
+                .filter(|cell| !cell.is_empty())
+        };
+        let invalid =
+            |name: &str, value: &str| format!("row {}: invalid {} '{}'", row, name, value);
+
+        let description = record.get(description).map(|cell| cell.trim());
+        let description = description
+            .filter(|cell| !cell.is_empty())
+            .ok_or_else(|| format!("row {}: empty description", row))?;
+        let mut details = Details {
+            description: description.to_string(),
+            notes: cell("notes").unwrap_or_default().to_string(),
+            tags: cell("tags")
+                .map(|tags| tags.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default(),
+            ..Details::default()
+        };
+        if let Some(priority) = cell("priority") {
+            details.priority =
+                Some(Priority::try_from(priority).map_err(|_| invalid("priority", priority))?);
+        }
+        if let Some(due) = cell("due") {
+            details.due = Some(parse_date(due).ok_or_else(|| invalid("due", due))?);
+        }
+        if let Some(remind) = cell("remind") {
+            details.remind =
+                Some(parse_timestamp(remind).ok_or_else(|| invalid("remind", remind))?);
+        }
+        if let Some(rule) = cell("recurrence") {
+            details.recurrence =
+                Some(Recurrence::parse_rrule(rule).map_err(|e| format!("row {}: {}", row, e))?);
+        }
+        let state = cell("state").unwrap_or("pending");
+        let state = state_name(state).ok_or_else(|| invalid("state", state))?;
+
+        let mut task = Imported::new(state, details);
+        task.key = cell("id").map(str::to_string);
+        task.parent = cell("parent").map(str::to_string);
+        task.blocked_by = cell("blocked_by")
+            .map(|ids| ids.split(',').map(|id| id.trim().to_string()).collect())
+            .unwrap_or_default();
+        tasks.push(task);
+    }
+    Ok(tasks)
+}
+
+// iCalendar (RFC 5545) VTODO components. States with no STATUS of their
+// own travel in an X-TASK-MANAGER-STATE property.
+
+const ICAL_STATE: &str = "X-TASK-MANAGER-STATE";
+
+fn ical_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn ical_unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Splits a comma-separated list value on unescaped commas.
+fn ical_list(value: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            ',' if !escaped => items.push(String::new()),
+            _ => items.last_mut().unwrap().push(c),
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    items
+        .iter()
+        .map(|item| ical_unescape(item.trim()))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// A content line's name, parameters and value, e.g.
+/// `RELATED-TO;RELTYPE=PARENT:uid`.
+fn ical_property(line: &str) -> Option<(String, HashMap<String, String>, &str)> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => return Some(i),
+            _ => {}
+        }
+        None
+    })?;
+    let mut parts = line[..colon].split(';');
+    let name = parts.next()?.to_uppercase();
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| (key.to_uppercase(), value.trim_matches('"').to_uppercase()))
+        .collect();
+    Some((name, params, &line[colon + 1..]))
+}
+
+fn read_ical(text: &str) -> Result<Vec<Imported>, String> {
+    // Undo line folding: a line starting with a space or tab continues the last.
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match line.strip_prefix([' ', '\t']) {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut tasks = Vec::new();
+    let mut current: Option<Imported> = None;
+    let mut nested = 0;
+    for line in &lines {
+        let Some((name, params, value)) = ical_property(line) else {
+            continue;
+        };
+        match (name.as_str(), value.to_uppercase().as_str()) {
+            ("BEGIN", "VTODO") => current = Some(Imported::new("pending", Details::default())),
+            ("END", "VTODO") => tasks.extend(current.take()),
+            // Skip whatever a VTODO holds, such as VALARM.
+            ("BEGIN", _) if current.is_some() => nested += 1,
+            ("END", _) if nested > 0 => nested -= 1,
+            _ => {}
+        }
+        let Some(task) = current.as_mut().filter(|_| nested == 0) else {
+            continue;
+        };
+        let details = &mut task.details;
+        match name.as_str() {
+            "UID" => task.key = Some(value.to_string()),
+            "SUMMARY" => details.description = ical_unescape(value),
+            "DESCRIPTION" => details.notes = ical_unescape(value),
+            "CATEGORIES" => details.tags.extend(
+                ical_list(value)
+                    .into_iter()
+                    .map(|tag| tag.replace(char::is_whitespace, "-")),
+            ),
+            "PRIORITY" => {
+                details.priority = match value.trim().parse::<u8>() {
+                    Ok(1..=4) => Some(Priority::High),
+                    Ok(5) => Some(Priority::Medium),
+                    Ok(6..=9) => Some(Priority::Low),
+                    _ => None,
+                }
+            }
+            "DUE" => {
+                let date = value.get(..8).unwrap_or(value);
+                details.due = Some(
+                    NaiveDate::parse_from_str(date, "%Y%m%d")
+                        .map_err(|_| format!("invalid DUE '{}'", value))?,
+                );
+            }
+            "RRULE" => details.recurrence = Some(Recurrence::parse_rrule(value)?),
+            "STATUS" if task.state == "pending" => {
+                task.state = match value.to_uppercase().as_str() {
+                    "IN-PROCESS" => "in progress",
+                    "COMPLETED" => "completed",
+                    "CANCELLED" => "cancelled",
+                    _ => "pending",
+                }
+                .to_string()
+            }
+            ICAL_STATE => {
+                if let Some(state) = state_name(value) {
+                    task.state = state.to_string();
+                }
+            }
+            "RELATED-TO" => match params.get("RELTYPE").map(String::as_str) {
+                None | Some("PARENT") => task.parent = Some(value.to_string()),
+                Some("DEPENDS-ON") => task.blocked_by.push(value.to_string()),
+                Some(_) => {}
+            },
+            _ => {}
+        }
+    }
+    Ok(tasks)
+}
+
+fn ical_uid(id: u32) -> String {
+    format!("task-{}@task_manager", id)
+}
+
+/// `at` as a UTC DATE-TIME, taking it to be local time.
+fn ical_utc(at: NaiveDateTime) -> String {
+    match Local.from_local_datetime(&at).earliest() {
+        Some(local) => local
+            .with_timezone(&Utc)
+            .format("%Y%m%dT%H%M%SZ")
+            .to_string(),
+        None => at.format("%Y%m%dT%H%M%S").to_string(),
+    }
+}
+
+/// Folds a content line to at most 75 octets per physical line.
+fn ical_fold(line: &str, out: &mut String) {
+    let mut rest = line;
+    let mut limit = 75;
+    loop {
+        let mut end = rest.len().min(limit);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        out.push_str(&rest[..end]);
+        out.push_str("\r\n");
+        rest = &rest[end..];
+        if rest.is_empty() {
+            break;
+        }
+        out.push(' ');
+        limit = 74;
+    }
+}
+
+impl TaskManager {
+    fn write_ical(&self) -> String {
+        let now = ical_utc(self.clock.now());
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".to_string(),
+            "PRODID:-//task_manager//EN".to_string(),
+        ];
+        for task in &self.tasks {
+            let details = task.details();
+            let stamps = task.stamps();
+            lines.push("BEGIN:VTODO".to_string());
+            lines.push(format!("UID:{}", ical_uid(task.id())));
+            lines.push(format!("DTSTAMP:{}", now));
+            lines.push(format!("SUMMARY:{}", ical_escape(&details.description)));
+            if !details.notes.is_empty() {
+                lines.push(format!("DESCRIPTION:{}", ical_escape(&details.notes)));
+            }
+            let status = match task {
+                AnyTask::InProgress(_) => "IN-PROCESS",
+                AnyTask::Completed(_) | AnyTask::Archived(_) => "COMPLETED",
+                AnyTask::Cancelled(_) => "CANCELLED",
+                AnyTask::Pending(_) | AnyTask::Blocked(_) => "NEEDS-ACTION",
+            };
+            lines.push(format!("STATUS:{}", status));
+            if matches!(task, AnyTask::Blocked(_) | AnyTask::Archived(_)) {
+                lines.push(format!("{}:{}", ICAL_STATE, task.state()));
+            }
+            if let Some(priority) = details.priority {
+                let level = match priority {
+                    Priority::High => 1,
+                    Priority::Medium => 5,
+                    Priority::Low => 9,
+                };
+                lines.push(format!("PRIORITY:{}", level));
+            }
+            if let Some(due) = details.due {
+                lines.push(format!("DUE;VALUE=DATE:{}", due.format("%Y%m%d")));
+            }
+            if !details.tags.is_empty() {
+                let tags: Vec<String> = details.tags.iter().map(|t| ical_escape(t)).collect();
+                lines.push(format!("CATEGORIES:{}", tags.join(",")));
+            }
+            if let Some(recurrence) = &details.recurrence {
+                lines.push(format!("RRULE:{}", recurrence));
+            }
+            if let Some(parent) = details.parent {
+                lines.push(format!("RELATED-TO;RELTYPE=PARENT:{}", ical_uid(parent)));
+            }
+            for blocker in &details.blocked_by {
+                lines.push(format!(
+                    "RELATED-TO;RELTYPE=DEPENDS-ON:{}",
+                    ical_uid(*blocker)
+                ));
+            }
+            if let Some(created) = stamps.created {
+                lines.push(format!("CREATED:{}", ical_utc(created)));
+            }
+            if let Some(updated) = stamps.updated {
+                lines.push(format!("LAST-MODIFIED:{}", ical_utc(updated)));
+            }
+            if let Some(completed) = stamps.completed {
+                lines.push(format!("COMPLETED:{}", ical_utc(completed)));
+            }
+            lines.push("END:VTODO".to_string());
+        }
+        lines.push("END:VCALENDAR".to_string());
+
+        let mut out = String::new();
+        for line in lines {
+            ical_fold(&line, &mut out);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::tests::{descriptions, manager, run, scratch_dir};
+
+    /// Tasks whose text looks like each format's syntax.
+    fn tricky() -> TaskManager {
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        for line in [
+            r#"add plan trip +travel !high due:2026-11-01"#,
+            r#"add "x" marks the "+spot" "@home" "due:soon" "id:7" "!important" \\share"#,
+            r#"add book hotel, "quoted"; tab	here "a \"b\"""#,
+            "add pay rent every:month",
+            "depend 2 1",
+            "parent 3 1",
+            "note 1 - [ ] not a subtask",
+            "start 1",
+            "complete 3",
+            "cancel 4",
+        ] {
+            run(&mut manager, &mut store, line).unwrap();
+        }
+        manager
+    }
+
+    /// Exports `manager` to `file` and imports it again into a manager that
+    /// already holds one task, so every id moves up by one.
+    fn round_trip(manager: &TaskManager, file: &str) -> TaskManager {
+        let path = scratch_dir(file).join(file);
+        manager
+            .export(&path, Transfer::from_path(&path).unwrap())
+            .unwrap();
+        let (mut imported, mut store) = (crate::tests::manager(), MemoryStore::default());
+        run(&mut imported, &mut store, "add existing task").unwrap();
+        let message = run(
+            &mut imported,
+            &mut store,
+            &format!("import {}", path.display()),
+        )
+        .unwrap();
+        assert_eq!(message, "Imported 4 task(s) as tasks 2-5.");
+        imported
+    }
+
+    /// The task whose description starts with `text`.
+    fn find<'a>(manager: &'a TaskManager, text: &str) -> &'a AnyTask {
+        let found = manager
+            .tasks
+            .iter()
+            .find(|t| t.details().description.starts_with(text));
+        found.unwrap_or_else(|| panic!("no task '{}'", text))
+    }
+
+    /// What every format keeps: text, state, tags, priority, due date,
+    /// recurrence and subtasks, the latter pointing at the tasks' new ids.
+    fn assert_kept(original: &TaskManager, imported: &TaskManager) {
+        let kept = |task: &AnyTask| {
+            let details = task.details().clone();
+            let state = task.state();
+            (
+                state,
+                details.tags,
+                details.priority,
+                details.due,
+                details.recurrence,
+            )
+        };
+        assert_eq!(imported.tasks.len(), original.tasks.len() + 1);
+        for task in &original.tasks {
+            let description = &task.details().description;
+            assert_eq!(
+                kept(find(imported, description)),
+                kept(task),
+                "{}",
+                description
+            );
+        }
+        let trip = find(imported, "plan trip").id();
+        assert_ne!(trip, 1);
+        assert_eq!(find(imported, "book hotel").details().parent, Some(trip));
+    }
+
+    /// Markdown has no way to write dependencies; the other formats keep them.
+    fn assert_dependencies_kept(imported: &TaskManager) {
+        let trip = find(imported, "plan trip").id();
+        assert_eq!(find(imported, "x marks").details().blocked_by, [trip]);
+    }
+
+    #[test]
+    fn todo_txt_escapes_words_that_look_like_syntax() {
+        let original = tricky();
+        assert_eq!(
+            descriptions(&original)[1],
+            r#"x marks the +spot @home due:soon id:7 !important \share"#
+        );
+        let imported = round_trip(&original, "tasks.txt");
+        assert_kept(&original, &imported);
+        assert_dependencies_kept(&imported);
+
+        let text = original.write_todo_txt();
+        assert!(
+            text.contains(r#" \x marks the \+spot \@home \due:soon \id:7 !important \\share "#),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn markdown_escapes_words_and_notes_that_look_like_syntax() {
+        let original = tricky();
+        let imported = round_trip(&original, "tasks.md");
+        assert_kept(&original, &imported);
+        assert_eq!(
+            find(&imported, "plan trip").details().notes,
+            "- [ ] not a subtask"
+        );
+
+        let text = original.write_markdown();
+        assert!(
+            text.contains(r#"\+spot @home \due:soon id:7 \!important \\share"#),
+            "{}",
+            text
+        );
+        assert!(text.contains("\n  \\- [ ] not a subtask\n"), "{}", text);
+    }
+
+    #[test]
+    fn csv_round_trips_quotes_commas_and_tabs() {
+        let original = tricky();
+        let imported = round_trip(&original, "tasks.csv");
+        assert_kept(&original, &imported);
+        assert_dependencies_kept(&imported);
+        let notes = |manager| &find(manager, "plan trip").details().notes;
+        assert_eq!(notes(&imported), notes(&original));
+    }
+
+    #[test]
+    fn ical_folds_long_lines_and_reads_them_back() {
+        let mut original = tricky();
+        let long = "réserver, ".repeat(20);
+        original.tasks[0].details_mut().add_note(&long);
+        let text = original.write_ical();
+        assert!(text.lines().all(|line| line.len() <= 75));
+        // Folding may split an escape, as unfolding comes before unescaping.
+        assert!(text.contains("réserver\\\r\n , réserver"), "{}", text);
+
+        let imported = round_trip(&original, "tasks.ics");
+        assert_kept(&original, &imported);
+        assert_dependencies_kept(&imported);
+        let notes = |manager| &find(manager, "plan trip").details().notes;
+        assert_eq!(notes(&imported), notes(&original));
+    }
+
+    #[test]
+    fn a_dry_run_changes_nothing() {
+        let path = scratch_dir("import-dry-run").join("tasks.txt");
+        tricky().export(&path, Transfer::TodoTxt).unwrap();
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        run(&mut manager, &mut store, "add existing task").unwrap();
+
+        let line = format!("import {} --dry-run", path.display());
+        let preview = run(&mut manager, &mut store, &line).unwrap();
+        assert!(preview.contains("plan trip"), "{}", preview);
+        assert!(preview.ends_with("(dry run: would import 4 task(s) as tasks 2-5)"));
+        assert_eq!(descriptions(&manager), ["existing task"]);
+        assert_eq!((manager.next_id, manager.revision), (2, 1));
+    }
+
+    #[test]
+    fn tasks_without_a_description_are_refused() {
+        let dir = scratch_dir("import-empty");
+        let (mut manager, mut store) = (manager(), MemoryStore::default());
+        for (file, text, error) in [
+            (
+                "a.csv",
+                "id,description\n1,buy milk\n2,  \n",
+                "row 3: empty description",
+            ),
+            (
+                "b.txt",
+                "buy milk\n(A) +shop due:2026-11-01\n",
+                "task 2 has no description",
+            ),
+            ("c.md", "- [ ] +shop\n", "task 1 has no description"),
+        ] {
+            let path = dir.join(file);
+            fs::write(&path, text).unwrap();
+            let line = format!("import {}", path.display());
+            let message = run(&mut manager, &mut store, &line)
+                .unwrap_err()
+                .to_string();
+            assert!(message.contains(error), "{}: {}", file, message);
+        }
+        assert!(manager.tasks.is_empty());
+    }
+}
@@ -0,0 +1,872 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::NaiveDateTime;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Clear, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::args;
+use crate::autosave::Autosaver;
+use crate::query::Query;
+use crate::store::SharedStore;
+use crate::task::AnyTask;
+use crate::timetrack::format_duration;
+use crate::{Command, CommandError, TaskManager};
+
+/// How long to wait for a key before redrawing, so running timers tick and
+/// changes the autosave thread merged in show up.
+const TICK: Duration = Duration::from_millis(250);
+
+/// How often to look for changes another process saved.
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// Rows PageUp and PageDown move by.
+const PAGE: usize = 10;
+
+/// Audit events shown under a task's details, most recent last.
+const RECENT_EVENTS: usize = 10;
+
+const KEYS: &str = "j/k move  space mark  a add  e edit  c complete  s timer  d delete  u undo  \
+                    / filter  : command  ? help  q quit";
+
+const KEY_HELP: &str = "Keys:
+  j k, Up Down, PgUp PgDn, g G   - Move through the list
+  space                          - Mark or unmark a task; commands act on the marked
+                                   tasks instead of the selected one, Esc unmarks all
+  a                              - Add a task, e.g. 'Call Bob +work due:friday'
+  e                              - Edit the description and add tokens, as 'edit' does
+  n                              - Add a note
+  c  d                           - Complete, delete
+  s                              - Start the timer, or stop it on the timed task
+  u  r                           - Undo, redo
+  Enter                          - Show the task's history
+  /                              - Filter the list with a 'list' query, e.g. '+work !high'
+  :                              - Run any command, as in the REPL
+  q, Ctrl-C                      - Quit";
+
+/// What the user is typing into, if anything.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Prompt {
+    Filter,
+    Add,
+    Edit,
+    Note,
+    Command,
+}
+
+impl Prompt {
+    fn label(self) -> &'static str {
+        match self {
+            Prompt::Filter => "Filter: ",
+            Prompt::Add => "Add: ",
+            Prompt::Edit => "Edit: ",
+            Prompt::Note => "Note: ",
+            Prompt::Command => ":",
+        }
+    }
+}
+
+/// A line being typed, with the cursor as a byte offset into `text`.
+struct Input {
+    prompt: Prompt,
+    text: String,
+    cursor: usize,
+}
+
+impl Input {
+    fn new(prompt: Prompt, text: String) -> Self {
+        let cursor = text.len();
+        Input {
+            prompt,
+            text,
+            cursor,
+        }
+    }
+
+    /// Handles an editing key, returning false for keys it does not use.
+    fn edit(&mut self, key: KeyEvent) -> bool {
+        let before = &self.text[..self.cursor];
+        let previous = before.char_indices().last().map_or(0, |(i, _)| i);
+        let next = self.text[self.cursor..]
+            .chars()
+            .next()
+            .map_or(self.cursor, |c| self.cursor + c.len_utf8());
+        match key.code {
+            KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.text.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.text.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+            }
+            KeyCode::Backspace => {
+                self.text.drain(previous..self.cursor);
+                self.cursor = previous;
+            }
+            KeyCode::Delete => {
+                self.text.drain(self.cursor..next);
+            }
+            KeyCode::Left => self.cursor = previous,
+            KeyCode::Right => self.cursor = next,
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.text.len(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Where the cursor is drawn, counting characters after the label.
+    fn column(&self) -> u16 {
+        (self.prompt.label().chars().count() + self.text[..self.cursor].chars().count()) as u16
+    }
+}
+
+/// Text shown over the rest of the screen until dismissed.
+struct Popup {
+    title: String,
+    text: String,
+    scroll: u16,
+}
+
+enum Status {
+    Info(String),
+    Error(String),
+}
+
+/// A full-screen view of the task list. Every change is made by building a
+/// command line and running it through [`crate::run_command`], exactly as if
+/// it had been typed into the REPL.
+struct App<'a> {
+    manager: &'a Mutex<TaskManager>,
+    store: &'a SharedStore,
+    autosaver: &'a Autosaver,
+    /// Set by the signal handler to stop the UI the way `q` does.
+    interrupted: &'a AtomicBool,
+    /// The filter bar's text, and the query last successfully parsed from it.
+    filter: String,
+    query: Query,
+    filter_error: Option<String>,
+    /// Ids in the order the list shows them, as of the last draw.
+    rows: Vec<u32>,
+    list: ListState,
+    /// The task under the cursor, followed as the list reorders.
+    selected: Option<u32>,
+    /// Tasks picked with space, which commands act on instead of the
+    /// selected one.
+    marked: BTreeSet<u32>,
+    input: Option<Input>,
+    popup: Option<Popup>,
+    status: Status,
+    last_refresh: Instant,
+    reminded_until: Option<NaiveDateTime>,
+    quit: bool,
+}
+
+/// Runs the terminal UI until the user quits or `interrupted` is set,
+/// leaving the terminal as it was found.
+pub fn run(
+    manager: &Mutex<TaskManager>,
+    store: &SharedStore,
+    autosaver: &Autosaver,
+    interrupted: &AtomicBool,
+) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let mut app = App::new(manager, store, autosaver, interrupted);
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl<'a> App<'a> {
+    fn new(
+        manager: &'a Mutex<TaskManager>,
+        store: &'a SharedStore,
+        autosaver: &'a Autosaver,
+        interrupted: &'a AtomicBool,
+    ) -> Self {
+        App {
+            manager,
+            store,
+            autosaver,
+            interrupted,
+            filter: String::new(),
+            query: Query::default(),
+            filter_error: None,
+            rows: Vec::new(),
+            list: ListState::default(),
+            selected: None,
+            marked: BTreeSet::new(),
+            input: None,
+            popup: None,
+            status: Status::Info("Press ? for the list of commands.".to_string()),
+            last_refresh: Instant::now(),
+            reminded_until: None,
+            quit: false,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while !self.quit && !self.interrupted.load(Ordering::SeqCst) {
+            if self.last_refresh.elapsed() >= REFRESH {
+                self.refresh();
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(TICK)?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                self.on_key(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges in what other processes saved, and shows any reminders that
+    /// came due.
+    fn refresh(&mut self) {
+        self.last_refresh = Instant::now();
+        let mut manager = self.manager.lock().unwrap();
+        let mut store = self.store.lock().unwrap();
+        match manager.refresh(store.as_mut()) {
+            Ok(true) => self.status = Status::Info("Merged changes from another session.".into()),
+            Ok(false) => {}
+            Err(e) => self.status = Status::Error(format!("Failed to check for changes: {}", e)),
+        }
+        let now = manager.clock.now();
+        if let Some(task) = manager.reminders(self.reminded_until, now).last() {
+            self.status = Status::Info(format!("Reminder: {}", task));
+        }
+        self.reminded_until = Some(now);
+    }
+
+    fn on_key(&mut self, key: KeyEvent) {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.quit = true;
+        } else if self.input.is_some() {
+            self.on_input_key(key);
+        } else if let Some(popup) = &mut self.popup {
+            match key.code {
+                KeyCode::Down | KeyCode::Char('j') => popup.scroll = popup.scroll.saturating_add(1),
+                KeyCode::Up | KeyCode::Char('k') => popup.scroll = popup.scroll.saturating_sub(1),
+                KeyCode::PageDown => popup.scroll = popup.scroll.saturating_add(PAGE as u16),
+                KeyCode::PageUp => popup.scroll = popup.scroll.saturating_sub(PAGE as u16),
+                _ => self.popup = None,
+            }
+        } else {
+            self.on_list_key(key);
+        }
+    }
+
+    fn on_list_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Esc => self.marked.clear(),
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::PageDown => self.move_by(PAGE as isize),
+            KeyCode::PageUp => self.move_by(-(PAGE as isize)),
+            KeyCode::Home | KeyCode::Char('g') => self.move_by(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => self.move_by(isize::MAX),
+            KeyCode::Char(' ') => {
+                if let Some(id) = self.selected
+                    && !self.marked.remove(&id)
+                {
+                    self.marked.insert(id);
+                }
+                self.move_by(1);
+            }
+            KeyCode::Char('/') => self.prompt(Prompt::Filter, self.filter.clone()),
+            KeyCode::Char(':') => self.prompt(Prompt::Command, String::new()),
+            KeyCode::Char('a') => self.prompt(Prompt::Add, String::new()),
+            KeyCode::Char('e') | KeyCode::Char('n') if self.targets().is_some() => {
+                let (prompt, text) = match key.code {
+                    KeyCode::Char('n') => (Prompt::Note, String::new()),
+                    // A single task starts from its description; several only
+                    // take the tokens typed.
+                    _ if self.marked.is_empty() => (Prompt::Edit, self.description()),
+                    _ => (Prompt::Edit, String::new()),
+                };
+                self.prompt(prompt, text);
+            }
+            KeyCode::Char('c') => self.on_targets("complete"),
+            KeyCode::Char('d') => self.on_targets("delete"),
+            KeyCode::Char('s') => {
+                let running = self
+                    .manager
+                    .lock()
+                    .unwrap()
+                    .running_timer()
+                    .map(|(id, _)| id);
+                if self.marked.is_empty() && running.is_some() && running == self.selected {
+                    self.run_line("stop");
+                } else {
+                    self.on_targets("start");
+                }
+            }
+            KeyCode::Enter => self.on_targets("history"),
+            KeyCode::Char('u') => self.run_line("undo"),
+            KeyCode::Char('r') => self.run_line("redo"),
+            KeyCode::Char('?') => {
+                self.popup = Some(Popup {
+                    title: "Help".to_string(),
+                    text: format!("{}\n\n{}", KEY_HELP, crate::HELP),
+                    scroll: 0,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn prompt(&mut self, prompt: Prompt, text: String) {
+        self.input = Some(Input::new(prompt, text));
+        self.status = Status::Info(String::new());
+    }
+
+    fn on_input_key(&mut self, key: KeyEvent) {
+        let Some(input) = &mut self.input else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => {
+                if input.prompt == Prompt::Filter {
+                    // Go back to the filter that was in effect.
+                    self.set_filter(self.filter.clone());
+                }
+                self.input = None;
+            }
+            KeyCode::Enter => {
+                let input = self.input.take().expect("checked above");
+                self.submit(input);
+            }
+            _ => {
+                if input.edit(key) && input.prompt == Prompt::Filter {
+                    let text = input.text.clone();
+                    self.set_filter(text);
+                }
+            }
+        }
+    }
+
+    /// Filters the list by `text` as it is typed, keeping the last query that
+    /// parsed while it does not.
+    fn set_filter(&mut self, text: String) {
+        let now = self.manager.lock().unwrap().clock.now();
+        match Query::parse(&text, now) {
+            Ok(query) => {
+                self.query = query;
+                self.filter_error = None;
+            }
+            Err(e) => self.filter_error = Some(e),
+        }
+    }
+
+    fn submit(&mut self, input: Input) {
+        let text = input.text.trim().to_string();
+        match input.prompt {
+            Prompt::Filter => {
+                if let Some(error) = &self.filter_error {
+                    self.status = Status::Error(format!("Invalid query: {}", error));
+                    self.input = Some(input);
+                } else {
+                    self.filter = text;
+                }
+            }
+            Prompt::Command
+                if text.eq_ignore_ascii_case("exit") || text.eq_ignore_ascii_case("quit") =>
+            {
+                self.quit = true;
+            }
+            Prompt::Command => {
+                let clock = self.manager.lock().unwrap().clock;
+                let command = Command::parse(&text, clock);
+                if let Err(CommandError::Syntax { column, .. }) = &command {
+                    // Put the cursor where the problem is so it can be fixed.
+                    let cursor = text
+                        .char_indices()
+                        .nth(column - 1)
+                        .map_or(text.len(), |(i, _)| i);
+                    self.input = Some(Input {
+                        text,
+                        cursor,
+                        ..input
+                    });
+                }
+                self.execute(command);
+            }
+            // Typed text is taken word for word, without quoting, so an
+            // apostrophe in a description needs no escaping.
+            Prompt::Add if !text.is_empty() => self.run_words(format!("add {}", text)),
+            Prompt::Edit | Prompt::Note if !text.is_empty() => {
+                let name = if input.prompt == Prompt::Edit {
+                    "edit"
+                } else {
+                    "note"
+                };
+                if let Some(targets) = self.targets() {
+                    self.run_words(format!("{} {} {}", name, targets, text));
+                }
+            }
+            Prompt::Add | Prompt::Edit | Prompt::Note => {}
+        }
+    }
+
+    /// The marked tasks as an id list, or else the selected task.
+    fn targets(&self) -> Option<String> {
+        if self.marked.is_empty() {
+            return self.selected.map(|id| id.to_string());
+        }
+        let ids: Vec<String> = self.marked.iter().map(u32::to_string).collect();
+        Some(ids.join(","))
+    }
+
+    fn description(&self) -> String {
+        let manager = self.manager.lock().unwrap();
+        manager
+            .tasks
+            .iter()
+            .find(|t| Some(t.id()) == self.selected)
+            .map(|t| t.details().description.clone())
+            .unwrap_or_default()
+    }
+
+    /// Runs `name` on the marked tasks, or else the selected one.
+    fn on_targets(&mut self, name: &str) {
+        match self.targets() {
+            Some(targets) => self.run_line(&format!("{} {}", name, targets)),
+            None => self.status = Status::Error("No task selected.".to_string()),
+        }
+    }
+
+    fn run_line(&mut self, line: &str) {
+        let clock = self.manager.lock().unwrap().clock;
+        self.execute(Command::parse(line, clock));
+    }
+
+    fn run_words(&mut self, line: String) {
+        let clock = self.manager.lock().unwrap().clock;
+        self.execute(Command::from_words(&args::split(&line), clock));
+    }
+
+    /// Runs `command` the way the REPL does. What a change reports goes to
+    /// the status line; anything a command only shows goes in a popup.
+    fn execute(&mut self, command: Result<Command, CommandError>) {
+        let result = command.and_then(|command| {
+            let mut manager = self.manager.lock().unwrap();
+            let mut store = self.store.lock().unwrap();
+            crate::run_command(&mut manager, store.as_mut(), command)
+        });
+        match result {
+            Ok((output, true)) => {
+                self.autosaver.notify();
+                self.marked.clear();
+                self.status = Status::Info(output.lines().next().unwrap_or_default().to_string());
+            }
+            Ok((output, false)) => {
+                self.popup = Some(Popup {
+                    title: "Output".to_string(),
+                    text: output.trim_matches('\n').to_string(),
+                    scroll: 0,
+                });
+            }
+            Err(e) => {
+                let message = e.to_string();
+                if message.contains('\n') {
+                    self.popup = Some(Popup {
+                        title: "Error".to_string(),
+                        text: message,
+                        scroll: 0,
+                    });
+                    self.status = Status::Error("Nothing was changed.".to_string());
+                } else {
+                    self.status = Status::Error(message);
+                }
+            }
+        }
+    }
+
+    /// Moves the cursor `delta` rows, stopping at either end.
+    fn move_by(&mut self, delta: isize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let index = self.list.selected().unwrap_or(0);
+        let index = index.saturating_add_signed(delta).min(self.rows.len() - 1);
+        self.list.select(Some(index));
+        self.selected = Some(self.rows[index]);
+    }
+
+    /// Keeps the cursor on the same task after the list changed, or on the
+    /// same row if that task is gone.
+    fn follow_selection(&mut self) {
+        if self.rows.is_empty() {
+            self.list.select(None);
+            self.selected = None;
+            return;
+        }
+        let index = self
+            .selected
+            .and_then(|id| self.rows.iter().position(|&row| row == id))
+            .unwrap_or_else(|| self.list.selected().unwrap_or(0).min(self.rows.len() - 1));
+        self.list.select(Some(index));
+        self.selected = Some(self.rows[index]);
+        self.marked.retain(|id| self.rows.contains(id));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [filter_area, main_area, status_area, keys_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list_area, detail_area] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(main_area);
+
+        let manager = self.manager.lock().unwrap();
+        let now = manager.clock.now();
+        let shown = self.query.select(&manager.tasks);
+        let tree = manager.tree(&shown);
+        self.rows = tree.iter().map(|(_, task)| task.id()).collect();
+        self.follow_selection();
+
+        let today = now.date();
+        let items: Vec<ListItem> = tree
+            .iter()
+            .map(|(depth, task)| {
+                let mark = if self.marked.contains(&task.id()) {
+                    "* "
+                } else {
+                    "  "
+                };
+                let line = format!("{}{}{}", mark, "  ".repeat(*depth), task);
+                ListItem::new(line).style(task_style(task, today))
+            })
+            .collect();
+        let mut title = format!(" Tasks ({} of {}) ", self.rows.len(), manager.tasks.len());
+        if let Some(timer) = manager.timer_indicator(now) {
+            title.push_str(&format!("{} ", timer.trim_end()));
+        }
+        if !self.marked.is_empty() {
+            title.push_str(&format!("{} marked ", self.marked.len()));
+        }
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, list_area, &mut self.list);
+
+        let task = self
+            .selected
+            .and_then(|id| manager.tasks.iter().find(|t| t.id() == id));
+        let details = match task {
+            Some(task) => details(&manager, task, now),
+            None if manager.tasks.is_empty() => Text::from("No tasks yet. Press a to add one."),
+            None => Text::from("No tasks match the filter."),
+        };
+        let details = Paragraph::new(details)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title(" Details "));
+        frame.render_widget(details, detail_area);
+        drop(manager);
+
+        let mut filter = vec![Span::raw(Prompt::Filter.label()).bold()];
+        match &self.input {
+            Some(input) if input.prompt == Prompt::Filter => {
+                filter.push(Span::raw(input.text.as_str()));
+                frame.set_cursor_position((filter_area.x + input.column(), filter_area.y));
+            }
+            _ if self.filter.is_empty() => {
+                filter.push(Span::raw("none, press / to filter").dark_gray());
+            }
+            _ => filter.push(Span::raw(self.filter.as_str())),
+        }
+        if let Some(error) = &self.filter_error {
+            filter.push(Span::raw(format!("  {}", error)).red());
+        }
+        frame.render_widget(Line::from(filter), filter_area);
+
+        let status = match &self.input {
+            Some(input) if input.prompt != Prompt::Filter => {
+                frame.set_cursor_position((status_area.x + input.column(), status_area.y));
+                Line::from(vec![
+                    Span::raw(input.prompt.label()).bold(),
+                    Span::raw(input.text.as_str()),
+                ])
+            }
+            _ => match &self.status {
+                Status::Info(message) => Line::from(message.as_str()),
+                Status::Error(message) => Line::from(format!("Error: {}", message)).red(),
+            },
+        };
+        frame.render_widget(status, status_area);
+        // An open prompt takes the status line, so errors about what was
+        // typed go below it.
+        let keys = match (&self.input, &self.status) {
+            (Some(_), Status::Error(message)) => Line::from(format!("Error: {}", message)).red(),
+            (Some(_), Status::Info(_)) => Line::from("Enter to run, Esc to cancel").dark_gray(),
+            (None, _) => Line::from(KEYS).dark_gray(),
+        };
+        frame.render_widget(keys, keys_area);
+
+        if let Some(popup) = &self.popup {
+            let area = popup_area(frame.area());
+            let title = format!(" {} (Esc to close) ", popup.title);
+            let text = Paragraph::new(popup.text.as_str())
+                .scroll((popup.scroll, 0))
+                .block(Block::bordered().title(title));
+            frame.render_widget(Clear, area);
+            frame.render_widget(text, area);
+        }
+    }
+}
+
+fn popup_area(area: Rect) -> Rect {
+    area.centered(Constraint::Percentage(90), Constraint::Percentage(80))
+}
+
+/// Finished tasks fade, blocked and overdue ones stand out.
+fn task_style(task: &AnyTask, today: chrono::NaiveDate) -> Style {
+    match task {
+        _ if task.is_finished() => Style::new().dark_gray(),
+        AnyTask::Blocked(_) => Style::new().magenta(),
+        _ if task.details().due.is_some_and(|due| due < today) => Style::new().red(),
+        AnyTask::InProgress(_) => Style::new().yellow(),
+        _ => Style::new(),
+    }
+}
+
+fn field(label: &str, value: String) -> Line<'static> {
+    Line::from(vec![
+        Span::raw(format!("{:<10} ", label)).bold(),
+        Span::raw(value),
+    ])
+}
+
+/// Everything about `task`: its fields, notes and latest audit events.
+fn details(manager: &TaskManager, task: &AnyTask, now: NaiveDateTime) -> Text<'static> {
+    let details = task.details();
+    let mut lines = vec![
+        Line::from(vec![
+            Span::raw(format!("Task {}", task.id())).bold(),
+            Span::raw(format!("  {}", task.state())).fg(Color::Cyan),
+        ]),
+        Line::from(details.description.clone()),
+        Line::default(),
+    ];
+    if let Some(priority) = details.priority {
+        lines.push(field("Priority", priority.name().to_string()));
+    }
+    if let Some(due) = details.due {
+        lines.push(field("Due", due.to_string()));
+    }
+    if !details.tags.is_empty() {
+        let tags: Vec<String> = details.tags.iter().map(|tag| format!("+{}", tag)).collect();
+        lines.push(field("Tags", tags.join(" ")));
+    }
+    if let Some(parent) = details.parent {
+        let description = manager
+            .tasks
+            .iter()
+            .find(|t| t.id() == parent)
+            .map(|t| t.details().description.as_str())
+            .unwrap_or_default();
+        lines.push(field("Parent", format!("{} {}", parent, description)));
+    }
+    if !details.blocked_by.is_empty() {
+        let blockers: Vec<String> = details.blocked_by.iter().map(u32::to_string).collect();
+        let waiting = manager.unfinished_blockers(task.id()).len();
+        lines.push(field(
+            "Needs",
+            format!("{} ({} unfinished)", blockers.join(", "), waiting),
+        ));
+    }
+    if let Some(recurrence) = &details.recurrence {
+        lines.push(field("Repeats", recurrence.to_string()));
+    }
+    if let Some(remind) = details.remind {
+        lines.push(field("Remind", remind.format("%Y-%m-%d %H:%M").to_string()));
+    }
+    if details.logged_seconds() > 0 {
+        lines.push(field("Logged", format_duration(details.logged_seconds())));
+    }
+    if let Some(since) = details.timer_since {
+        let running = (now - since).num_seconds().max(0) as u64;
+        lines.push(field(
+            "Timer",
+            format!("running for {}", format_duration(running)),
+        ));
+    }
+    let stamps = task.stamps();
+    for (label, stamp) in [
+        ("Created", stamps.created),
+        ("Updated", stamps.updated),
+        ("Completed", stamps.completed),
+    ] {
+        if let Some(at) = stamp {
+            lines.push(field(label, at.format("%Y-%m-%d %H:%M").to_string()));
+        }
+    }
+
+    if !details.notes.is_empty() {
+        lines.push(Line::default());
+        lines.push(Line::from("Notes").bold());
+        lines.extend(
+            details
+                .notes
+                .lines()
+                .map(|note| Line::from(format!("  {}", note))),
+        );
+    }
+    let events: Vec<String> = manager
+        .audit
+        .iter()
+        .filter(|event| event.task == task.id())
+        .map(|event| format!("  {}", event))
+        .collect();
+    if !events.is_empty() {
+        lines.push(Line::default());
+        lines.push(Line::from("History").bold());
+        let skip = events.len().saturating_sub(RECENT_EVENTS);
+        lines.extend(events.into_iter().skip(skip).map(Line::from));
+    }
+    Text::from(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ratatui::Terminal;
+    use ratatui::backend::TestBackend;
+
+    use super::*;
+    use crate::autosave::DEFAULT_INTERVAL;
+    use crate::store::MemoryStore;
+    use crate::tests::{descriptions, manager, state};
+
+    /// Runs `test` on an app over a fresh manager, with an autosaver that is
+    /// shut down afterwards.
+    fn with_app(test: impl FnOnce(&mut App)) {
+        let manager = Arc::new(Mutex::new(manager()));
+        let store: SharedStore = Arc::new(Mutex::new(Box::new(MemoryStore::default())));
+        let autosaver = Autosaver::spawn(manager.clone(), store.clone(), DEFAULT_INTERVAL);
+        let interrupted = AtomicBool::new(false);
+        test(&mut App::new(&manager, &store, &autosaver, &interrupted));
+        autosaver.shutdown().unwrap();
+    }
+
+    /// Draws the app off-screen, which is when it lays out its rows.
+    fn draw(app: &mut App) {
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+    }
+
+    fn add(app: &mut App, descriptions: &[&str]) {
+        for description in descriptions {
+            app.run_line(&format!("add {}", description));
+        }
+        draw(app);
+    }
+
+    #[test]
+    fn moving_stops_at_either_end() {
+        with_app(|app| {
+            add(app, &["buy milk", "walk dog", "pay rent"]);
+            assert_eq!(app.rows, [1, 2, 3]);
+            assert_eq!(app.selected, Some(1));
+
+            app.move_by(-1);
+            assert_eq!(app.selected, Some(1));
+            app.move_by(1);
+            assert_eq!(app.selected, Some(2));
+            app.move_by(PAGE as isize);
+            assert_eq!(app.selected, Some(3));
+            app.move_by(isize::MIN);
+            assert_eq!(app.selected, Some(1));
+            app.move_by(isize::MAX);
+            assert_eq!((app.selected, app.list.selected()), (Some(3), Some(2)));
+        });
+    }
+
+    #[test]
+    fn the_selection_follows_its_task_or_else_its_row() {
+        with_app(|app| {
+            add(app, &["buy milk", "walk dog", "pay rent"]);
+            app.move_by(1);
+            app.marked.extend([1, 3]);
+
+            app.run_line("delete 1");
+            draw(app);
+            assert_eq!(app.selected, Some(2));
+            assert_eq!(app.list.selected(), Some(0));
+
+            app.marked.insert(3);
+            app.run_line("delete 2");
+            draw(app);
+            assert_eq!(app.selected, Some(3));
+
+            app.marked.insert(3);
+            app.run_line("delete 3");
+            draw(app);
+            assert_eq!((app.selected, app.list.selected()), (None, None));
+            assert!(app.marked.is_empty());
+        });
+    }
+
+    #[test]
+    fn hidden_tasks_are_unmarked() {
+        with_app(|app| {
+            add(app, &["buy milk +shop", "walk dog", "pay rent +shop"]);
+            app.marked.extend([1, 2]);
+            app.set_filter("+shop".to_string());
+            draw(app);
+            assert_eq!(app.rows, [1, 3]);
+            assert_eq!(app.marked, BTreeSet::from([1]));
+        });
+    }
+
+    #[test]
+    fn commands_act_on_the_marked_tasks_or_else_the_selected_one() {
+        with_app(|app| {
+            assert_eq!(app.targets(), None);
+            app.on_targets("complete");
+            assert!(matches!(&app.status, Status::Error(e) if e == "No task selected."));
+
+            add(app, &["buy milk", "walk dog", "pay rent"]);
+            app.move_by(1);
+            assert_eq!(app.targets().as_deref(), Some("2"));
+            app.marked.extend([3, 1]);
+            assert_eq!(app.targets().as_deref(), Some("1,3"));
+
+            app.on_targets("complete");
+            assert!(app.marked.is_empty());
+            let manager = app.manager.lock().unwrap();
+            let states: Vec<&str> = (1..=3).map(|id| state(&manager, id)).collect();
+            assert_eq!(states, ["completed", "pending", "completed"]);
+        });
+    }
+
+    #[test]
+    fn a_syntax_error_puts_the_cursor_on_the_mistake() {
+        with_app(|app| {
+            let line = "add café run !urgent";
+            app.submit(Input::new(Prompt::Command, format!("  {}  ", line)));
+
+            let input = app.input.as_ref().expect("the line is kept for fixing");
+            assert_eq!(input.text, line);
+            assert_eq!(input.cursor, line.find('!').unwrap());
+            assert_eq!(input.column(), (":add café run ".chars().count()) as u16);
+            assert!(matches!(app.status, Status::Error(_)));
+            assert!(descriptions(&app.manager.lock().unwrap()).is_empty());
+        });
+    }
+}
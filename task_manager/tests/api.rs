@@ -0,0 +1,474 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, ChildStdout, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+
+const BIN: &str = env!("CARGO_BIN_EXE_task_manager");
+
+/// A `task_manager serve` process on a free port, with its own data
+/// directory. Dropping it stops the server and removes the directory.
+struct Server {
+    child: Child,
+    /// Kept open so the server can still write to it.
+    _stdout: BufReader<ChildStdout>,
+    address: String,
+    dir: PathBuf,
+}
+
+impl Server {
+    fn start(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("task_manager_api_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut child = Command::new(BIN)
+            .current_dir(&dir)
+            .args(["--autosave", "1", "serve", "--addr", "127.0.0.1:0"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        let address = line
+            .trim()
+            .strip_prefix("Listening on http://")
+            .unwrap_or_else(|| panic!("unexpected first line {:?}", line))
+            .to_string();
+        Server {
+            child,
+            _stdout: stdout,
+            address,
+            dir,
+        }
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: Option<&Value>,
+    ) -> Reply {
+        let body = body.map(Value::to_string).unwrap_or_default();
+        let mut stream = TcpStream::connect(&self.address).unwrap();
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            self.address,
+            body.len()
+        );
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes()).unwrap();
+        stream.write_all(body.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let mut lines = head.lines();
+        let status = lines
+            .next()
+            .unwrap()
+            .split(' ')
+            .nth(1)
+            .unwrap()
+            .parse()
+            .unwrap();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_lowercase(), value.trim().to_string()))
+            .collect();
+        Reply {
+            status,
+            headers,
+            body: body.to_string(),
+        }
+    }
+
+    fn get(&self, path: &str) -> Reply {
+        self.request("GET", path, &[], None)
+    }
+
+    fn post(&self, path: &str, body: &Value) -> Reply {
+        self.request("POST", path, &[], Some(body))
+    }
+
+    /// Adds a task and returns its id.
+    fn add(&self, body: Value) -> u64 {
+        let reply = self.post("/tasks", &body);
+        assert_eq!(reply.status, 201, "{}", reply.body);
+        task(&reply.json())["id"].as_u64().unwrap()
+    }
+
+    /// Runs a one-shot command against the same data, as another process.
+    fn run(&self, args: &[&str]) {
+        let status = Command::new(BIN)
+            .current_dir(&self.dir)
+            .args(args)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Reply {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field == &name.to_lowercase())
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn etag(&self) -> String {
+        self.header("ETag").expect("reply has an ETag").to_string()
+    }
+
+    fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap_or_else(|e| panic!("{}: {}", e, self.body))
+    }
+
+    fn error(&self) -> String {
+        self.json()["error"].as_str().unwrap().to_string()
+    }
+}
+
+/// The state a task JSON is in, e.g. `Pending`.
+fn state(task: &Value) -> &str {
+    task.as_object().unwrap().keys().next().unwrap()
+}
+
+/// The fields inside a task JSON, whatever its state.
+fn task(task: &Value) -> &Value {
+    task.as_object().unwrap().values().next().unwrap()
+}
+
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "timed out waiting for {}",
+            what
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn saved(dir: &Path) -> String {
+    fs::read_to_string(dir.join("tasks.json")).unwrap_or_default()
+}
+
+#[test]
+fn added_tasks_mirror_the_saved_json() {
+    let server = Server::start("add");
+    let reply = server.post(
+        "/tasks",
+        &json!({"description": "Buy milk", "priority": "High", "tags": ["home"], "due": "2030-01-31"}),
+    );
+    assert_eq!(reply.status, 201);
+    assert_eq!(reply.header("Location"), Some("/tasks/1"));
+    let added = reply.json();
+    assert_eq!(state(&added), "Pending");
+    assert_eq!(task(&added)["id"], 1);
+    assert_eq!(task(&added)["description"], "Buy milk");
+    assert_eq!(task(&added)["priority"], "High");
+    assert_eq!(task(&added)["tags"], json!(["home"]));
+    assert_eq!(task(&added)["due"], "2030-01-31");
+
+    let fetched = server.get("/tasks/1");
+    assert_eq!(fetched.status, 200);
+    assert_eq!(fetched.json(), added);
+    assert_eq!(fetched.etag(), reply.etag());
+
+    wait_for("the autosave", || saved(&server.dir).contains("Buy milk"));
+    let file: Value = serde_json::from_str(&saved(&server.dir)).unwrap();
+    assert_eq!(file["tasks"][0], added);
+}
+
+#[test]
+fn list_filters_with_a_query() {
+    let server = Server::start("list");
+    server.add(json!({"description": "Write report", "tags": ["work"]}));
+    server.add(json!({"description": "Buy milk", "tags": ["home"]}));
+    server.add(json!({"description": "Plan sprint", "tags": ["work"], "priority": "High"}));
+
+    let all = server.get("/tasks").json();
+    assert_eq!(all.as_array().unwrap().len(), 3);
+
+    let work = server.get("/tasks?q=%2Bwork+sort%3Apriority").json();
+    let ids: Vec<&Value> = work
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| &task(t)["id"])
+        .collect();
+    assert_eq!(ids, [3, 1]);
+
+    let invalid = server.get("/tasks?q=%28%28");
+    assert_eq!(invalid.status, 400);
+    assert!(
+        invalid.error().starts_with("Invalid query"),
+        "{}",
+        invalid.error()
+    );
+}
+
+#[test]
+fn completing_moves_a_task_to_completed_once() {
+    let server = Server::start("complete");
+    let id = server.add(json!({"description": "Ship it"}));
+
+    let reply = server.post(&format!("/tasks/{}/complete", id), &json!(null));
+    assert_eq!(reply.status, 200);
+    assert_eq!(state(&reply.json()), "Completed");
+    assert!(task(&reply.json())["completed"].is_string());
+
+    let again = server.post(&format!("/tasks/{}/complete", id), &json!(null));
+    assert_eq!(again.status, 409);
+    assert_eq!(
+        again.error(),
+        "Task 1 is completed and cannot be completed."
+    );
+}
+
+#[test]
+fn edits_need_a_current_etag_when_one_is_given() {
+    let server = Server::start("edit");
+    let id = server.add(json!({"description": "Draft", "tags": ["a"]}));
+    let path = format!("/tasks/{}", id);
+    let etag = server.get(&path).etag();
+
+    let edited = server.request(
+        "PATCH",
+        &path,
+        &[("If-Match", &etag)],
+        Some(&json!({"description": "Final", "tags": ["b"]})),
+    );
+    assert_eq!(edited.status, 200, "{}", edited.body);
+    assert_eq!(task(&edited.json())["description"], "Final");
+    assert_eq!(task(&edited.json())["tags"], json!(["a", "b"]));
+    assert_ne!(edited.etag(), etag);
+
+    // The first tag is now stale, so a second writer holding it is refused.
+    let stale = server.request(
+        "PATCH",
+        &path,
+        &[("If-Match", &etag)],
+        Some(&json!({"description": "Lost"})),
+    );
+    assert_eq!(stale.status, 412);
+    assert_eq!(stale.etag(), edited.etag());
+    assert_eq!(task(&server.get(&path).json())["description"], "Final");
+
+    let unconditional = server.request("PATCH", &path, &[], Some(&json!({"priority": "Low"})));
+    assert_eq!(unconditional.status, 200);
+    assert_eq!(task(&unconditional.json())["description"], "Final");
+    assert_eq!(task(&unconditional.json())["priority"], "Low");
+}
+
+#[test]
+fn null_clears_a_field_and_remove_tags_takes_tags_off() {
+    let server = Server::start("clear");
+    server.add(json!({"description": "Parent"}));
+    let id = server.add(json!({
+        "description": "Child",
+        "priority": "High",
+        "due": "2030-01-31",
+        "tags": ["a", "b"],
+        "parent": 1,
+        "recurrence": {"frequency": "Weekly", "interval": 1},
+        "remind": "2030-01-30T09:00:00"
+    }));
+    let path = format!("/tasks/{}", id);
+
+    let cleared = server.request(
+        "PATCH",
+        &path,
+        &[],
+        Some(&json!({
+            "priority": null,
+            "due": null,
+            "parent": null,
+            "recurrence": null,
+            "remind": null,
+            "remove_tags": ["a"]
+        })),
+    );
+    assert_eq!(cleared.status, 200, "{}", cleared.body);
+    let fields = task(&cleared.json()).clone();
+    for field in ["priority", "due", "parent", "recurrence", "remind"] {
+        assert!(fields[field].is_null(), "{} is {}", field, fields[field]);
+    }
+    assert_eq!(fields["tags"], json!(["b"]));
+    assert_eq!(fields["description"], "Child");
+
+    // Setting, clearing and leaving out fields in one request.
+    let mixed = server.request(
+        "PATCH",
+        &path,
+        &[],
+        Some(&json!({"priority": "Low", "tags": ["c"], "remove_tags": ["b"]})),
+    );
+    assert_eq!(mixed.status, 200, "{}", mixed.body);
+    assert_eq!(task(&mixed.json())["priority"], "Low");
+    assert_eq!(task(&mixed.json())["tags"], json!(["c"]));
+
+    // Both changes are undone together.
+    wait_for("the autosave", || saved(&server.dir).contains("\"Low\""));
+    server.run(&["undo"]);
+    let undone = server.get(&path);
+    assert_eq!(task(&undone.json())["priority"], json!(null));
+    assert_eq!(task(&undone.json())["tags"], json!(["b"]));
+}
+
+#[test]
+fn recurring_tasks_added_without_a_due_date_get_one() {
+    let server = Server::start("recurring");
+    let id = server.add(json!({
+        "description": "Water plants",
+        "recurrence": {"frequency": "Daily", "interval": 2}
+    }));
+    let added = server.get(&format!("/tasks/{}", id)).json();
+    let due = task(&added)["due"]
+        .as_str()
+        .expect("due date is pinned")
+        .to_string();
+
+    server.post(&format!("/tasks/{}/complete", id), &json!(null));
+    let next = server.get(&format!("/tasks/{}", id + 1));
+    assert_eq!(next.status, 200);
+    let next_due = task(&next.json())["due"].as_str().unwrap().to_string();
+    // ISO dates sort as text.
+    assert!(next_due > due, "{} should come after {}", next_due, due);
+}
+
+#[test]
+fn unchanged_tasks_are_not_sent_again() {
+    let server = Server::start("not_modified");
+    let id = server.add(json!({"description": "Cache me"}));
+    let path = format!("/tasks/{}", id);
+    let etag = server.get(&path).etag();
+
+    let cached = server.request("GET", &path, &[("If-None-Match", &etag)], None);
+    assert_eq!(cached.status, 304);
+    assert!(cached.body.is_empty());
+
+    server.post(&format!("{}/complete", path), &json!(null));
+    let changed = server.request("GET", &path, &[("If-None-Match", &etag)], None);
+    assert_eq!(changed.status, 200);
+}
+
+#[test]
+fn deleting_honours_if_match() {
+    let server = Server::start("delete");
+    let id = server.add(json!({"description": "Temporary"}));
+    let path = format!("/tasks/{}", id);
+
+    let stale = server.request("DELETE", &path, &[("If-Match", "\"0\"")], None);
+    assert_eq!(stale.status, 412);
+    assert_eq!(server.get(&path).status, 200);
+
+    let etag = server.get(&path).etag();
+    let deleted = server.request("DELETE", &path, &[("If-Match", &etag)], None);
+    assert_eq!(deleted.status, 204);
+    assert!(deleted.body.is_empty());
+
+    let gone = server.get(&path);
+    assert_eq!(gone.status, 404);
+    assert_eq!(gone.error(), "Task 1 not found.");
+    assert_eq!(server.request("DELETE", &path, &[], None).status, 404);
+}
+
+#[test]
+fn bad_requests_are_refused_with_a_reason() {
+    let server = Server::start("errors");
+    let reply = server.request("POST", "/tasks", &[], None);
+    assert_eq!(reply.status, 400);
+    assert!(reply.error().starts_with("Invalid task JSON"));
+
+    let reply = server.post("/tasks", &json!({"description": "  "}));
+    assert_eq!(reply.status, 400);
+    assert_eq!(reply.error(), "A task needs a description");
+
+    let reply = server.post("/tasks", &json!({"description": "x", "id": 7}));
+    assert_eq!(reply.status, 400);
+    assert!(
+        reply.error().contains("unknown field `id`"),
+        "{}",
+        reply.error()
+    );
+
+    let reply = server.post(
+        "/tasks",
+        &json!({"description": "x", "tags": ["two words"]}),
+    );
+    assert_eq!(reply.status, 400);
+
+    let reply = server.post("/tasks", &json!({"description": "x", "parent": 42}));
+    assert_eq!(reply.status, 404);
+
+    assert_eq!(server.get("/tasks/abc").status, 404);
+    assert_eq!(server.get("/nowhere").status, 404);
+    assert_eq!(server.request("PUT", "/tasks/1", &[], None).status, 405);
+    assert!(server.get("/tasks").json().as_array().unwrap().is_empty());
+}
+
+#[test]
+fn changes_from_other_processes_show_up() {
+    let server = Server::start("refresh");
+    let id = server.add(json!({"description": "Shared"}));
+    let path = format!("/tasks/{}", id);
+    wait_for("the autosave", || saved(&server.dir).contains("Shared"));
+    let etag = server.get(&path).etag();
+
+    server.run(&["complete", &id.to_string()]);
+    server.run(&["add", "From", "the", "command", "line"]);
+
+    let reply = server.get(&path);
+    assert_eq!(state(&reply.json()), "Completed");
+    let stale = server.request(
+        "PATCH",
+        &path,
+        &[("If-Match", &etag)],
+        Some(&json!({"description": "Mine"})),
+    );
+    assert_eq!(stale.status, 412);
+
+    let descriptions: Vec<String> = server
+        .get("/tasks")
+        .json()
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| task(t)["description"].as_str().unwrap().to_string())
+        .collect();
+    assert!(
+        descriptions.contains(&"From the command line".to_string()),
+        "{:?}",
+        descriptions
+    );
+}
@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_task_manager");
+
+/// A data directory of its own for one test, removed again when dropped.
+struct Dir(PathBuf);
+
+impl Dir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("task_manager_cli_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Dir(dir)
+    }
+
+    /// Runs one command against the JSON store in this directory.
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(BIN)
+            .current_dir(&self.0)
+            .args(["--now", "2026-10-14T09:00"])
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    fn files(&self) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(&self.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn a_corrupt_file_is_left_alone_until_recover_is_asked_for() {
+    let dir = Dir::new("recover");
+    assert!(dir.run(&["add", "buy milk"]).status.success());
+    assert!(dir.run(&["add", "walk dog"]).status.success());
+    let path = dir.0.join("tasks.json");
+    let text = fs::read_to_string(&path).unwrap();
+    let truncated = &text[..text.find("walk dog").unwrap()];
+    fs::write(&path, truncated).unwrap();
+
+    let output = dir.run(&["list"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("--recover"), "{}", stderr(&output));
+    assert_eq!(fs::read_to_string(&path).unwrap(), truncated);
+
+    let output = dir.run(&["--recover", "list"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Recovered 1 task(s)"));
+    assert!(stdout(&output).contains("buy milk"));
+    let backups: Vec<String> = dir
+        .files()
+        .into_iter()
+        .filter(|name| name.starts_with("tasks.json.corrupt-"))
+        .collect();
+    assert_eq!(backups.len(), 1);
+    assert_eq!(
+        fs::read_to_string(dir.0.join(&backups[0])).unwrap(),
+        truncated
+    );
+
+    let output = dir.run(&["add", "walk dog"]);
+    assert_eq!(stdout(&output).trim(), "Added task 2.");
+}
+
+#[test]
+fn usage_errors_exit_with_2() {
+    let dir = Dir::new("usage");
+    let output = dir.run(&["--store", "bogus", "list"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("Usage: task_manager"));
+
+    assert_eq!(dir.run(&["frobnicate"]).status.code(), Some(2));
+    let output = dir.run(&["depend", "1"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("Column 10: Missing <blocker>"));
+    assert!(
+        stderr(&output).contains("\n           ^"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn each_kind_of_failure_has_its_own_exit_code() {
+    let dir = Dir::new("codes");
+    assert!(dir.run(&["add", "buy milk"]).status.success());
+    assert_eq!(dir.run(&["complete", "9"]).status.code(), Some(3));
+    assert!(dir.run(&["cancel", "1"]).status.success());
+    assert_eq!(dir.run(&["complete", "1"]).status.code(), Some(4));
+    assert!(dir.run(&["undo"]).status.success());
+    assert!(dir.run(&["undo"]).status.success());
+    assert_eq!(dir.run(&["undo"]).status.code(), Some(5));
+}
+
+#[test]
+fn a_failed_batch_exits_with_its_first_failure_and_changes_nothing() {
+    let dir = Dir::new("batch");
+    assert!(dir.run(&["add", "buy milk"]).status.success());
+    let output = dir.run(&["complete", "1,9"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).contains("task 9: Task 9 not found."));
+    assert!(stdout(&dir.run(&["list"])).contains("[ ] 1"));
+}
+
+#[test]
+fn storage_failures_exit_with_1() {
+    let dir = Dir::new("io");
+    let output = dir.run(&["--path", ".", "list"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("Failed to load tasks"));
+}